@@ -1,11 +1,10 @@
-use std::fs;
 use std::error::Error;
 use loom_reader::parse::{
-    Exp, read_expressions
+    read_expressions
 };
 use loom_compiler::frontend::Expr;
 
-const test_code: &str = r#"
+const TEST_CODE: &str = r#"
     (set i 0)
     (set b 2)
     (if (= i 0)
@@ -21,12 +20,12 @@ const test_code: &str = r#"
 
 fn main() -> Result<(), Box<dyn Error>> {
     //let source = fs::read_to_string("test.loom")?;
-    let source = test_code.to_string();
+    let source = TEST_CODE.to_string();
     let expressions = read_expressions(source)?;
 
     for x in expressions {
         println!("{x}");
-        println!("--> {:?}", Expr::from_exp(&x));
+        println!("--> {:?}", Expr::from_exp(&x)?);
     }
 
     Ok(())
@@ -1,7 +1,8 @@
 use core::mem;
+use std::error::Error;
 use loom_compiler::jit;
 
-fn main() -> Result<(), String> {
+fn main() -> Result<(), Box<dyn Error>> {
     // Create the JIT instance, which manages all generated functions and data.
     let mut jit = jit::JIT::default();
     println!("the answer is: {}", run_foo(&mut jit)?);
@@ -13,11 +14,19 @@ fn main() -> Result<(), String> {
         "iterative_fib(10) = {}",
         run_iterative_fib_code(&mut jit, 10)?
     );
+    println!(
+        "keyword_args = {}",
+        run_keyword_args(&mut jit)?
+    );
     run_hello(&mut jit)?;
     println!(
         "array test = {}",
         run_array(&mut jit)?
     );
+    println!(
+        "lerp(2.0, 4.0, 0.25) = {}",
+        run_lerp(&mut jit)?
+    );
     /*println!(
         "print int test = {}",
         run_print_int(&mut jit)?
@@ -25,28 +34,38 @@ fn main() -> Result<(), String> {
     Ok(())
 }
 
-fn run_foo(jit: &mut jit::JIT) -> Result<isize, String> {
+fn run_foo(jit: &mut jit::JIT) -> Result<isize, Box<dyn Error>> {
     unsafe { run_code(jit, FOO_CODE, (42, 63)) }
 }
 
-fn run_recursive_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, String> {
+fn run_recursive_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, Box<dyn Error>> {
     unsafe { run_code(jit, RECURSIVE_FIB_CODE, input) }
 }
 
-fn run_iterative_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, String> {
+fn run_iterative_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, Box<dyn Error>> {
     unsafe { run_code(jit, ITERATIVE_FIB_CODE, input) }
 }
 
-fn run_hello(jit: &mut jit::JIT) -> Result<isize, String> {
+fn run_keyword_args(jit: &mut jit::JIT) -> Result<isize, Box<dyn Error>> {
+    jit.compile(SUBTRACT_CODE)?;
+    unsafe { run_code(jit, KEYWORD_ARGS_CODE, ()) }
+}
+
+fn run_hello(jit: &mut jit::JIT) -> Result<isize, Box<dyn Error>> {
     jit.create_data("hello_string", "hello world!\0".as_bytes().to_vec())?;
     unsafe { run_code(jit, HELLO_CODE, ()) }
 }
 
-fn run_array(jit: &mut jit::JIT) -> Result<isize, String> {
+fn run_array(jit: &mut jit::JIT) -> Result<isize, Box<dyn Error>> {
     unsafe { run_code(jit, ARRAY_CODE, ()) }
 }
 
-fn run_print_int(jit: &mut jit::JIT) -> Result<isize, String> {
+fn run_lerp(jit: &mut jit::JIT) -> Result<f64, Box<dyn Error>> {
+    unsafe { run_code(jit, LERP_CODE, ()) }
+}
+
+#[allow(dead_code)]
+fn run_print_int(jit: &mut jit::JIT) -> Result<isize, Box<dyn Error>> {
     unsafe { run_code(jit, PRINT_INT_CODE, ()) }
 }
 
@@ -58,13 +77,13 @@ fn run_print_int(jit: &mut jit::JIT) -> Result<isize, String> {
 ///
 /// This function is unsafe since it relies on the caller to provide it with the correct
 /// input and output types. Using incorrect types at this point may corrupt the program's state.
-unsafe fn run_code<I, O>(jit: &mut jit::JIT, code: &str, input: I) -> Result<O, String> {
+unsafe fn run_code<I, O>(jit: &mut jit::JIT, code: &str, input: I) -> Result<O, Box<dyn Error>> {
     // Pass the string to the JIT, and it returns a raw pointer to machine code.
     let code_ptr = jit.compile(code)?;
     // Cast the raw pointer to a typed function pointer. This is unsafe, because
     // this is the critical point where you have to trust that the generated code
     // is safe to be called.
-    let code_fn = mem::transmute::<_, fn(I) -> O>(code_ptr);
+    let code_fn = mem::transmute::<*const u8, fn(I) -> O>(code_ptr);
     // And now we can call it!
     Ok(code_fn(input))
 }
@@ -119,6 +138,23 @@ const ITERATIVE_FIB_CODE: &str = r#"
     )
 "#;
 
+/// Keyword arguments are bound to the callee's parameters by name, after the
+/// positional ones.
+const SUBTRACT_CODE: &str = r#"
+    (fn subtract [a b] []
+        (- a b)
+    )
+"#;
+
+const KEYWORD_ARGS_CODE: &str = r#"
+    (fn keyword_args [] []
+        (+
+            (subtract :b 1 :a 10)
+            (subtract 100 :b 50)
+        )
+    )
+"#;
+
 const ARRAY_CODE: &str = r#"
     (fn array_test [] []
         (set a (array 8))
@@ -131,6 +167,19 @@ const ARRAY_CODE: &str = r#"
     )
 "#;
 
+/// `lerp` is generic over numbers, so it's compiled again for the floats it's
+/// called with here, which are passed and returned in float registers.
+const LERP_CODE: &str = r#"
+    (fn lerp_example [] []
+        (lerp 2.0 4.0 0.25)
+    )
+
+    (fn lerp [a b t] []
+        (+ a (* (- b a) t))
+    )
+"#;
+
+#[allow(dead_code)]
 const PRINT_INT_CODE: &str = r#"
     (fn print_int [] []
         (set n 9997)
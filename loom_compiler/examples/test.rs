@@ -0,0 +1,19 @@
+use std::fs;
+use std::error::Error;
+use loom_reader::parse::read_expressions;
+use loom_compiler::jit;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string("test.loom")?;
+    let expressions = read_expressions(source)?;
+
+    let mut jit = jit::JIT::default();
+
+    // Every function of the file is compiled together, so they can call
+    // each other in any order
+    for (name, _) in jit.compile_module(&expressions)? {
+        println!("compiled {name}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Closure conversion for compiled functions.
+//!
+//! A function written inside another is compiled to a function of its own,
+//! which is called with the closure it's called through as an extra first
+//! parameter. A closure lives on the heap: the address of its code, followed
+//! by a pointer to a cell for every variable it captures. Captured variables
+//! always live in cells, so the function which declares them and every
+//! closure which captures them see each other's assignments, like in the
+//! interpreter.
+//!
+//! Closures take every argument, and return their result, as a pointer-sized
+//! integer, so that any function value can be called the same way. Functions
+//! defined at the top level become values through small adapters which are
+//! called like that too.
+//!
+//! Closures and cells are never freed for now.
+
+use std::collections::{HashMap, HashSet};
+use crate::frontend::Expr;
+
+/// Prepare the body of a function for translation, given its parameters:
+/// calls of variables become calls of function values, and every function
+/// written in the body learns which variables it captures.
+///
+/// Returns the variables which are captured, and so must live in cells.
+pub fn convert(params: &[String], body: &mut [Expr]) -> Result<HashSet<String>, String> {
+    let mut variables: HashSet<String> = params.iter().cloned().collect();
+    for x in body.iter_mut() {
+        declared(x, &mut variables);
+    }
+    for x in body.iter_mut() {
+        call_values(x, &variables)?;
+    }
+
+    // A variable is captured by a function if it's used there, and anywhere
+    // outside of it too, since every variable has a name of its own by now
+    let mut total = Occurrences::default();
+    params.iter().for_each(|p| total.add(p));
+    for x in body.iter_mut() {
+        total.count(x, &variables);
+    }
+    let mut captured = HashSet::new();
+    for x in body.iter_mut() {
+        captures(x, &variables, &total, &mut captured);
+    }
+    Ok(captured)
+}
+
+/// The variables a body refers to, besides those only referred to by the
+/// functions written in it, in the order they're first referred to.
+pub fn variables(body: &mut [Expr]) -> Vec<String> {
+    fn visit(x: &mut Expr, out: &mut Vec<String>) {
+        let names = match x {
+            Expr::Identifier(name) | Expr::Assign(name, _) => std::slice::from_ref(name),
+            Expr::AssignValues(names, _) => names.as_slice(),
+            _ => &[],
+        };
+        for name in names {
+            if !out.contains(name) {
+                out.push(name.clone());
+            }
+        }
+        if let Expr::Lambda(lambda) = x {
+            for capture in &lambda.captures {
+                if !out.contains(capture) {
+                    out.push(capture.clone());
+                }
+            }
+            return;
+        }
+        for child in x.children_mut() {
+            visit(child, out);
+        }
+    }
+    let mut out = Vec::new();
+    for x in body {
+        visit(x, &mut out);
+    }
+    out
+}
+
+/// Collect every variable declared in an expression.
+fn declared(x: &mut Expr, variables: &mut HashSet<String>) {
+    match x {
+        Expr::Assign(name, _) => {
+            variables.insert(name.clone());
+        }
+        Expr::AssignValues(names, _) => variables.extend(names.iter().cloned()),
+        Expr::Lambda(lambda) => variables.extend(lambda.params.iter().cloned()),
+        _ => {}
+    }
+    for child in x.children_mut() {
+        declared(child, variables);
+    }
+}
+
+/// Turn calls of variables into calls of the function values they hold.
+fn call_values(x: &mut Expr, variables: &HashSet<String>) -> Result<(), String> {
+    if let Expr::Call(name, args, kwargs) = x {
+        if variables.contains(name) {
+            if let Some((k, _)) = kwargs.first() {
+                return Err(format!("Can't pass keyword argument :{k} to '{name}', its parameters are unknown"));
+            }
+            let callee = Box::new(Expr::Identifier(name.clone()));
+            *x = Expr::CallValue(callee, std::mem::take(args));
+        }
+    }
+    for child in x.children_mut() {
+        call_values(child, variables)?;
+    }
+    Ok(())
+}
+
+/// How many times each variable is referred to, in the order they're first
+/// referred to
+#[derive(Default)]
+struct Occurrences {
+    order: Vec<String>,
+    counts: HashMap<String, usize>,
+}
+
+impl Occurrences {
+    fn add(&mut self, name: &str) {
+        let count = self.counts.entry(name.to_string()).or_insert(0);
+        if *count == 0 {
+            self.order.push(name.to_string());
+        }
+        *count += 1;
+    }
+
+    fn count(&mut self, x: &mut Expr, variables: &HashSet<String>) {
+        match x {
+            Expr::Identifier(name) | Expr::Assign(name, _) if variables.contains(name.as_str()) => self.add(name),
+            Expr::AssignValues(names, _) => names.iter().for_each(|n| self.add(n)),
+            Expr::Lambda(lambda) => lambda.params.iter().for_each(|p| self.add(p)),
+            _ => {}
+        }
+        for child in x.children_mut() {
+            self.count(child, variables);
+        }
+    }
+}
+
+/// Find what every function written in an expression captures.
+fn captures(x: &mut Expr, variables: &HashSet<String>, total: &Occurrences, captured: &mut HashSet<String>) {
+    if let Expr::Lambda(lambda) = x {
+        let mut inside = Occurrences::default();
+        lambda.params.iter().for_each(|p| inside.add(p));
+        for x in lambda.body.iter_mut() {
+            inside.count(x, variables);
+        }
+        lambda.captures = inside.order
+                                .into_iter()
+                                .filter(|name| inside.counts[name] < total.counts[name])
+                                .collect();
+        captured.extend(lambda.captures.iter().cloned());
+    }
+    for child in x.children_mut() {
+        captures(child, variables, total, captured);
+    }
+}
@@ -1,9 +1,23 @@
-use loom_reader::parse::Exp;
+use loom_reader::diagnostics::{Diagnostic, ErrorCode};
+use loom_reader::parse::{Exp, Span};
+use std::collections::HashMap;
+use crate::types::Type;
+
+/// The number types which values can be converted between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Number {
+    Int,
+    F32,
+    F64,
+}
 
 /// The AST node for expressions.
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Literal(String),
+    Literal(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
     Identifier(String),
     Assign(String, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
@@ -14,146 +28,581 @@ pub enum Expr {
     Ge(Box<Expr>, Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
+    /// Unary minus
+    Neg(Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
     Modulo(Box<Expr>, Box<Expr>),
     IfElse(Box<Expr>, Vec<Expr>, Vec<Expr>),
     WhileLoop(Box<Expr>, Vec<Expr>),
-    Call(String, Vec<Expr>),
+    /// A call with its positional arguments and its keyword arguments.
+    /// Keyword arguments are bound to the callee's parameters by name before
+    /// translation, see `Expr::bind_calls`.
+    Call(String, Vec<Expr>, Vec<(String, Expr)>),
     GlobalDataAddr(String),
     Sequence(Vec<Expr>),
     MakeArray(u32),
     GetArrayElem(Box<Expr>, Box<Expr>),
     SetArrayElem(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Convert a number to another number type
+    Convert(Number, Box<Expr>),
+    /// A form, with where it's written, so that later passes can point at it
+    Located(Span, Box<Expr>),
+    /// An expression with the type the checker found for it, for the ones
+    /// whose type can't be told from their operands, like calls
+    Typed(Type, Box<Expr>),
+    /// A function written inside another, whose value is a closure
+    Lambda(Box<Lambda>),
+    /// A call of a function value, like a closure held by a variable
+    CallValue(Box<Expr>, Vec<Expr>),
+    /// A call in tail position, whose result is the result of the function
+    /// it's made from, see `tail`
+    Tail(Box<Expr>),
+    /// Several values at once, which a function can return
+    Values(Vec<Expr>),
+    /// Assign each of the values an expression produces to a variable
+    AssignValues(Vec<String>, Box<Expr>),
+    /// A call or a value of a function which is generic over numbers, with
+    /// the types of its numbers there. The checker makes it a use of the
+    /// function's copy for those numbers, see `types`.
+    Instance(Vec<Type>, Box<Expr>),
+}
+
+/// A function written inside another, see `closures`
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub params: Vec<String>,
+    pub body: Vec<Expr>,
+    /// The variables of the enclosing functions which the body refers to,
+    /// filled in by closure conversion
+    pub captures: Vec<String>,
 }
 
 impl Expr {
-    pub fn from_exp(x: &Exp) -> Self {
+    /// Convert a parsed expression into an AST node. Errors are prefixed with
+    /// the line and column of the offending expression.
+    pub fn from_exp(x: &Exp) -> Result<Self, String> {
         match x {
-            Exp::Symbol { contents } => {
-                match contents.parse::<i32>() {
-                    Ok(n) => Expr::Literal(format!("{n}")),
-                    Err(_) => {
-                        if contents.starts_with("&") {
-                            Expr::GlobalDataAddr(contents.clone()
-                                                         .strip_prefix("&")
-                                                         .unwrap()
-                                                         .to_string())
-                        } else {
-                            Expr::Identifier(contents.clone())
-                        }
+            Exp::Nil(_) => Ok(Expr::Literal(0)),
+            Exp::Int(n, _) => Ok(Expr::Literal(*n)),
+            Exp::Float(n, _) => Ok(Expr::Float(*n)),
+            Exp::Bool(b, _) => Ok(Expr::Bool(*b)),
+            Exp::Str(s, _) => Ok(Expr::Str(s.clone())),
+            Exp::Symbol(name, _) => {
+                if let Some(name) = name.strip_prefix('&') {
+                    Ok(Expr::GlobalDataAddr(name.to_string()))
+                } else {
+                    Ok(Expr::Identifier(name.clone()))
+                }
+            }
+            Exp::Keyword(..) => {
+                Err(format!("{}: Unsupported literal in compiled code: {x}", x.span()))
+            }
+            Exp::List(..) => {
+                Err(format!("{}: Unexpected list in expression position: {x}", x.span()))
+            }
+            Exp::SExp { kind, args, kwargs, span } => {
+                let Some(name) = kind.as_symbol() else {
+                    // A call of whatever function the head evaluates to
+                    if let Some((k, _)) = kwargs.first() {
+                        return Err(format!("{span}: Can't pass keyword argument :{k} to a function value"));
                     }
+                    let callee = Expr::from_exp(kind)?;
+                    let args = args.iter()
+                                   .map(Expr::from_exp)
+                                   .collect::<Result<Vec<Expr>, String>>()?;
+                    return Ok(Expr::Located(*span, Box::new(Expr::CallValue(Box::new(callee), args))));
+                };
+                if !kwargs.is_empty() && is_special_form(&name) {
+                    return Err(format!("{span}: '{name}' does not accept keyword arguments"));
+                }
+                if name == "let" {
+                    return let_form(args, *span);
+                }
+                if name == "let-values" {
+                    return let_values_form(args, *span);
+                }
+                if name == "fn" || name == "def" {
+                    let form = function_form(&name, args).map_err(|e| format!("{span}: {e}"))?;
+                    return Ok(Expr::Located(*span, Box::new(form)));
+                }
+                if name == "quote" || name == "quasiquote" {
+                    // Quoted data is a literal, but only atoms are supported
+                    // for now
+                    return match args.as_slice() {
+                        [Exp::Nil(_)] => Ok(Expr::Literal(0)),
+                        [Exp::Int(n, _)] => Ok(Expr::Literal(*n)),
+                        [Exp::Float(n, _)] => Ok(Expr::Float(*n)),
+                        [Exp::Bool(b, _)] => Ok(Expr::Bool(*b)),
+                        [Exp::Str(s, _)] => Ok(Expr::Str(s.clone())),
+                        [x] => Err(format!("{}: Only atoms can be quoted in compiled code, found {x}", x.span())),
+                        _ => Err(format!("{span}: '{name}' expects 1 argument")),
+                    };
+                }
+                let args = args.iter()
+                               .map(Expr::from_exp)
+                               .collect::<Result<Vec<Expr>, String>>()?;
+                let mut keywords: Vec<(String, Expr)> = Vec::new();
+                for (k, v) in kwargs {
+                    keywords.push((k.clone(), Expr::from_exp(v)?));
+                }
+                let form = Self::from_form(name, args, keywords).map_err(|e| format!("{span}: {e}"))?;
+                Ok(Expr::Located(*span, Box::new(form)))
+            }
+        }
+    }
+
+    /// Convert a form, given its already converted arguments.
+    fn from_form(name: String, args: Vec<Expr>, kwargs: Vec<(String, Expr)>) -> Result<Self, String> {
+        match name.as_str() {
+            "+" => fold_arithmetic(&name, args, Expr::Add),
+            "-" if args.len() == 1 => Ok(Expr::Neg(Box::new(args.into_iter().next().unwrap()))),
+            "-" => fold_arithmetic(&name, args, Expr::Sub),
+            "*" => fold_arithmetic(&name, args, Expr::Mul),
+            "/" => fold_arithmetic(&name, args, Expr::Div),
+            "%" => fold_arithmetic(&name, args, Expr::Modulo),
+            "=" => comparison(&name, args, Expr::Eq),
+            "!=" => comparison(&name, args, Expr::Ne),
+            "<" => comparison(&name, args, Expr::Lt),
+            "<=" => comparison(&name, args, Expr::Le),
+            ">" => comparison(&name, args, Expr::Gt),
+            ">=" => comparison(&name, args, Expr::Ge),
+            "if" => {
+                let mut args = args.into_iter();
+                let (Some(condition), Some(truthy)) = (args.next(), args.next()) else {
+                    return Err("'if' expects a condition and a body".to_string());
+                };
+                let falsy = args.next();
+                if args.next().is_some() {
+                    return Err("'if' expects at most three arguments".to_string());
+                }
+                Ok(Expr::IfElse(
+                    Box::new(condition),
+                    into_body(truthy),
+                    falsy.map(into_body).unwrap_or_default(),
+                ))
+            }
+            "set" => {
+                let [target, value] = expect_args::<2>(&name, args)?;
+                let Expr::Identifier(varname) = target else {
+                    return Err(format!("'set' expects a variable name, found {target:?}"));
+                };
+                Ok(Expr::Assign(varname, Box::new(value)))
+            }
+            "while" => {
+                let mut args = args.into_iter();
+                let Some(condition) = args.next() else {
+                    return Err("'while' expects a condition".to_string());
+                };
+                Ok(Expr::WhileLoop(Box::new(condition), args.collect()))
+            }
+            "do" => Ok(Expr::Sequence(args)),
+            "values" => {
+                // A single value is just that value
+                let mut args = args;
+                match args.len() {
+                    0 => Err("'values' expects at least 1 argument".to_string()),
+                    1 => Ok(args.remove(0)),
+                    _ => Ok(Expr::Values(args)),
                 }
             }
-            Exp::Literal { contents } => {
-                Expr::Literal(contents.clone())
-            }
-            Exp::SExp { car, cdr } => {
-                let args: Vec<Box<Expr>> = cdr.iter().map(|i| {
-                    Box::new(Expr::from_exp(i))
-                }).collect();
-                let mut name = String::new();
-                match *car.clone() {
-                    Exp::Symbol { contents } => {
-                        name = contents;
+            "array" => {
+                let [length] = expect_args::<1>(&name, args)?;
+                match length {
+                    Expr::Literal(n) => {
+                        let length = u32::try_from(n).map_err(|_| {
+                            format!("Invalid array length: {n}")
+                        })?;
+                        Ok(Expr::MakeArray(length))
                     }
-                    _ =>  todo!()
+                    _ => Err("'array' expects a literal length".to_string())
                 }
-                match name.as_str() {
-                    "+" => Expr::Add(args[0].clone(), args[1].clone()),
-                    "-" => Expr::Sub(args[0].clone(), args[1].clone()),
-                    "*" => Expr::Mul(args[0].clone(), args[1].clone()),
-                    "/" => Expr::Div(args[0].clone(), args[1].clone()),
-                    "=" => Expr::Eq(args[0].clone(), args[1].clone()),
-                    "!=" => Expr::Ne(args[0].clone(), args[1].clone()),
-                    "<" => Expr::Lt(args[0].clone(), args[1].clone()),
-                    "<=" => Expr::Le(args[0].clone(), args[1].clone()),
-                    ">" => Expr::Gt(args[0].clone(), args[1].clone()),
-                    ">=" => Expr::Ge(args[0].clone(), args[1].clone()),
-                    "%" => Expr::Modulo(args[0].clone(), args[1].clone()),
-                    "if" => {
-                        let truthy = *args[1].clone();
-                        let truthy = match truthy {
-                            Expr::Sequence(contents) => contents,
-                            _ => vec![truthy]
-                        };
-                        if args.len() > 2 {
-                            let falsy = *args[2].clone();
-                            let falsy = match falsy {
-                                Expr::Sequence(contents) => contents,
-                                _ => vec![falsy]
-                            };
-                            Expr::IfElse(
-                                args[0].clone(),
-                                truthy,
-                                falsy
-                            )
-                        } else {
-                            Expr::IfElse(
-                                args[0].clone(),
-                                truthy,
-                                vec![]
-                            )
-                        }
-                    },
-                    "set" => {
-                        let varname: String = match *args[0].clone() {
-                            Expr::Identifier(n) => n,
-                            _ => todo!()
+            }
+            "int" | "f32" | "float" | "f64" => {
+                let [value] = expect_args::<1>(&name, args)?;
+                let number = match name.as_str() {
+                    "int" => Number::Int,
+                    "f32" => Number::F32,
+                    _ => Number::F64,
+                };
+                Ok(Expr::Convert(number, Box::new(value)))
+            }
+            "array_get" => {
+                let [addr, index] = expect_args::<2>(&name, args)?;
+                Ok(Expr::GetArrayElem(Box::new(addr), Box::new(index)))
+            }
+            "array_set" => {
+                let [addr, index, value] = expect_args::<3>(&name, args)?;
+                Ok(Expr::SetArrayElem(Box::new(addr), Box::new(index), Box::new(value)))
+            }
+            _ => Ok(Expr::Call(name, args, kwargs)),
+        }
+    }
+
+    /// Rewrite every call so that its keyword arguments are passed
+    /// positionally, given the parameter names of the known functions.
+    pub fn bind_calls(self, signatures: &HashMap<String, Vec<String>>) -> Result<Self, String> {
+        let bind = |x: Box<Expr>| -> Result<Box<Expr>, String> {
+            Ok(Box::new(x.bind_calls(signatures)?))
+        };
+        let bind_all = |xs: Vec<Expr>| -> Result<Vec<Expr>, String> {
+            xs.into_iter().map(|x| x.bind_calls(signatures)).collect()
+        };
+        Ok(match self {
+            Expr::Literal(_) | Expr::Float(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Identifier(_)
+            | Expr::GlobalDataAddr(_) | Expr::MakeArray(_) => self,
+            Expr::Assign(name, value) => Expr::Assign(name, bind(value)?),
+            Expr::Eq(lhs, rhs) => Expr::Eq(bind(lhs)?, bind(rhs)?),
+            Expr::Ne(lhs, rhs) => Expr::Ne(bind(lhs)?, bind(rhs)?),
+            Expr::Lt(lhs, rhs) => Expr::Lt(bind(lhs)?, bind(rhs)?),
+            Expr::Le(lhs, rhs) => Expr::Le(bind(lhs)?, bind(rhs)?),
+            Expr::Gt(lhs, rhs) => Expr::Gt(bind(lhs)?, bind(rhs)?),
+            Expr::Ge(lhs, rhs) => Expr::Ge(bind(lhs)?, bind(rhs)?),
+            Expr::Add(lhs, rhs) => Expr::Add(bind(lhs)?, bind(rhs)?),
+            Expr::Sub(lhs, rhs) => Expr::Sub(bind(lhs)?, bind(rhs)?),
+            Expr::Neg(x) => Expr::Neg(bind(x)?),
+            Expr::Mul(lhs, rhs) => Expr::Mul(bind(lhs)?, bind(rhs)?),
+            Expr::Div(lhs, rhs) => Expr::Div(bind(lhs)?, bind(rhs)?),
+            Expr::Modulo(lhs, rhs) => Expr::Modulo(bind(lhs)?, bind(rhs)?),
+            Expr::IfElse(condition, then_body, else_body) => {
+                Expr::IfElse(bind(condition)?, bind_all(then_body)?, bind_all(else_body)?)
+            }
+            Expr::WhileLoop(condition, body) => Expr::WhileLoop(bind(condition)?, bind_all(body)?),
+            Expr::Call(name, args, kwargs) => {
+                let params = signatures.get(&name).map(|p| p.as_slice());
+                let args = bind_arguments(&name, params, args, kwargs)?;
+                Expr::Call(name, bind_all(args)?, Vec::new())
+            }
+            Expr::Sequence(body) => Expr::Sequence(bind_all(body)?),
+            Expr::GetArrayElem(addr, index) => Expr::GetArrayElem(bind(addr)?, bind(index)?),
+            Expr::SetArrayElem(addr, index, value) => {
+                Expr::SetArrayElem(bind(addr)?, bind(index)?, bind(value)?)
+            }
+            Expr::Convert(number, x) => Expr::Convert(number, bind(x)?),
+            Expr::Located(span, x) => Expr::Located(span, bind(x)?),
+            Expr::Typed(ty, x) => Expr::Typed(ty, bind(x)?),
+            Expr::Lambda(lambda) => {
+                let Lambda { params, body, captures } = *lambda;
+                Expr::Lambda(Box::new(Lambda { params, body: bind_all(body)?, captures }))
+            }
+            Expr::CallValue(callee, args) => Expr::CallValue(bind(callee)?, bind_all(args)?),
+            Expr::Tail(call) => Expr::Tail(bind(call)?),
+            Expr::Values(values) => Expr::Values(bind_all(values)?),
+            Expr::AssignValues(names, value) => Expr::AssignValues(names, bind(value)?),
+            Expr::Instance(numbers, x) => Expr::Instance(numbers, bind(x)?),
+        })
+    }
+
+    /// The expressions directly inside this one, in evaluation order
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Float(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Identifier(_)
+            | Expr::GlobalDataAddr(_) | Expr::MakeArray(_) => Vec::new(),
+            Expr::Assign(_, x)
+            | Expr::Convert(_, x)
+            | Expr::Neg(x)
+            | Expr::Located(_, x)
+            | Expr::Typed(_, x)
+            | Expr::Tail(x)
+            | Expr::AssignValues(_, x)
+            | Expr::Instance(_, x) => vec![x],
+            Expr::Eq(lhs, rhs)
+            | Expr::Ne(lhs, rhs)
+            | Expr::Lt(lhs, rhs)
+            | Expr::Le(lhs, rhs)
+            | Expr::Gt(lhs, rhs)
+            | Expr::Ge(lhs, rhs)
+            | Expr::Add(lhs, rhs)
+            | Expr::Sub(lhs, rhs)
+            | Expr::Mul(lhs, rhs)
+            | Expr::Div(lhs, rhs)
+            | Expr::Modulo(lhs, rhs)
+            | Expr::GetArrayElem(lhs, rhs) => vec![lhs, rhs],
+            Expr::SetArrayElem(addr, index, value) => vec![addr, index, value],
+            Expr::IfElse(condition, then_body, else_body) => {
+                let mut children = vec![condition.as_mut()];
+                children.extend(then_body.iter_mut().chain(else_body));
+                children
+            }
+            Expr::WhileLoop(condition, body) => {
+                let mut children = vec![condition.as_mut()];
+                children.extend(body);
+                children
+            }
+            Expr::Call(_, args, kwargs) => args.iter_mut().chain(kwargs.iter_mut().map(|(_, v)| v)).collect(),
+            Expr::Sequence(body) | Expr::Values(body) => body.iter_mut().collect(),
+            Expr::Lambda(lambda) => lambda.body.iter_mut().collect(),
+            Expr::CallValue(callee, args) => {
+                let mut children = vec![callee.as_mut()];
+                children.extend(args);
+                children
+            }
+        }
+    }
+}
+
+/// Bind the arguments of a call to the callee's parameters.
+///
+/// Positional arguments fill the parameters from left to right, then every
+/// keyword argument fills the parameter with the same name. Every parameter
+/// must be bound exactly once. When the callee's parameter names aren't known
+/// (e.g. an imported function), only positional arguments are allowed.
+fn bind_arguments(
+    name: &str,
+    params: Option<&[String]>,
+    args: Vec<Expr>,
+    kwargs: Vec<(String, Expr)>,
+) -> Result<Vec<Expr>, String> {
+    let Some(params) = params else {
+        if let Some((k, _)) = kwargs.first() {
+            return Err(format!(
+                "Can't pass keyword argument :{k} to '{name}', its parameters are unknown"
+            ));
+        }
+        return Ok(args);
+    };
+    if args.len() > params.len() {
+        return Err(format!(
+            "'{name}' expects {} arguments, got {}", params.len(), args.len()
+        ));
+    }
+    let mut bound: Vec<Option<Expr>> = args.into_iter().map(Some).collect();
+    bound.resize(params.len(), None);
+    for (k, v) in kwargs {
+        let Some(index) = params.iter().position(|p| *p == k) else {
+            return Err(format!("'{name}' has no parameter named '{k}'"));
+        };
+        if bound[index].is_some() {
+            return Err(format!("Parameter '{k}' of '{name}' is bound more than once"));
+        }
+        bound[index] = Some(v);
+    }
+    bound.into_iter()
+         .zip(params)
+         .map(|(arg, param)| {
+             arg.ok_or_else(|| format!("Missing argument '{param}' in call to '{name}'"))
+         })
+         .collect()
+}
+
+/// The names which are translated directly instead of being called.
+pub(crate) fn is_special_form(name: &str) -> bool {
+    matches!(
+        name,
+        "+" | "-" | "*" | "/" | "%" | "=" | "!=" | "<" | "<=" | ">" | ">="
+            | "if" | "set" | "while" | "do" | "array" | "array_get" | "array_set"
+            | "quote" | "quasiquote" | "let" | "int" | "f32" | "float" | "f64" | "fn" | "def"
+            | "values" | "let-values"
+    )
+}
+
+/// A top-level function definition in the form the JIT compiles,
+/// `(fn name [params] [] body...)`. Functions can also be defined like in the
+/// interpreter, with `(def name (fn [params] body...))` or
+/// `(def (name params...) body...)`.
+///
+/// Returns `None` for anything which isn't a function definition, and an
+/// error for a named `fn` without its keyword parameter list, whose body
+/// would otherwise lose its first expression.
+pub fn function_definition(x: &Exp) -> Result<Option<Exp>, Diagnostic> {
+    let span = x.span();
+    let Some(args) = x.args() else { return Ok(None) };
+    let (name, params, body) = match (x.car_symbol().as_deref(), args.as_slice()) {
+        (Some("fn"), [Exp::Symbol(..), Exp::List(..), Exp::List(..), ..]) => return Ok(Some(x.clone())),
+        (Some("fn"), [Exp::Symbol(name, _), Exp::List(..), ..]) => {
+            let message = format!("Expected a keyword parameter list for '{name}'");
+            return Err(Diagnostic::error(message, span)
+                .with_code(ErrorCode::InvalidForm)
+                .with_help("write `[]` after the parameters if there are no keyword parameters".to_string()));
+        }
+        (Some("def"), [name @ Exp::Symbol(..), value]) if value.car_symbol().as_deref() == Some("fn") => {
+            match value.args().unwrap_or_default().as_slice() {
+                [Exp::List(params, _), body @ ..] => (name.clone(), params.clone(), body.to_vec()),
+                _ => return Ok(None),
+            }
+        }
+        (Some("def"), [signature @ Exp::SExp { kind, .. }, body @ ..]) if kind.as_symbol().is_some() => {
+            (kind.as_ref().clone(), signature.args().unwrap_or_default(), body.to_vec())
+        }
+        _ => return Ok(None),
+    };
+    let mut args = vec![name, Exp::List(params, span), Exp::List(Vec::new(), span)];
+    args.extend(body);
+    Ok(Some(Exp::SExp { kind: Box::new(Exp::Symbol("fn".to_string(), span)), args, kwargs: Vec::new(), span }))
+}
+
+/// A function inside another: `(fn [params] body...)` is a closure, and
+/// `(fn name [params] [] body...)`, `(def (name params...) body...)` and
+/// `(def name value)` assign a variable.
+///
+/// Their variables have unique names already, after name resolution.
+fn function_form(name: &str, args: &[Exp]) -> Result<Expr, String> {
+    let lambda = |params: &[Exp], body: &[Exp]| -> Result<Expr, String> {
+        let params = params.iter().map(|p| {
+            p.as_symbol().ok_or_else(|| format!("{}: Invalid parameter name: {p}", p.span()))
+        }).collect::<Result<Vec<String>, String>>()?;
+        let body = body.iter().map(Expr::from_exp).collect::<Result<Vec<Expr>, String>>()?;
+        Ok(Expr::Lambda(Box::new(Lambda { params, body, captures: Vec::new() })))
+    };
+    match (name, args) {
+        ("fn", [Exp::List(params, _), body @ ..]) => lambda(params, body),
+        ("fn", [Exp::Symbol(name, _), Exp::List(params, _), Exp::List(..), body @ ..]) => {
+            Ok(Expr::Assign(name.clone(), Box::new(lambda(params, body)?)))
+        }
+        ("def", [Exp::Symbol(name, _), value]) => Ok(Expr::Assign(name.clone(), Box::new(Expr::from_exp(value)?))),
+        ("def", [signature @ Exp::SExp { kind, .. }, body @ ..]) => {
+            let Some(name) = kind.as_symbol() else {
+                return Err(format!("Invalid function name: {kind}"));
+            };
+            Ok(Expr::Assign(name, Box::new(lambda(&signature.args().unwrap_or_default(), body)?)))
+        }
+        ("fn", _) => Err("'fn' expects a parameter list".to_string()),
+        _ => Err("'def' expects a name and a value".to_string()),
+    }
+}
+
+/// `(let [name value ...] body...)`
+///
+/// Compiled functions have a single scope, so each bound variable is renamed
+/// after where it's bound, and assigned before the body.
+fn let_form(args: &[Exp], span: Span) -> Result<Expr, String> {
+    let Some((Exp::List(bindings, _), body)) = args.split_first() else {
+        return Err(format!("{span}: 'let' expects a list of bindings"));
+    };
+    if bindings.len() % 2 != 0 {
+        return Err(format!("{span}: 'let' expects a value for every name"));
+    }
+    let mut body: Vec<Exp> = body.to_vec();
+    let mut stmts = Vec::new();
+    for pair in bindings.chunks(2) {
+        let Some(name) = pair[0].as_symbol() else {
+            return Err(format!("{}: Expected a variable name, found {}", pair[0].span(), pair[0]));
+        };
+        // The values are evaluated before any of the names are bound
+        let renamed = unique_variable(&name, pair[0].span());
+        stmts.push(Expr::Assign(renamed.clone(), Box::new(Expr::from_exp(&pair[1])?)));
+        body = body.iter().map(|x| rename(x, &name, &renamed)).collect();
+    }
+    for x in &body {
+        stmts.push(Expr::from_exp(x)?);
+    }
+    Ok(Expr::Sequence(stmts))
+}
+
+/// `(let-values [[names...] value ...] body...)`
+///
+/// Like `let`, except that each value is several values, one for each name.
+fn let_values_form(args: &[Exp], span: Span) -> Result<Expr, String> {
+    let Some((Exp::List(bindings, _), body)) = args.split_first() else {
+        return Err(format!("{span}: 'let-values' expects a list of bindings"));
+    };
+    if bindings.len() % 2 != 0 {
+        return Err(format!("{span}: 'let-values' expects a value for every list of names"));
+    }
+    let mut body: Vec<Exp> = body.to_vec();
+    let mut stmts = Vec::new();
+    for pair in bindings.chunks(2) {
+        let names = match &pair[0] {
+            Exp::List(names, _) if !names.is_empty() => names,
+            other => return Err(format!("{}: Expected a list of variable names, found {other}", other.span())),
+        };
+        let mut renamed = Vec::new();
+        for x in names {
+            let Some(name) = x.as_symbol() else {
+                return Err(format!("{}: Expected a variable name, found {x}", x.span()));
+            };
+            let variable = unique_variable(&name, x.span());
+            body = body.iter().map(|b| rename(b, &name, &variable)).collect();
+            renamed.push(variable);
+        }
+        let assign = Expr::AssignValues(renamed, Box::new(Expr::from_exp(&pair[1])?));
+        stmts.push(Expr::Located(pair[0].span(), Box::new(assign)));
+    }
+    for x in &body {
+        stmts.push(Expr::from_exp(x)?);
+    }
+    Ok(Expr::Sequence(stmts))
+}
+
+/// The unique name of a variable which is only in scope in part of a
+/// function, like one bound by `let`, after where it's bound.
+pub(crate) fn unique_variable(name: &str, span: Span) -> String {
+    format!("{name}@{span}")
+}
+
+/// Rename every reference to a variable, except where it's shadowed.
+fn rename(x: &Exp, from: &str, to: &str) -> Exp {
+    match x {
+        Exp::Symbol(name, span) if name == from => Exp::Symbol(to.to_string(), *span),
+        Exp::List(items, span) => Exp::List(items.iter().map(|i| rename(i, from, to)).collect(), *span),
+        Exp::SExp { kind, args, kwargs, span } => {
+            match kind.as_symbol().as_deref() {
+                Some("quote") => return x.clone(),
+                Some("let") | Some("let-values") => {
+                    // A nested `let` binding the same name shadows it in its
+                    // body, but not in its values, and so does `let-values`
+                    if let Some((Exp::List(bindings, list_span), body)) = args.split_first() {
+                        let binds = |b: &Exp| match b {
+                            Exp::List(names, _) => names.iter().any(|n| n.as_symbol().as_deref() == Some(from)),
+                            _ => b.as_symbol().as_deref() == Some(from),
                         };
-                        Expr::Assign(varname, args[1].clone())
-                    },
-                    "while" => {
-                        let mut body: Vec<Expr> = Vec::new();
-                        let mut i: usize = 0;
-                        for a in &args {
-                            if i > 0 {
-                                body.push(*a.clone());
-                            }
-                            i += 1;
-                        }
-                        Expr::WhileLoop(args[0].clone(), body)
-                    },
-                    "do" => {
-                        let mut body: Vec<Expr> = Vec::new();
-                        for a in &args {
-                            body.push(*a.clone());
-                        }
-                        Expr::Sequence(body)
-                    }
-                    "array" => {
-                        let Some(length) = args.get(0) else { todo!() };
-                        match *length.clone() {
-                            Expr::Literal(contents) => {
-                                let length: u32 = contents.parse().unwrap();
-                                Expr::MakeArray(length)
-                            }
-                            _ => todo!()
-                        }
-                    }
-                    "array_get" => {
-                        let Some(addr) = args.get(0) else { todo!() };
-                        let Some(index) = args.get(1) else { todo!() };
-                        Expr::GetArrayElem(addr.clone(), index.clone())
-                    }
-                    "array_set" => {
-                        let Some(addr) = args.get(0) else { todo!() };
-                        let Some(index) = args.get(1) else { todo!() };
-                        let Some(value) = args.get(2) else { todo!() };
-                        Expr::SetArrayElem(addr.clone(), index.clone(), value.clone())
-                    }
-                    _ => {
-                        let mut body: Vec<Expr> = Vec::new();
-                        for a in &args {
-                            body.push(*a.clone());
-                        }
-                        Expr::Call(name, body)
+                        let shadows = bindings.iter().step_by(2).any(binds);
+                        let bindings = bindings.iter().enumerate().map(|(i, b)| {
+                            if i % 2 == 1 { rename(b, from, to) } else { b.clone() }
+                        }).collect();
+                        let mut renamed = vec![Exp::List(bindings, *list_span)];
+                        renamed.extend(body.iter().map(|b| if shadows { b.clone() } else { rename(b, from, to) }));
+                        return Exp::SExp { kind: kind.clone(), args: renamed, kwargs: kwargs.clone(), span: *span };
                     }
                 }
+                _ => {}
             }
-            _ => {
-                Expr::Literal(format!("-1"))
+            Exp::SExp {
+                kind: Box::new(rename(kind, from, to)),
+                args: args.iter().map(|a| rename(a, from, to)).collect(),
+                kwargs: kwargs.iter().map(|(k, v)| (k.clone(), rename(v, from, to))).collect(),
+                span: *span,
             }
         }
+        _ => x.clone()
+    }
+}
+
+/// Fold the arguments of a variadic arithmetic operator from the left.
+fn fold_arithmetic(
+    name: &str,
+    args: Vec<Expr>,
+    op: fn(Box<Expr>, Box<Expr>) -> Expr,
+) -> Result<Expr, String> {
+    let mut args = args.into_iter();
+    let (Some(first), Some(second)) = (args.next(), args.next()) else {
+        return Err(format!("'{name}' expects at least 2 arguments"));
+    };
+    let mut result = op(Box::new(first), Box::new(second));
+    for arg in args {
+        result = op(Box::new(result), Box::new(arg));
+    }
+    Ok(result)
+}
+
+fn comparison(
+    name: &str,
+    args: Vec<Expr>,
+    op: fn(Box<Expr>, Box<Expr>) -> Expr,
+) -> Result<Expr, String> {
+    let [lhs, rhs] = expect_args::<2>(name, args)?;
+    Ok(op(Box::new(lhs), Box::new(rhs)))
+}
+
+fn expect_args<const N: usize>(name: &str, args: Vec<Expr>) -> Result<[Expr; N], String> {
+    let count = args.len();
+    args.try_into().map_err(|_| format!("'{name}' expects {N} arguments, got {count}"))
+}
+
+/// Unwrap a `do` block into the statements of a body.
+fn into_body(x: Expr) -> Vec<Expr> {
+    match x {
+        Expr::Sequence(contents) => contents,
+        _ => vec![x]
     }
 }
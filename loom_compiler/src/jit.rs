@@ -1,303 +1,1039 @@
+use crate::closures;
+use crate::tail;
 use crate::frontend::*;
+use crate::resolve::{ Resolution, resolve_function };
+use crate::types::{ FunctionTypes, Scheme, TypeEnv, binding_groups, specialization };
+use cranelift::codegen::isa::OwnedTargetIsa;
 use cranelift::prelude::*;
-use cranelift::codegen::ir::StackSlot;
-use cranelift::codegen::ir::immediates::Offset32;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{DataContext, Linkage, Module};
-use std::collections::HashMap;
+use cranelift_module::{DataContext, DataId, FuncId, Linkage, Module};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ffi::CString;
 use std::slice;
+use loom_reader::diagnostics::{ Diagnostic, ErrorCode };
 use loom_reader::parse::{
     Exp, read_expressions
 };
 
-/// The basic JIT class.
-pub struct JIT {
+/// The compiler, which translates functions into a Cranelift module. The
+/// module's backend decides what becomes of them: the JIT makes them into
+/// code the host process can call, see `JIT`, and the object backend into a
+/// native object file, see `object`.
+pub struct Compiler<M: Module> {
     /// The function builder context, which is reused across multiple
     /// FunctionBuilder instances.
-    builder_context: FunctionBuilderContext,
+    pub(crate) builder_context: FunctionBuilderContext,
 
     /// The main Cranelift context, which holds the state for codegen. Cranelift
     /// separates this from `Module` to allow for parallel compilation, with a
-    /// context per thread, though this isn't in the simple demo here.
-    ctx: codegen::Context,
+    /// context per thread, but functions are translated one at a time here.
+    pub(crate) ctx: codegen::Context,
 
     /// The data context, which is to data objects what `ctx` is to functions.
-    data_ctx: DataContext,
+    pub(crate) data_ctx: DataContext,
 
-    /// The module, with the jit backend, which manages the JIT'd
-    /// functions.
-    module: JITModule,
+    /// The module, which manages the compiled functions.
+    pub(crate) module: M,
+
+    /// How the functions and data objects of the module are exported.
+    linkage: Linkage,
+
+    /// The parameter names of every compiled function, used to bind keyword
+    /// arguments in calls.
+    signatures: HashMap<String, Vec<String>>,
+
+    /// The types of every compiled function, and of declared host functions.
+    pub(crate) types: TypeEnv,
+
+    /// The Cranelift IR of every compiled function, for debugging.
+    ir: HashMap<String, String>,
+
+    /// The names of the functions and data objects defined in the module.
+    /// Any other name must be provided from outside it, see `external`.
+    defined: HashSet<String>,
+
+    /// The symbol of every function the module defines, by name. A function
+    /// which is defined again gets a new symbol, like `f#2`, since the old
+    /// one can't be replaced, and the code compiled before keeps calling the
+    /// old definition.
+    symbols: HashMap<String, String>,
+
+    /// Whether a name the module doesn't define is provided from outside it:
+    /// by the host process for the JIT, or by whatever the object is linked
+    /// with for the object backend.
+    external: fn(&str) -> bool,
+
+    /// The adapters which let functions be used as closures, by the name of
+    /// the function they call, with the closure which holds each of them.
+    adapters: HashMap<String, (FuncId, DataId)>,
+
+    /// The buffer which tail calls bounce through, see `tail`.
+    tail_call: DataId,
+
+    /// The code which makes calls which bounced, by how many arguments they
+    /// have.
+    dispatchers: HashMap<usize, FuncId>,
+
+    /// The bodies of the functions which bounce, by name. The function of
+    /// the module with the name calls its body through a trampoline.
+    bodies: HashMap<String, FuncId>,
+
+    /// The functions which are generic over numbers, as they are before
+    /// their types are checked, to compile copies of them for other numbers
+    /// than ints, see `types::specialization`.
+    generic: HashMap<String, Function>,
 }
 
+/// The compiler with the JIT backend, which compiles functions into the
+/// memory of the host process.
+pub type JIT = Compiler<JITModule>;
+
 impl Default for JIT {
     fn default() -> Self {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
-            panic!("host machine is not supported: {}", msg);
-        });
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-
-        let module = JITModule::new(builder);
+        let mut builder = JITBuilder::with_isa(native_isa(false), cranelift_module::default_libcall_names());
+        builder.symbol(DIVISION_BY_ZERO, division_by_zero as *const u8);
+        Compiler::with_module(JITModule::new(builder), Linkage::Export, host_symbol_exists)
+    }
+}
+
+/// The instruction set of the host machine, for code which is or isn't
+/// position-independent.
+pub(crate) fn native_isa(pic: bool) -> OwnedTargetIsa {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", if pic { "true" } else { "false" }).unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .unwrap()
+}
+
+impl<M: Module> Compiler<M> {
+    /// Create a compiler for an empty module, whose functions and data
+    /// objects are exported with the given linkage, and which can refer to
+    /// the names `external` accepts.
+    pub(crate) fn with_module(mut module: M, linkage: Linkage, external: fn(&str) -> bool) -> Self {
+        let mut data_ctx = DataContext::new();
+        let word = module.target_config().pointer_bytes() as usize;
+        data_ctx.define_zeroinit((tail::MAX_ARGS + 2) * word);
+        let tail_call = module.declare_anonymous_data(true, false).unwrap();
+        module.define_data(tail_call, &data_ctx).unwrap();
+        data_ctx.clear();
         Self {
             builder_context: FunctionBuilderContext::new(),
             ctx: module.make_context(),
-            data_ctx: DataContext::new(),
+            data_ctx,
             module,
+            linkage,
+            signatures: HashMap::new(),
+            types: TypeEnv::default(),
+            ir: HashMap::new(),
+            defined: HashSet::new(),
+            symbols: HashMap::new(),
+            external,
+            adapters: HashMap::new(),
+            tail_call,
+            dispatchers: HashMap::new(),
+            bodies: HashMap::new(),
+            generic: HashMap::new(),
         }
     }
 }
 
 impl JIT {
-    /// Compile a string in the toy language into machine code.
-    pub fn compile(&mut self, input: &str) -> Result<*const u8, String> {
-        let source = read_expressions(input.to_string()).unwrap()
-                                                        .get(0)
-                                                        .unwrap()
-                                                        .clone();
+    /// Compile a string of loom source into machine code. Every
+    /// function in it is compiled together, see `compile_module`, and the
+    /// code of the first one is returned.
+    pub fn compile(&mut self, input: &str) -> Result<*const u8, Box<dyn Error>> {
+        let source = read_expressions(input.to_string())?;
+        match self.compile_module(&source)?.first() {
+            Some((_, code)) => Ok(*code),
+            None => Err("No function to compile".into()),
+        }
+    }
 
-        let Exp::SExp { car, cdr } = source else { todo!() };
+    /// Compile an already parsed function definition into machine code. See
+    /// `function_definition` for the ways a function can be defined.
+    pub fn compile_exp(&mut self, source: &Exp) -> Result<*const u8, Box<dyn Error>> {
+        if function_definition(source)?.is_none() {
+            return Err(format!("{}: Expected a function definition, found {source}", source.span()).into());
+        }
+        let compiled = self.compile_module(slice::from_ref(source))?;
+        Ok(compiled[0].1)
+    }
 
-        let Exp::Symbol { contents } = *car else {
-            todo!();
-        };
+    /// Compile the top-level forms of a module into machine code: function
+    /// definitions, and the type declarations they rely on, see
+    /// `define_module`.
+    ///
+    /// The functions are finalized together, and the code of every one of
+    /// them is returned, in the order they're written.
+    pub fn compile_module(&mut self, forms: &[Exp]) -> Result<Vec<(String, *const u8)>, Box<dyn Error>> {
+        let defined = self.define_module(forms)?;
 
-        if contents.as_str() != "fn" { todo!(); }
+        // Finalize the functions which we just defined, which resolves any
+        // outstanding relocations (patching in addresses, now that they're
+        // available).
+        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+
+        // We can now retrieve a pointer to the machine code.
+        Ok(defined.into_iter().map(|(name, id)| (name, self.module.get_finalized_function(id))).collect())
+    }
 
-        let name = match cdr.get(0) {
-            Some(Exp::Symbol { contents }) => contents.clone(),
-            _ => todo!()
+    /// Compile a function which calls a compiled function that returns
+    /// several values, and writes the values to memory so that the host can
+    /// read them. It takes the function's arguments, then the address of a
+    /// word for each value. Floats are written as their bits.
+    pub fn compile_values_writer(&mut self, name: &str) -> Result<*const u8, String> {
+        let int = self.module.target_config().pointer_type();
+        let Some((params, returns)) = self.types.function(name).and_then(|s| abi_signature(&s.ty, int)) else {
+            return Err(format!("'{name}' isn't a compiled function"));
         };
+        let mut sig = self.module.make_signature();
+        sig.params.extend(params.into_iter().map(AbiParam::new));
+        sig.returns.extend(returns.into_iter().map(AbiParam::new));
+        let callee = self.module
+                         .declare_function(&self.symbol(name), Linkage::Import, &sig)
+                         .map_err(|e| e.to_string())?;
+        sig.params.push(AbiParam::new(int));
+        sig.returns.clear();
+        let id = self.module.declare_anonymous_function(&sig).map_err(|e| e.to_string())?;
+
+        self.ctx.func.signature = sig;
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+        let entry_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.switch_to_block(entry_block);
+        builder.seal_block(entry_block);
+        let mut args = builder.block_params(entry_block).to_vec();
+        let words = args.pop().unwrap();
+        let local_callee = self.module.declare_func_in_func(callee, builder.func);
+        let call = builder.ins().call(local_callee, &args);
+        for (i, value) in builder.inst_results(call).to_vec().into_iter().enumerate() {
+            let ty = builder.func.dfg.value_type(value);
+            let word = if ty.is_float() {
+                let bits = builder.ins().bitcast(ty.as_int(), MemFlags::new(), value);
+                if ty.bits() < int.bits() { builder.ins().uextend(int, bits) } else { bits }
+            } else {
+                value
+            };
+            let offset = i as i32 * int.bytes() as i32;
+            builder.ins().store(MemFlags::new().with_aligned(), word, words, offset);
+        }
+        builder.ins().return_(&[]);
+        builder.finalize();
 
-        let params: Vec<String> = match cdr.get(1) {
-            Some(Exp::List { contents }) => {
-                contents.iter().map(|i| {
-                    match i {
-                        Exp::Symbol { contents } => contents.clone(),
-                        _ => todo!()
-                    }
-                }).collect()
+        let defined = self.module.define_function(id, &mut self.ctx).map_err(|e| e.to_string());
+        self.module.clear_context(&mut self.ctx);
+        defined?;
+        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+        Ok(self.module.get_finalized_function(id))
+    }
+
+    /// Create a data object, with the given contents.
+    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], String> {
+        let id = self.define_data(name, contents)?;
+        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+        let buffer = self.module.get_finalized_data(id);
+        // The module owns the data, and doesn't move or change it while it
+        // lives, which the slice borrows
+        Ok(unsafe { slice::from_raw_parts(buffer.0, buffer.1) })
+    }
+}
+
+impl<M: Module> Compiler<M> {
+    /// Translate the top-level forms of a module into Cranelift IR, and
+    /// define them in the module: function definitions, and the type
+    /// declarations they rely on, which are declared first wherever they're
+    /// written.
+    ///
+    /// Every function is declared before any of them is translated, so they
+    /// can call each other whatever order they're written in. If any of them
+    /// fails to compile, the compiler forgets about all of them. Returns the
+    /// id of every function, in the order they're written.
+    pub(crate) fn define_module(&mut self, forms: &[Exp]) -> Result<Vec<(String, FuncId)>, Box<dyn Error>> {
+        let registry = self.registry();
+        let result = self.define_forms(forms);
+        if result.is_err() {
+            // Forget everything about the module, so that it can be compiled
+            // again once it's fixed
+            self.restore(registry);
+        }
+        result
+    }
+
+    /// Resolve the names in the top-level forms of a module, and infer the
+    /// types of its functions, without compiling anything. Returns the type
+    /// of every function, in the order they're written. The compiler forgets
+    /// about the module afterwards, whether it checks or not.
+    pub fn check_module(&mut self, forms: &[Exp]) -> Result<Vec<(String, Scheme)>, Box<dyn Error>> {
+        let registry = self.registry();
+        let result = self.check_forms(forms);
+        self.restore(registry);
+        Ok(result?.0.into_iter().map(|(function, types)| (function.name, types.scheme)).collect())
+    }
+
+    /// What the compiler knows about the functions of the module, to restore
+    /// if compiling more of them fails.
+    fn registry(&self) -> Registry {
+        (
+            self.signatures.clone(),
+            self.defined.clone(),
+            self.symbols.clone(),
+            self.types.clone(),
+            self.adapters.clone(),
+            self.dispatchers.clone(),
+            self.bodies.clone(),
+            self.generic.clone(),
+        )
+    }
+
+    fn restore(&mut self, registry: Registry) {
+        (
+            self.signatures,
+            self.defined,
+            self.symbols,
+            self.types,
+            self.adapters,
+            self.dispatchers,
+            self.bodies,
+            self.generic,
+        ) = registry;
+        self.module.clear_context(&mut self.ctx);
+    }
+
+    /// Run every function of a module through the frontend, and infer
+    /// their types, registering them with the compiler. Returns the
+    /// functions in the order they're written, then the copies of functions
+    /// generic over numbers which they need.
+    #[allow(clippy::type_complexity)]
+    fn check_forms(&mut self, forms: &[Exp]) -> Result<(Vec<(Function, FunctionTypes)>, Vec<(Function, FunctionTypes)>), Box<dyn Error>> {
+        let mut definitions: Vec<(String, Vec<String>, Exp)> = Vec::new();
+        for x in forms {
+            if matches!(x.car_symbol().as_deref(), Some("deftype") | Some("extern")) {
+                self.declare(x)?;
+                continue;
             }
-            _ => todo!()
-        };
+            let span = x.span();
+            let Some(source) = function_definition(x)? else {
+                return Err(format!("{span}: Expected a function definition, found {x}").into());
+            };
+            let Some(name) = source.arg_symbol(0) else {
+                return Err(format!("{span}: Expected a function name").into());
+            };
+            let params: Vec<String> = match source.arg(1) {
+                Some(Exp::List(contents, _)) => {
+                    contents.iter().map(|i| {
+                        i.as_symbol().ok_or_else(|| format!("{}: Invalid parameter name: {i}", i.span()))
+                    }).collect::<Result<Vec<String>, String>>()?
+                }
+                _ => return Err(format!("{span}: Expected a parameter list for '{name}'").into())
+            };
+            if definitions.iter().any(|(defined, ..)| *defined == name) {
+                return Err(format!("{span}: '{name}' is defined more than once").into());
+            }
+            definitions.push((name, params, source));
+        }
+
+        // Register every function before translating any, so that they can
+        // call each other, with keyword arguments too. The copies of any
+        // function they define again are made again when they're needed.
+        for (name, params, _) in &definitions {
+            self.signatures.insert(name.clone(), params.clone());
+            self.defined.insert(name.clone());
+            self.defined.retain(|defined| !defined.starts_with(&format!("{name}[")));
+            self.generic.remove(name);
+        }
+
+        let mut functions = Vec::new();
+        for (name, params, source) in definitions {
+            // Every name must resolve before anything is translated
+            let resolution = self.resolve(&source);
+            if let Some(e) = resolution.errors().next() {
+                return Err(Box::new(e.clone()));
+            }
+
+            // Every variable has a name of its own after resolution, which
+            // the rest of compilation relies on
+            let mut body: Vec<Expr> = resolution.function
+                                                .args()
+                                                .unwrap_or_default()
+                                                .iter()
+                                                .skip(3)
+                                                .map(Expr::from_exp)
+                                                .collect::<Result<Vec<Expr>, String>>()?;
+            let boxed = closures::convert(&params, &mut body)?;
+            let body = body.into_iter()
+                           .map(|x| x.bind_calls(&self.signatures))
+                           .collect::<Result<Vec<Expr>, String>>()?;
+            functions.push(Function { name, params, body, boxed });
+        }
+
+        // Type errors are reported before any IR is generated. Functions
+        // which refer to each other are checked together, after the
+        // functions they refer to.
+        let indices: HashMap<String, usize> = functions.iter()
+                                                       .enumerate()
+                                                       .map(|(i, f)| (f.name.clone(), i))
+                                                       .collect();
+        let references: Vec<Vec<usize>> = functions.iter_mut().map(|f| {
+            let mut names = HashSet::new();
+            f.body.iter_mut().for_each(|x| references(x, &mut names));
+            names.iter().filter_map(|name| indices.get(name).copied()).collect()
+        }).collect();
+        let mut types = Vec::new();
+        let mut needed = Vec::new();
+        for group in binding_groups(&references) {
+            let sources: Vec<Function> = group.iter().map(|i| functions[*i].clone()).collect();
+            let mut checked: Vec<(&str, &[String], &mut [Expr])> = functions.iter_mut()
+                                                                          .enumerate()
+                                                                          .filter(|(i, _)| group.contains(i))
+                                                                          .map(|(_, f)| (f.name.as_str(), f.params.as_slice(), f.body.as_mut_slice()))
+                                                                          .collect();
+            let found = self.types.check_functions(&mut checked)?;
+            for ((i, found), source) in group.into_iter().zip(found).zip(sources) {
+                self.types.define(&functions[i].name, found.scheme.clone());
+                if !found.scheme.numeric.is_empty() {
+                    self.generic.insert(source.name.clone(), source);
+                }
+                needed.extend(found.specializations.iter().cloned());
+                types.push((i, found));
+            }
+        }
+        types.sort_by_key(|(i, _)| *i);
+        let functions = functions.into_iter().zip(types).map(|(function, (_, types))| (function, types)).collect();
+
+        // Then the copies of functions generic over numbers which are called
+        // with other numbers, and the copies those call in turn
+        let mut specializations = Vec::new();
+        while let Some((name, numbers)) = needed.pop() {
+            let copy = specialization(&name, &numbers);
+            if self.defined.contains(&copy) {
+                continue;
+            }
+            let (Some(mut function), Some(scheme)) = (self.generic.get(&name).cloned(), self.types.function(&name)) else {
+                return Err(format!("'{name}' isn't generic over numbers").into());
+            };
+            let found = self.types.check_specialization(&function.params, &mut function.body, scheme, &numbers)?;
+            function.name = copy.clone();
+            self.defined.insert(copy.clone());
+            self.types.define(&copy, found.scheme.clone());
+            needed.extend(found.specializations.iter().cloned());
+            specializations.push((function, found));
+        }
+        Ok((functions, specializations))
+    }
 
-        // For now, just hardcode the return var as "result"
-        let the_return: String = format!("result");
+    fn define_forms(&mut self, forms: &[Exp]) -> Result<Vec<(String, FuncId)>, Box<dyn Error>> {
+        let (written, specializations) = self.check_forms(forms)?;
+        let count = written.len();
+        let (mut functions, types): (Vec<Function>, Vec<FunctionTypes>) = written.into_iter().chain(specializations).unzip();
+
+        // Functions which are defined again get new symbols, and adapters
+        // which call the new definitions, before anything refers to them
+        for function in &functions {
+            let name = &function.name;
+            let symbol = match self.symbols.contains_key(name) {
+                true => {
+                    self.adapters.remove(name);
+                    (2..).map(|n| format!("{name}#{n}")).find(|s| self.module.get_name(s).is_none()).unwrap()
+                }
+                false => name.clone(),
+            };
+            self.symbols.insert(name.clone(), symbol);
+        }
 
-        let mut stmts: Vec<Expr> = cdr.clone().split_off(3)
-                                  .iter()
-                                  .map(|x| Expr::from_exp(x))
-                                  .collect();
+        // The bodies of the functions which bounce are declared before
+        // anything is translated, since any function can use any other as a
+        // value
+        let mut bodies = Vec::new();
+        for (function, types) in functions.iter_mut().zip(&types) {
+            let bounces = tail::mark(&function.name, &mut function.body, &|name| self.defined.contains(name));
+            let body = match bounces {
+                true => Some(self.module.declare_anonymous_function(&self.native_signature(types)?).map_err(|e| e.to_string())?),
+                false => None,
+            };
+            match body {
+                Some(body) => self.bodies.insert(function.name.clone(), body),
+                None => self.bodies.remove(&function.name),
+            };
+            bodies.push(body);
+        }
 
-        // Use the final statement in the body of a function as the result value
-        let last_stmt = stmts.pop();
-        if let Some(last) = last_stmt {
-            stmts.push(Expr::Assign(format!("result"), Box::new(last)));
+        // Then, translate the AST nodes into Cranelift IR, along with the
+        // closures and adapters each function needs
+        let mut translated = Vec::new();
+        for ((function, types), body) in functions.into_iter().zip(&types).zip(bodies) {
+            let Function { name, params, body: stmts, boxed } = function;
+            let code = Code::Function { params, body: stmts };
+            let (functions, records) = self.translate_all(&name, types, &boxed, body, code)?;
+            let ctx = std::mem::replace(&mut self.ctx, self.module.make_context());
+            let mut ir = ctx.func.display().to_string();
+            for (_, ctx) in &functions {
+                ir.push_str(&format!("\n{}", ctx.func.display()));
+            }
+            translated.push((name, ctx, functions, records, ir));
         }
 
-        println!("name: {name}, params: {params:?}, the_return: {the_return}");
-        //println!("    stmts: {stmts:#?}\n");
+        // And hand them to the module
+        let mut defined = Vec::new();
+        for (name, mut ctx, functions, records, ir) in translated {
+            let id = self.define(&self.symbol(&name), &mut ctx)?;
+            for (function, mut ctx) in functions {
+                self.module.define_function(function, &mut ctx).map_err(|e| e.to_string())?;
+            }
+            for (record, code) in records {
+                self.define_record(record, code)?;
+            }
+            defined.push((name, id, ir));
+        }
 
-        // Then, translate the AST nodes into Cranelift IR.
-        self.translate(params, the_return, stmts)?;
+        // The copies of functions aren't called by name from outside
+        let mut defined: Vec<(String, FuncId)> = defined.into_iter().map(|(name, id, ir)| {
+            self.ir.insert(name.clone(), ir);
+            (name, id)
+        }).collect();
+        defined.truncate(count);
+        Ok(defined)
+    }
 
-        // Next, declare the function to jit. Functions must be declared
-        // before they can be called, or defined.
-        //
-        // TODO: This may be an area where the API should be streamlined; should
-        // we have a version of `declare_function` that automatically declares
-        // the function?
+    /// Translate a function into `ctx`, then everything else it needs into
+    /// contexts of their own: its body if it bounces, which is given, and
+    /// its closures, adapters and dispatchers. Returns those, and the
+    /// closures of adapters to define.
+    #[allow(clippy::type_complexity)]
+    fn translate_all(
+        &mut self,
+        name: &str,
+        types: &FunctionTypes,
+        boxed: &HashSet<String>,
+        body: Option<FuncId>,
+        code: Code,
+    ) -> Result<(Vec<(FuncId, codegen::Context)>, Vec<(DataId, FuncId)>), String> {
+        let mut translation = Translation {
+            module: &mut self.module,
+            defined: &self.defined,
+            symbols: &self.symbols,
+            external: self.external,
+            types: &self.types,
+            this: (name, types),
+            this_body: body,
+            boxed,
+            adapters: &mut self.adapters,
+            dispatchers: &mut self.dispatchers,
+            bodies: &self.bodies,
+            tail_call: self.tail_call,
+            pending: Vec::new(),
+            records: Vec::new(),
+        };
+        translate(&mut self.ctx, &mut self.builder_context, &mut translation, code)?;
+        let mut functions = Vec::new();
+        if let Some(body) = body {
+            let ctx = std::mem::replace(&mut self.ctx, translation.module.make_context());
+            functions.push((body, ctx));
+            translate(&mut self.ctx, &mut self.builder_context, &mut translation, Code::Wrapper(body))?;
+        }
+        while let Some((id, code)) = translation.pending.pop() {
+            let mut ctx = translation.module.make_context();
+            translate(&mut ctx, &mut self.builder_context, &mut translation, code)?;
+            functions.push((id, ctx));
+        }
+        Ok((functions, translation.records))
+    }
+
+    /// The signature of a function with the platform's calling convention.
+    fn native_signature(&self, types: &FunctionTypes) -> Result<Signature, String> {
+        let int = self.module.target_config().pointer_type();
+        let Some((params, returns)) = abi_signature(&types.scheme.ty, int) else {
+            return Err(format!("{} isn't a function type", types.scheme));
+        };
+        let mut sig = self.module.make_signature();
+        sig.params.extend(params.into_iter().map(AbiParam::new));
+        sig.returns.extend(returns.into_iter().map(AbiParam::new));
+        Ok(sig)
+    }
+
+    /// Define the closure which holds an adapter, which doesn't capture
+    /// anything, so it isn't on the heap.
+    fn define_record(&mut self, record: DataId, code: FuncId) -> Result<(), String> {
+        // Not zero-initialized, since object files keep such data in a
+        // section which can't have the address of the code written to it
+        let mut data_ctx = DataContext::new();
+        let word = self.module.target_config().pointer_bytes() as usize;
+        data_ctx.define(vec![0; word].into_boxed_slice());
+        let code = self.module.declare_func_in_data(code, &mut data_ctx);
+        data_ctx.write_function_addr(0, code);
+        self.module.define_data(record, &data_ctx).map_err(|e| e.to_string())
+    }
+
+    /// Declare and define the function in `ctx` under the given name.
+    fn define(&mut self, name: &str, ctx: &mut codegen::Context) -> Result<FuncId, String> {
+        // Functions must be declared before they can be called, or defined.
         let id = self
             .module
-            .declare_function(&name, Linkage::Export, &self.ctx.func.signature)
+            .declare_function(name, self.linkage, &ctx.func.signature)
             .map_err(|e| e.to_string())?;
 
-        // Define the function to jit. This finishes compilation, although
-        // there may be outstanding relocations to perform. Currently, jit
-        // cannot finish relocations until all functions to be called are
-        // defined. For this toy demo for now, we'll just finalize the
-        // function below.
+        // Define the function in the module. This finishes compilation,
+        // although its calls of functions which aren't defined yet are
+        // still to be relocated: the JIT finalizes every function of a
+        // module together, see `compile_module`, and the object backend
+        // leaves them to the linker.
         self.module
-            .define_function(id, &mut self.ctx)
+            .define_function(id, ctx)
             .map_err(|e| e.to_string())?;
+        Ok(id)
+    }
 
-        // Now that compilation is finished, we can clear out the context state.
-        self.module.clear_context(&mut self.ctx);
+    /// The symbol of a function, which is its name unless it's been defined
+    /// more than once.
+    pub fn symbol(&self, name: &str) -> String {
+        self.symbols.get(name).map_or(name, |symbol| symbol.as_str()).to_string()
+    }
 
-        // Finalize the functions which we just defined, which resolves any
-        // outstanding relocations (patching in addresses, now that they're
-        // available).
-        self.module.finalize_definitions().unwrap();
+    /// Whether a function or data object can be referred to, because the
+    /// module defines it or it's provided from outside the module.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defined.contains(name) || (self.external)(name)
+    }
 
-        // We can now retrieve a pointer to the machine code.
-        let code = self.module.get_finalized_function(id);
+    /// Declare a user-defined type, `(deftype name)`, or the type of a host
+    /// function, `(extern name [params] result)`, returning the name.
+    pub fn declare(&mut self, x: &Exp) -> Result<String, Box<dyn Error>> {
+        if x.car_symbol().as_deref() == Some("extern") {
+            if let Some(name) = x.arg_symbol(0).filter(|name| !self.is_defined(name)) {
+                let message = format!("The host process has no function named '{name}'");
+                return Err(Box::new(Diagnostic::error(message, x.span()).with_code(ErrorCode::UnboundName)));
+            }
+        }
+        Ok(self.types.declare(x)?)
+    }
 
-        Ok(code)
+    /// The type of a compiled or declared function
+    pub fn type_of(&self, name: &str) -> Option<&Scheme> {
+        self.types.function(name)
     }
 
-    /// Create a zero-initialized data section.
-    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], String> {
-        // The steps here are analogous to `compile`, except that data is much
+    /// Resolve the names in a function definition against the module.
+    pub fn resolve(&self, source: &Exp) -> Resolution {
+        resolve_function(source, &|name| self.is_defined(name))
+    }
+
+    /// The Cranelift IR of a compiled function, as it was before optimization.
+    pub fn ir(&self, name: &str) -> Option<&str> {
+        self.ir.get(name).map(|s| s.as_str())
+    }
+
+    /// Declare and define a data object, with the given contents.
+    pub(crate) fn define_data(&mut self, name: &str, contents: Vec<u8>) -> Result<DataId, String> {
+        // The steps here are analogous to `define`, except that data is much
         // simpler than functions.
         self.data_ctx.define(contents.into_boxed_slice());
         let id = self
             .module
-            .declare_data(name, Linkage::Export, true, false)
+            .declare_data(name, self.linkage, true, false)
             .map_err(|e| e.to_string())?;
+        self.defined.insert(name.to_string());
 
-        self.module
-            .define_data(id, &self.data_ctx)
-            .map_err(|e| e.to_string())?;
+        let defined = self.module.define_data(id, &self.data_ctx).map_err(|e| e.to_string());
         self.data_ctx.clear();
-        self.module.finalize_definitions().unwrap();
-        let buffer = self.module.get_finalized_data(id);
-        // TODO: Can we move the unsafe into cranelift?
-        Ok(unsafe { slice::from_raw_parts(buffer.0, buffer.1) })
+        defined?;
+        Ok(id)
     }
+}
 
-    // Translate from toy-language AST nodes into Cranelift IR.
-    fn translate(
-        &mut self,
-        params: Vec<String>,
-        the_return: String,
-        stmts: Vec<Expr>,
-    ) -> Result<(), String> {
-        // Our toy language currently only supports I64 values, though Cranelift
-        // supports other types.
-        let int = self.module.target_config().pointer_type();
-
-        for _p in &params {
-            self.ctx.func.signature.params.push(AbiParam::new(int));
-        }
+/// What the compiler knows about the functions of its module: their
+/// parameters, their names, their types, adapters, dispatchers, bodies and
+/// the sources of the generic ones
+type Registry = (
+    HashMap<String, Vec<String>>,
+    HashSet<String>,
+    HashMap<String, String>,
+    TypeEnv,
+    HashMap<String, (FuncId, DataId)>,
+    HashMap<usize, FuncId>,
+    HashMap<String, FuncId>,
+    HashMap<String, Function>,
+);
+
+/// A function of a module, once it's been through the frontend
+#[derive(Clone)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Expr>,
+    /// The variables which closures capture
+    boxed: HashSet<String>,
+}
 
-        // Our toy language currently only supports one return value, though
-        // Cranelift is designed to support more.
-        self.ctx.func.signature.returns.push(AbiParam::new(int));
+/// A function to translate
+enum Code {
+    /// The function being compiled, whose last expression is its result
+    Function { params: Vec<String>, body: Vec<Expr> },
+    /// The function of the module with the name of a function which bounces,
+    /// which calls its body through a trampoline
+    Wrapper(FuncId),
+    /// The code of a closure
+    Closure(Lambda),
+    /// The code which calls a function when it's used as a closure
+    Adapter(String),
+    /// The code which makes a call which bounced, with a number of arguments
+    Dispatcher(usize),
+}
 
-        // Create the builder to build a function.
-        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+/// What translating any of the code of a function needs from the JIT
+struct Translation<'a> {
+    module: &'a mut dyn Module,
+    defined: &'a HashSet<String>,
+    symbols: &'a HashMap<String, String>,
+    external: fn(&str) -> bool,
+    types: &'a TypeEnv,
+    /// The function being compiled, and what the checker found out about it
+    this: (&'a str, &'a FunctionTypes),
+    /// The body of the function being compiled, if it bounces
+    this_body: Option<FuncId>,
+    /// The variables which closures capture
+    boxed: &'a HashSet<String>,
+    adapters: &'a mut HashMap<String, (FuncId, DataId)>,
+    dispatchers: &'a mut HashMap<usize, FuncId>,
+    bodies: &'a HashMap<String, FuncId>,
+    tail_call: DataId,
+    /// Closures, adapters and dispatchers which are declared, but still to be
+    /// translated
+    pending: Vec<(FuncId, Code)>,
+    /// The closures of adapters, which are defined along with the function
+    records: Vec<(DataId, FuncId)>,
+}
 
-        // Create the entry block, to start emitting code in.
-        let entry_block = builder.create_block();
+impl Translation<'_> {
+    /// Whether a function or data object can be referred to, see
+    /// `Compiler::is_defined`.
+    fn is_defined(&self, name: &str) -> bool {
+        self.defined.contains(name) || (self.external)(name)
+    }
+}
 
-        // Since this is the entry block, add block parameters corresponding to
-        // the function's parameters.
-        //
-        // TODO: Streamline the API here.
-        builder.append_block_params_for_function_params(entry_block);
+// Translate some code of a function, from AST nodes into Cranelift IR.
+fn translate(
+    ctx: &mut codegen::Context,
+    builder_context: &mut FunctionBuilderContext,
+    translation: &mut Translation,
+    code: Code,
+) -> Result<(), String> {
+    // Floats are passed as floats, and every other value as a pointer-sized
+    // integer, except that closures take and return nothing but integers.
+    let int = translation.module.target_config().pointer_type();
+    let (name, types) = translation.this;
+    let (param_types, return_types) = match &code {
+        Code::Function { .. } | Code::Wrapper(_) => match abi_signature(&types.scheme.ty, int) {
+            Some(signature) => signature,
+            None => return Err(format!("'{name}' has type {}, which isn't a function type", types.scheme)),
+        },
+        Code::Closure(lambda) => (vec![int; lambda.params.len() + 1], vec![int]),
+        Code::Adapter(function) => {
+            let (params, returns) = adapted_signature(translation, function, int)?;
+            if returns.len() > 1 {
+                return Err(format!("'{function}' returns several values, so it can't be used as a value"));
+            }
+            (vec![int; params.len() + 1], vec![int])
+        }
+        Code::Dispatcher(_) => (Vec::new(), vec![int]),
+    };
 
-        // Tell the builder to emit code in this block.
-        builder.switch_to_block(entry_block);
+    for ty in &param_types {
+        ctx.func.signature.params.push(AbiParam::new(*ty));
+    }
 
-        // And, tell the builder that this block will have no further
-        // predecessors. Since it's the entry block, it won't have any
-        // predecessors.
-        builder.seal_block(entry_block);
+    // A function returns each of its values, if it has several
+    for ty in &return_types {
+        ctx.func.signature.returns.push(AbiParam::new(*ty));
+    }
 
-        // The toy language allows variables to be declared implicitly.
-        // Walk the AST and declare all implicitly-declared variables.
-        let variables =
-            declare_variables(int, &mut builder, &params, &the_return, &stmts, entry_block);
+    // Create the builder to build a function.
+    let mut builder = FunctionBuilder::new(&mut ctx.func, builder_context);
+
+    // Create the entry block, to start emitting code in.
+    let entry_block = builder.create_block();
+
+    // Since this is the entry block, add block parameters corresponding to
+    // the function's parameters.
+    builder.append_block_params_for_function_params(entry_block);
+
+    // Tell the builder to emit code in this block.
+    builder.switch_to_block(entry_block);
+
+    // And, tell the builder that this block will have no further
+    // predecessors. Since it's the entry block, it won't have any
+    // predecessors.
+    builder.seal_block(entry_block);
+    let entry_values = builder.block_params(entry_block).to_vec();
+
+    let mut trans = FunctionTranslator {
+        int,
+        builder,
+        variables: HashMap::new(),
+        jit: translation,
+        return_types: return_types.clone(),
+        header: None,
+        malloc: None,
+        tail_buffer: None,
+        error: None,
+    };
+    let return_values = match code {
+        Code::Function { params, mut body } => {
+            // The function calls itself in tail position by jumping back to
+            // the header, which declares the parameters and locals afresh
+            let header = trans.builder.create_block();
+            for ty in &param_types {
+                trans.builder.append_block_param(header, *ty);
+            }
+            trans.builder.ins().jump(header, &entry_values);
+            trans.builder.switch_to_block(header);
+            trans.header = Some(header);
+            let values = trans.builder.block_params(header).to_vec();
+            for (param, value) in params.iter().zip(values) {
+                trans.declare_initialized(param, value);
+            }
+            trans.declare_locals(&mut body);
 
-        // Now translate the statements of the function body.
-        let mut trans = FunctionTranslator {
-            int,
-            builder,
-            variables,
-            module: &mut self.module,
-        };
-        for expr in stmts {
-            trans.translate_expr(expr);
+            // Now translate the statements of the function body, the last
+            // of which is the result.
+            let results = trans.translate_body_values(body, &return_types);
+            trans.builder.seal_block(header);
+            results
         }
+        Code::Wrapper(body) => {
+            let result = trans.call_function(body, entry_values)[0];
+            vec![trans.trampoline(result)]
+        }
+        Code::Closure(Lambda { params, mut body, captures }) => {
+            // The closure holds the cells of the variables it captures
+            let closure = entry_values[0];
+            for (i, capture) in captures.iter().enumerate() {
+                let offset = (i as i32 + 1) * int.bytes() as i32;
+                let cell = trans.builder.ins().load(int, MemFlags::new().with_aligned(), closure, offset);
+                let ty = trans.variable_type(capture);
+                let variable = trans.declare(capture, ty, true);
+                trans.builder.def_var(variable, cell);
+            }
+            for (param, value) in params.iter().zip(&entry_values[1..]) {
+                trans.declare_initialized(param, *value);
+            }
+            trans.declare_locals(&mut body);
+            let mut result = trans.builder.ins().iconst(int, 0);
+            for expr in body {
+                result = trans.translate_expr(expr);
+            }
+            vec![trans.coerce(result, int)]
+        }
+        Code::Adapter(function) => {
+            // Functions which bounce are adapted by calling their body, so
+            // that the bounces reach the trampoline which called the adapter
+            let body = if function == name { trans.jit.this_body } else { trans.jit.bodies.get(&function).copied() };
+            let args = entry_values[1..].to_vec();
+            let result = match body {
+                Some(body) => trans.call_function(body, args)[0],
+                None => trans.translate_direct_call(function, args)[0],
+            };
+            vec![trans.coerce(result, int)]
+        }
+        Code::Dispatcher(args) => {
+            let buffer = trans.tail_buffer();
+            let word = int.bytes() as i32;
+            let flags = MemFlags::new().with_aligned();
+            let closure = trans.builder.ins().load(int, flags, buffer, word);
+            let mut arg_values = vec![closure];
+            for i in 0..args as i32 {
+                arg_values.push(trans.builder.ins().load(int, flags, buffer, (i + 2) * word));
+            }
+            let code = trans.builder.ins().load(int, flags, closure, 0);
+            let sig = trans.closure_signature(args);
+            let sig = trans.builder.import_signature(sig);
+            let call = trans.builder.ins().call_indirect(sig, code, &arg_values);
+            vec![trans.builder.inst_results(call)[0]]
+        }
+    };
 
-        // Set up the return variable of the function. Above, we declared a
-        // variable to hold the return value. Here, we just do a use of that
-        // variable.
-        let return_variable = trans.variables.get(&the_return).unwrap();
-        let return_value = trans.builder.use_var(*return_variable);
-
-        // Emit the return instruction.
-        trans.builder.ins().return_(&[return_value]);
+    // Emit the return instruction.
+    trans.builder.ins().return_(&return_values);
 
-        // Tell the builder we're done with this function.
-        trans.builder.finalize();
-        Ok(())
+    // Tell the builder we're done with this function.
+    trans.builder.finalize();
+    match trans.error {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
 }
 
-/// A collection of state used for translating from toy-language AST nodes
+/// The parameter and return types of a function which is used as a closure.
+fn adapted_signature(
+    translation: &Translation,
+    function: &str,
+    int: types::Type,
+) -> Result<(Vec<types::Type>, Vec<types::Type>), String> {
+    let (this, types) = translation.this;
+    let scheme = if function == this { Some(&types.scheme) } else { translation.types.function(function) };
+    scheme.and_then(|s| abi_signature(&s.ty, int))
+          .ok_or_else(|| format!("The type of '{function}' isn't known, so it can't be used as a value"))
+}
+
+/// A variable of a function being translated
+#[derive(Debug, Clone, Copy)]
+struct Var {
+    variable: Variable,
+    /// How the value of the variable is represented
+    ty: types::Type,
+    /// Whether the variable holds the address of a cell which holds the
+    /// value, because a closure captures it
+    boxed: bool,
+}
+
+/// A collection of state used for translating a function from AST nodes
 /// into Cranelift IR.
-struct FunctionTranslator<'a> {
+struct FunctionTranslator<'a, 'b> {
     int: types::Type,
     builder: FunctionBuilder<'a>,
-    variables: HashMap<String, Variable>,
-    module: &'a mut JITModule,
+    variables: HashMap<String, Var>,
+    jit: &'a mut Translation<'b>,
+    return_types: Vec<types::Type>,
+    /// Where the function being compiled jumps back to when it calls itself
+    /// in tail position
+    header: Option<Block>,
+    /// The allocator, once the function refers to it
+    malloc: Option<codegen::ir::FuncRef>,
+    /// The buffer which calls bounce through, once the function refers to it
+    tail_buffer: Option<codegen::ir::GlobalValue>,
+
+    /// The first error found during translation. Translation carries on past
+    /// an error with placeholder values, so that the function is still
+    /// finalized and the builder context left clean.
+    error: Option<String>,
 }
 
-impl<'a> FunctionTranslator<'a> {
+impl FunctionTranslator<'_, '_> {
     /// When you write out instructions in Cranelift, you get back `Value`s. You
     /// can then use these references in other instructions.
     fn translate_expr(&mut self, expr: Expr) -> Value {
         match expr {
-            Expr::Literal(literal) => {
-                let imm: i32 = literal.parse().unwrap();
-                self.builder.ins().iconst(self.int, i64::from(imm))
+            Expr::Literal(imm) => self.builder.ins().iconst(self.int, imm),
+            Expr::Bool(b) => self.builder.ins().iconst(self.int, b as i64),
+            Expr::Float(n) => self.builder.ins().f64const(n),
+            Expr::Str(s) => self.translate_string(s),
+            Expr::Located(_, expr) | Expr::Instance(_, expr) => self.translate_expr(*expr),
+            Expr::Typed(ty, expr) => {
+                let ty = abi_type(&ty, self.int);
+                match *expr {
+                    Expr::Call(name, args, _) => self.translate_call(name, args, Some(ty)),
+                    Expr::CallValue(callee, args) => self.translate_call_value(*callee, args, ty),
+                    Expr::Tail(call) => self.translate_tail_call(*call, ty),
+                    Expr::GetArrayElem(addr, index) => self.translate_array_get(*addr, *index, ty),
+                    expr => {
+                        let value = self.translate_expr(expr);
+                        self.coerce(value, ty)
+                    }
+                }
             }
+            Expr::Convert(number, expr) => self.translate_convert(number, *expr),
 
             Expr::Add(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().iadd(lhs, rhs)
+                let (lhs, rhs) = self.translate_operands(*lhs, *rhs);
+                if self.is_float(lhs) {
+                    self.builder.ins().fadd(lhs, rhs)
+                } else {
+                    self.builder.ins().iadd(lhs, rhs)
+                }
             }
 
             Expr::Sub(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().isub(lhs, rhs)
+                let (lhs, rhs) = self.translate_operands(*lhs, *rhs);
+                if self.is_float(lhs) {
+                    self.builder.ins().fsub(lhs, rhs)
+                } else {
+                    self.builder.ins().isub(lhs, rhs)
+                }
+            }
+
+            Expr::Neg(x) => {
+                let x = self.translate_expr(*x);
+                if self.is_float(x) {
+                    self.builder.ins().fneg(x)
+                } else {
+                    self.builder.ins().ineg(x)
+                }
             }
 
             Expr::Mul(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().imul(lhs, rhs)
+                let (lhs, rhs) = self.translate_operands(*lhs, *rhs);
+                if self.is_float(lhs) {
+                    self.builder.ins().fmul(lhs, rhs)
+                } else {
+                    self.builder.ins().imul(lhs, rhs)
+                }
             }
 
             Expr::Div(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().udiv(lhs, rhs)
+                let (lhs, rhs) = self.translate_operands(*lhs, *rhs);
+                if self.is_float(lhs) {
+                    self.builder.ins().fdiv(lhs, rhs)
+                } else {
+                    self.translate_int_division(lhs, rhs, false)
+                }
             }
 
             Expr::Modulo(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().urem(lhs, rhs)
-            }
-
-            Expr::Eq(lhs, rhs) => self.translate_icmp(IntCC::Equal, *lhs, *rhs),
-            Expr::Ne(lhs, rhs) => self.translate_icmp(IntCC::NotEqual, *lhs, *rhs),
-            Expr::Lt(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThan, *lhs, *rhs),
-            Expr::Le(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThanOrEqual, *lhs, *rhs),
-            Expr::Gt(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThan, *lhs, *rhs),
-            Expr::Ge(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThanOrEqual, *lhs, *rhs),
-            Expr::Call(name, args) => self.translate_call(name, args),
+                let (lhs, rhs) = self.translate_operands(*lhs, *rhs);
+                self.translate_int_division(lhs, rhs, true)
+            }
+
+            Expr::Eq(lhs, rhs) => self.translate_cmp(IntCC::Equal, FloatCC::Equal, *lhs, *rhs),
+            Expr::Ne(lhs, rhs) => self.translate_cmp(IntCC::NotEqual, FloatCC::NotEqual, *lhs, *rhs),
+            Expr::Lt(lhs, rhs) => self.translate_cmp(IntCC::SignedLessThan, FloatCC::LessThan, *lhs, *rhs),
+            Expr::Le(lhs, rhs) => {
+                self.translate_cmp(IntCC::SignedLessThanOrEqual, FloatCC::LessThanOrEqual, *lhs, *rhs)
+            }
+            Expr::Gt(lhs, rhs) => self.translate_cmp(IntCC::SignedGreaterThan, FloatCC::GreaterThan, *lhs, *rhs),
+            Expr::Ge(lhs, rhs) => {
+                self.translate_cmp(IntCC::SignedGreaterThanOrEqual, FloatCC::GreaterThanOrEqual, *lhs, *rhs)
+            }
+            Expr::Call(name, args, _) => self.translate_call(name, args, None),
             Expr::GlobalDataAddr(name) => self.translate_global_data_addr(name),
             Expr::Identifier(name) => {
                 // `use_var` is used to read the value of a variable.
-                let variable = match self.variables.get(&name) {
-                    Some(v) => v,
-                    None => {
-                        println!("Variable \'{name}\' is not defined");
-                        todo!()
+                match self.variables.get(&name).copied() {
+                    Some(Var { variable, ty, boxed: true }) => {
+                        let cell = self.builder.use_var(variable);
+                        self.builder.ins().load(ty, MemFlags::new().with_aligned(), cell, 0)
                     }
-                };
-                self.builder.use_var(*variable)
+                    Some(Var { variable, .. }) => self.builder.use_var(variable),
+                    // Anything else is a function used as a value
+                    None => self.translate_function_value(name),
+                }
             }
+            Expr::Lambda(lambda) => self.translate_lambda(*lambda),
+            Expr::CallValue(callee, args) => self.translate_call_value(*callee, args, self.int),
+            Expr::Tail(call) => self.translate_tail_call(*call, self.int),
             Expr::Assign(name, expr) => self.translate_assign(name, *expr),
+            Expr::AssignValues(names, expr) => {
+                let types: Vec<types::Type> = names.iter().map(|name| self.variable_type(name)).collect();
+                let values = self.translate_values(*expr, &types);
+                for (name, value) in names.into_iter().zip(values) {
+                    self.assign(name, value);
+                }
+                self.builder.ins().iconst(self.int, 0)
+            }
+            Expr::Values(exprs) => {
+                // Where a single value is expected, as when the values are
+                // thrown away, the first one stands for all of them
+                let mut values = Vec::new();
+                for expr in exprs {
+                    values.push(self.translate_expr(expr));
+                }
+                values[0]
+            }
             Expr::IfElse(condition, then_body, else_body) => {
                 self.translate_if_else(*condition, then_body, else_body)
             }
@@ -317,19 +1053,7 @@ impl<'a> FunctionTranslator<'a> {
                 // Return the address of this stack slot
                 stack_addr
             }
-            Expr::GetArrayElem(addr, index) => {
-                let addr = self.translate_expr(*addr);
-                let index = self.translate_expr(*index);
-                let elem_size = self.builder.ins().iconst(self.int, 64);
-                let elem_offset = self.builder.ins().imul(index, elem_size);
-                let elem_addr = self.builder.ins().iadd(addr, elem_offset);
-                self.builder.ins().load(
-                    self.int,
-                    MemFlags::new().with_aligned(),
-                    elem_addr,
-                    0
-                )
-            }
+            Expr::GetArrayElem(addr, index) => self.translate_array_get(*addr, *index, self.int),
             Expr::SetArrayElem(addr, index, value) => {
                 let addr = self.translate_expr(*addr);
                 let index = self.translate_expr(*index);
@@ -346,30 +1070,169 @@ impl<'a> FunctionTranslator<'a> {
                 self.builder.ins().iconst(self.int, 0)
             }
 
-            _ => { todo!() }
+            Expr::Sequence(body) => {
+                let mut result = self.builder.ins().iconst(self.int, 0);
+                for expr in body {
+                    result = self.translate_expr(expr);
+                }
+                result
+            }
+        }
+    }
+
+    /// Record a translation error, returning a placeholder value.
+    fn fail(&mut self, message: String) -> Value {
+        if self.error.is_none() {
+            self.error = Some(message);
+        }
+        self.builder.ins().iconst(self.int, 0)
+    }
+
+    fn is_float(&self, value: Value) -> bool {
+        self.builder.func.dfg.value_type(value).is_float()
+    }
+
+    /// Reinterpret a value as another type of the same size or smaller, which
+    /// is how values of generic types are passed around.
+    fn coerce(&mut self, value: Value, to: types::Type) -> Value {
+        let from = self.builder.func.dfg.value_type(value);
+        if from == to || from.is_float() == to.is_float() {
+            return value;
+        }
+        let flags = MemFlags::new();
+        if from.is_float() {
+            let bits = self.builder.ins().bitcast(from.as_int(), flags, value);
+            if from.bits() < to.bits() {
+                self.builder.ins().uextend(to, bits)
+            } else {
+                bits
+            }
+        } else {
+            let bits = if from.bits() > to.bits() {
+                self.builder.ins().ireduce(to.as_int(), value)
+            } else {
+                value
+            };
+            self.builder.ins().bitcast(to, flags, bits)
+        }
+    }
+
+    /// Translate the operands of a binary operator, which have the same type.
+    fn translate_operands(&mut self, lhs: Expr, rhs: Expr) -> (Value, Value) {
+        let lhs = self.translate_expr(lhs);
+        let rhs = self.translate_expr(rhs);
+        let ty = self.builder.func.dfg.value_type(lhs);
+        (lhs, self.coerce(rhs, ty))
+    }
+
+    fn translate_convert(&mut self, number: Number, expr: Expr) -> Value {
+        if let (Number::F32, Expr::Float(n)) = (number, &expr) {
+            return self.builder.ins().f32const(*n as f32);
+        }
+        let value = self.translate_expr(expr);
+        let from = self.builder.func.dfg.value_type(value);
+        let to = match number {
+            Number::Int => self.int,
+            Number::F32 => types::F32,
+            Number::F64 => types::F64,
+        };
+        match (from.is_float(), to.is_float()) {
+            _ if from == to => value,
+            (false, true) => self.builder.ins().fcvt_from_sint(to, value),
+            // Floats out of range saturate, and NaN becomes 0
+            (true, false) => self.builder.ins().fcvt_to_sint_sat(to, value),
+            (true, true) if from.bits() < to.bits() => self.builder.ins().fpromote(to, value),
+            (true, true) => self.builder.ins().fdemote(to, value),
+            (false, false) => value,
         }
     }
 
+    /// Divide integers, or take the remainder, without the machine
+    /// instructions' traps, which would kill the host process. Dividing by
+    /// zero calls the runtime, which reports it, and gives 0. Dividing by -1
+    /// wraps around, like the interpreter does.
+    fn translate_int_division(&mut self, lhs: Value, rhs: Value, remainder: bool) -> Value {
+        let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+        let error_block = self.builder.create_block();
+        let divide_block = self.builder.create_block();
+        self.builder.ins().brif(is_zero, error_block, &[], divide_block, &[]);
+
+        self.builder.switch_to_block(error_block);
+        self.builder.seal_block(error_block);
+        let sig = self.jit.module.make_signature();
+        match self.jit.module.declare_function(DIVISION_BY_ZERO, Linkage::Import, &sig) {
+            Ok(callee) => {
+                self.call_function(callee, Vec::new());
+            }
+            Err(e) => {
+                self.fail(format!("Can't report division by zero: {e}"));
+            }
+        }
+        self.builder.ins().jump(divide_block, &[]);
+
+        self.builder.switch_to_block(divide_block);
+        self.builder.seal_block(divide_block);
+        let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+        let is_special = self.builder.ins().bor(is_zero, is_minus_one);
+        let one = self.builder.ins().iconst(self.int, 1);
+        let divisor = self.builder.ins().select(is_special, one, rhs);
+        if remainder {
+            // Which is 0 for both
+            return self.builder.ins().srem(lhs, divisor);
+        }
+        let quotient = self.builder.ins().sdiv(lhs, divisor);
+        let negated = self.builder.ins().ineg(lhs);
+        let zero = self.builder.ins().iconst(self.int, 0);
+        let quotient = self.builder.ins().select(is_minus_one, negated, quotient);
+        self.builder.ins().select(is_zero, zero, quotient)
+    }
+
     fn translate_assign(&mut self, name: String, expr: Expr) -> Value {
+        let new_value = self.translate_expr(expr);
+        self.assign(name, new_value)
+    }
+
+    fn assign(&mut self, name: String, new_value: Value) -> Value {
         // `def_var` is used to write the value of a variable. Note that
         // variables can have multiple definitions. Cranelift will
         // convert them into SSA form for itself automatically.
-        let new_value = self.translate_expr(expr);
-        let variable = match self.variables.get(&name) {
-            Some(v) => v,
-            None => {
-                println!("Undefined variable: {name:?}");
-                todo!();
+        match self.variables.get(&name).copied() {
+            Some(Var { variable, ty, boxed }) => {
+                let new_value = self.coerce(new_value, ty);
+                if boxed {
+                    let cell = self.builder.use_var(variable);
+                    self.builder.ins().store(MemFlags::new().with_aligned(), new_value, cell, 0);
+                } else {
+                    self.builder.def_var(variable, new_value);
+                }
+                new_value
             }
+            None => self.fail(format!("Undefined variable: {name:?}")),
+        }
+    }
+
+    fn translate_cmp(&mut self, icmp: IntCC, fcmp: FloatCC, lhs: Expr, rhs: Expr) -> Value {
+        let (lhs, rhs) = self.translate_operands(lhs, rhs);
+        let c = if self.is_float(lhs) {
+            self.builder.ins().fcmp(fcmp, lhs, rhs)
+        } else {
+            self.builder.ins().icmp(icmp, lhs, rhs)
         };
-        self.builder.def_var(*variable, new_value);
-        new_value
+        self.builder.ins().uextend(self.int, c)
     }
 
-    fn translate_icmp(&mut self, cmp: IntCC, lhs: Expr, rhs: Expr) -> Value {
-        let lhs = self.translate_expr(lhs);
-        let rhs = self.translate_expr(rhs);
-        self.builder.ins().icmp(cmp, lhs, rhs)
+    fn translate_array_get(&mut self, addr: Expr, index: Expr, ty: types::Type) -> Value {
+        let addr = self.translate_expr(addr);
+        let index = self.translate_expr(index);
+        let elem_size = self.builder.ins().iconst(self.int, 64);
+        let elem_offset = self.builder.ins().imul(index, elem_size);
+        let elem_addr = self.builder.ins().iadd(addr, elem_offset);
+        self.builder.ins().load(
+            ty,
+            MemFlags::new().with_aligned(),
+            elem_addr,
+            0
+        )
     }
 
     fn translate_if_else(
@@ -384,13 +1247,6 @@ impl<'a> FunctionTranslator<'a> {
         let else_block = self.builder.create_block();
         let merge_block = self.builder.create_block();
 
-        // If-else constructs in the toy language have a return value.
-        // In traditional SSA form, this would produce a PHI between
-        // the then and else bodies. Cranelift uses block parameters,
-        // so set up a parameter in the merge block, and we'll pass
-        // the return values to it from the branches.
-        self.builder.append_block_param(merge_block, self.int);
-
         // Test the if condition and conditionally branch.
         self.builder
             .ins()
@@ -403,7 +1259,21 @@ impl<'a> FunctionTranslator<'a> {
             then_return = self.translate_expr(expr);
         }
 
+        // If-else constructs in loom have a value.
+        // In traditional SSA form, this would produce a PHI between
+        // the then and else bodies. Cranelift uses block parameters,
+        // so set up a parameter in the merge block, and we'll pass
+        // the return values to it from the branches. Without an else
+        // branch, the value isn't used, and is just an integer.
+        let ty = if else_body.is_empty() {
+            self.int
+        } else {
+            self.builder.func.dfg.value_type(then_return)
+        };
+        self.builder.append_block_param(merge_block, ty);
+
         // Jump to the merge block, passing it the block return value.
+        let then_return = self.coerce(then_return, ty);
         self.builder.ins().jump(merge_block, &[then_return]);
 
         self.builder.switch_to_block(else_block);
@@ -414,6 +1284,7 @@ impl<'a> FunctionTranslator<'a> {
         }
 
         // Jump to the merge block, passing it the block return value.
+        let else_return = self.coerce(else_return, ty);
         self.builder.ins().jump(merge_block, &[else_return]);
 
         // Switch to the merge block for subsequent statements.
@@ -429,6 +1300,84 @@ impl<'a> FunctionTranslator<'a> {
         phi
     }
 
+    /// Translate an expression which produces a value of each type, like
+    /// the last expression of a function which returns several values.
+    fn translate_values(&mut self, expr: Expr, types: &[types::Type]) -> Vec<Value> {
+        if let [ty] = types {
+            let value = self.translate_expr(expr);
+            return vec![self.coerce(value, *ty)];
+        }
+        let values = match expr {
+            Expr::Located(_, expr) => return self.translate_values(*expr, types),
+            Expr::Sequence(body) => return self.translate_body_values(body, types),
+            Expr::IfElse(condition, then_body, else_body) if !else_body.is_empty() => {
+                return self.translate_if_else_values(*condition, then_body, else_body, types);
+            }
+            Expr::Values(exprs) => exprs.into_iter().map(|expr| self.translate_expr(expr)).collect(),
+            Expr::Typed(_, call) => match *call {
+                Expr::Call(name, args, _) => self.translate_call_values(name, args),
+                // Calls which return several values are only marked as tail
+                // calls when they jump back to the start of the function
+                Expr::Tail(call) => {
+                    self.translate_tail_call(*call, self.int);
+                    types.iter().map(|ty| zero(&mut self.builder, *ty)).collect()
+                }
+                call => vec![self.translate_expr(call)],
+            },
+            expr => vec![self.translate_expr(expr)],
+        };
+        if values.len() != types.len() {
+            self.fail(format!("Expected {} values, found {}", types.len(), values.len()));
+            return types.iter().map(|ty| zero(&mut self.builder, *ty)).collect();
+        }
+        values.into_iter().zip(types).map(|(value, ty)| self.coerce(value, *ty)).collect()
+    }
+
+    /// Translate a body, whose last expression produces a value of each type.
+    fn translate_body_values(&mut self, mut body: Vec<Expr>, types: &[types::Type]) -> Vec<Value> {
+        let Some(last) = body.pop() else {
+            return types.iter().map(|ty| zero(&mut self.builder, *ty)).collect();
+        };
+        for expr in body {
+            self.translate_expr(expr);
+        }
+        self.translate_values(last, types)
+    }
+
+    /// Like `translate_if_else`, for branches which produce a value of each
+    /// type, which the merge block takes a parameter for.
+    fn translate_if_else_values(
+        &mut self,
+        condition: Expr,
+        then_body: Vec<Expr>,
+        else_body: Vec<Expr>,
+        types: &[types::Type],
+    ) -> Vec<Value> {
+        let condition_value = self.translate_expr(condition);
+
+        let then_block = self.builder.create_block();
+        let else_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        for ty in types {
+            self.builder.append_block_param(merge_block, *ty);
+        }
+
+        self.builder
+            .ins()
+            .brif(condition_value, then_block, &[], else_block, &[]);
+
+        for (block, body) in [(then_block, then_body), (else_block, else_body)] {
+            self.builder.switch_to_block(block);
+            self.builder.seal_block(block);
+            let values = self.translate_body_values(body, types);
+            self.builder.ins().jump(merge_block, &values);
+        }
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+        self.builder.block_params(merge_block).to_vec()
+    }
+
     fn translate_while_loop(&mut self, condition: Expr, loop_body: Vec<Expr>) -> Value {
         let header_block = self.builder.create_block();
         let body_block = self.builder.create_block();
@@ -461,116 +1410,499 @@ impl<'a> FunctionTranslator<'a> {
         self.builder.ins().iconst(self.int, 0)
     }
 
-    fn translate_call(&mut self, name: String, args: Vec<Expr>) -> Value {
-        let mut sig = self.module.make_signature();
+    /// Call a function, returning its result as `result` if given. Of
+    /// several values, only the first is kept.
+    fn translate_call(&mut self, name: String, args: Vec<Expr>, result: Option<types::Type>) -> Value {
+        let value = self.translate_call_values(name, args)[0];
+        let ty = self.builder.func.dfg.value_type(value);
+        self.coerce(value, result.unwrap_or(ty))
+    }
 
-        // Add a parameter for each argument.
-        for _arg in &args {
-            sig.params.push(AbiParam::new(self.int));
+    /// Call a function, returning every value it returns.
+    fn translate_call_values(&mut self, name: String, args: Vec<Expr>) -> Vec<Value> {
+        if !self.jit.is_defined(&name) {
+            return vec![self.fail(format!("Function '{name}' is not defined"))];
         }
 
-        // For simplicity for now, just make all calls return a single I64.
-        sig.returns.push(AbiParam::new(self.int));
-
-        // TODO: Streamline the API here?
-        let callee = self
-            .module
-            .declare_function(&name, Linkage::Import, &sig)
-            .expect("problem declaring function");
-        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
-
         let mut arg_values = Vec::new();
         for arg in args {
             arg_values.push(self.translate_expr(arg))
         }
+        self.translate_direct_call(name, arg_values)
+    }
+
+    /// Call a function by name, returning its results the way the function
+    /// returns them.
+    fn translate_direct_call(&mut self, name: String, arg_values: Vec<Value>) -> Vec<Value> {
+        // The signature follows the callee's type if it's known, and the
+        // arguments otherwise, for host functions.
+        let scheme = if name == self.jit.this.0 { Some(&self.jit.this.1.scheme) } else { self.jit.types.function(&name) };
+        let (params, returns) = match scheme.and_then(|s| abi_signature(&s.ty, self.int)) {
+            Some(signature) => signature,
+            None => {
+                let params = arg_values.iter().map(|v| self.builder.func.dfg.value_type(*v)).collect();
+                (params, vec![self.int])
+            }
+        };
+        let mut sig = self.jit.module.make_signature();
+        for ty in &params {
+            sig.params.push(AbiParam::new(*ty));
+        }
+        for ty in &returns {
+            sig.returns.push(AbiParam::new(*ty));
+        }
+
+        let symbol = self.jit.symbols.get(&name).unwrap_or(&name);
+        let callee = match self.jit.module.declare_function(symbol, Linkage::Import, &sig) {
+            Ok(callee) => callee,
+            Err(e) => return vec![self.fail(format!("Can't call '{name}': {e}"))],
+        };
+        self.call_function(callee, arg_values)
+    }
+
+    /// Call a function of the module, coercing the arguments to its
+    /// parameter types, and returning its results.
+    fn call_function(&mut self, callee: FuncId, arg_values: Vec<Value>) -> Vec<Value> {
+        let params: Vec<types::Type> = self.jit
+                                           .module
+                                           .declarations()
+                                           .get_function_decl(callee)
+                                           .signature
+                                           .params
+                                           .iter()
+                                           .map(|p| p.value_type)
+                                           .collect();
+        let local_callee = self.jit.module.declare_func_in_func(callee, self.builder.func);
+        let arg_values: Vec<Value> = arg_values.into_iter()
+                                               .zip(params)
+                                               .map(|(value, ty)| self.coerce(value, ty))
+                                               .collect();
         let call = self.builder.ins().call(local_callee, &arg_values);
+        self.builder.inst_results(call).to_vec()
+    }
+
+    /// Translate a call in tail position, see `tail`. The function returns,
+    /// so the value is a placeholder of type `result`.
+    fn translate_tail_call(&mut self, call: Expr, result: types::Type) -> Value {
+        match call {
+            Expr::Call(name, args, _) if name == self.jit.this.0 && self.header.is_some() => {
+                let header = self.header.unwrap();
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(self.translate_expr(arg));
+                }
+                let params: Vec<types::Type> = self.builder
+                                                   .func
+                                                   .dfg
+                                                   .block_params(header)
+                                                   .iter()
+                                                   .map(|p| self.builder.func.dfg.value_type(*p))
+                                                   .collect();
+                let arg_values: Vec<Value> = arg_values.into_iter()
+                                                       .zip(params)
+                                                       .map(|(value, ty)| self.coerce(value, ty))
+                                                       .collect();
+                self.builder.ins().jump(header, &arg_values);
+            }
+            Expr::Call(name, args, _) => {
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(self.translate_expr(arg));
+                }
+                let closure = self.adapter_closure(name);
+                self.bounce(closure, arg_values);
+            }
+            Expr::CallValue(callee, args) => {
+                let closure = self.translate_expr(*callee);
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(self.translate_expr(arg));
+                }
+                self.bounce(closure, arg_values);
+            }
+            call => {
+                let value = self.translate_expr(call);
+                return self.coerce(value, result);
+            }
+        }
+
+        // Anything after the call is unreachable
+        let block = self.builder.create_block();
+        self.builder.switch_to_block(block);
+        self.builder.seal_block(block);
+        zero(&mut self.builder, result)
+    }
+
+    /// Return from the function, leaving a call of a closure for the
+    /// trampoline which called the function to make.
+    fn bounce(&mut self, closure: Value, arg_values: Vec<Value>) {
+        let dispatcher = self.dispatcher(arg_values.len());
+        let dispatcher = self.jit.module.declare_func_in_func(dispatcher, self.builder.func);
+        let dispatcher = self.builder.ins().func_addr(self.int, dispatcher);
+        let buffer = self.tail_buffer();
+        let word = self.int.bytes() as i32;
+        let flags = MemFlags::new().with_aligned();
+        self.builder.ins().store(flags, dispatcher, buffer, 0);
+        self.builder.ins().store(flags, closure, buffer, word);
+        for (i, value) in arg_values.into_iter().enumerate() {
+            let value = self.coerce(value, self.int);
+            self.builder.ins().store(flags, value, buffer, (i as i32 + 2) * word);
+        }
+        let placeholders: Vec<Value> = self.return_types
+                                            .clone()
+                                            .into_iter()
+                                            .map(|ty| zero(&mut self.builder, ty))
+                                            .collect();
+        self.builder.ins().return_(&placeholders);
+    }
+
+    /// Make the calls which bounced after a call which returned a value,
+    /// until one of them returns without bouncing. The result has the same
+    /// type as the value.
+    fn trampoline(&mut self, value: Value) -> Value {
+        let ty = self.builder.func.dfg.value_type(value);
+        let check_block = self.builder.create_block();
+        let bounce_block = self.builder.create_block();
+        let done_block = self.builder.create_block();
+        self.builder.append_block_param(check_block, ty);
+        self.builder.append_block_param(done_block, ty);
+        self.builder.ins().jump(check_block, &[value]);
+
+        // A call bounced if there's a dispatcher in the buffer
+        self.builder.switch_to_block(check_block);
+        let value = self.builder.block_params(check_block)[0];
+        let buffer = self.tail_buffer();
+        let flags = MemFlags::new().with_aligned();
+        let dispatcher = self.builder.ins().load(self.int, flags, buffer, 0);
+        self.builder.ins().brif(dispatcher, bounce_block, &[], done_block, &[value]);
+
+        self.builder.switch_to_block(bounce_block);
+        self.builder.seal_block(bounce_block);
+        let empty = self.builder.ins().iconst(self.int, 0);
+        self.builder.ins().store(flags, empty, buffer, 0);
+        let mut sig = self.jit.module.make_signature();
+        sig.returns.push(AbiParam::new(self.int));
+        let sig = self.builder.import_signature(sig);
+        let call = self.builder.ins().call_indirect(sig, dispatcher, &[]);
+        let result = self.builder.inst_results(call)[0];
+        let result = self.coerce(result, ty);
+        self.builder.ins().jump(check_block, &[result]);
+        self.builder.seal_block(check_block);
+
+        self.builder.switch_to_block(done_block);
+        self.builder.seal_block(done_block);
+        self.builder.block_params(done_block)[0]
+    }
+
+    /// The address of the buffer which calls bounce through
+    fn tail_buffer(&mut self) -> Value {
+        let buffer = match self.tail_buffer {
+            Some(buffer) => buffer,
+            None => {
+                let buffer = self.jit.module.declare_data_in_func(self.jit.tail_call, self.builder.func);
+                *self.tail_buffer.insert(buffer)
+            }
+        };
+        self.builder.ins().symbol_value(self.int, buffer)
+    }
+
+    /// The code which makes calls with a number of arguments which bounced
+    fn dispatcher(&mut self, args: usize) -> FuncId {
+        if let Some(dispatcher) = self.jit.dispatchers.get(&args) {
+            return *dispatcher;
+        }
+        let mut sig = self.jit.module.make_signature();
+        sig.returns.push(AbiParam::new(self.int));
+        let dispatcher = match self.jit.module.declare_anonymous_function(&sig) {
+            Ok(dispatcher) => dispatcher,
+            Err(e) => {
+                self.fail(format!("Can't declare a dispatcher: {e}"));
+                return FuncId::from_u32(0);
+            }
+        };
+        self.jit.dispatchers.insert(args, dispatcher);
+        self.jit.pending.push((dispatcher, Code::Dispatcher(args)));
+        dispatcher
+    }
+
+    /// Call a function value, returning its result as `result`.
+    fn translate_call_value(&mut self, callee: Expr, args: Vec<Expr>, result: types::Type) -> Value {
+        let closure = self.translate_expr(callee);
+        let mut arg_values = vec![closure];
+        for arg in args {
+            let value = self.translate_expr(arg);
+            arg_values.push(self.coerce(value, self.int));
+        }
+        let code = self.builder.ins().load(self.int, MemFlags::new().with_aligned(), closure, 0);
+        let sig = self.closure_signature(arg_values.len() - 1);
+        let sig = self.builder.import_signature(sig);
+        let call = self.builder.ins().call_indirect(sig, code, &arg_values);
+        let value = self.builder.inst_results(call)[0];
+        let value = self.trampoline(value);
+        self.coerce(value, result)
+    }
+
+    /// The signature of the code of closures which take a number of
+    /// parameters
+    fn closure_signature(&self, params: usize) -> Signature {
+        let mut sig = self.jit.module.make_signature();
+        for _ in 0..=params {
+            sig.params.push(AbiParam::new(self.int));
+        }
+        sig.returns.push(AbiParam::new(self.int));
+        sig
+    }
+
+    /// Create a closure on the heap, given the code it runs and the cells of
+    /// the variables it captures.
+    fn make_closure(&mut self, code: FuncId, cells: Vec<Value>) -> Value {
+        let size = (cells.len() as i64 + 1) * self.int.bytes() as i64;
+        let closure = self.allocate(size);
+        let local_code = self.jit.module.declare_func_in_func(code, self.builder.func);
+        let code = self.builder.ins().func_addr(self.int, local_code);
+        for (i, value) in [code].into_iter().chain(cells).enumerate() {
+            let offset = i as i32 * self.int.bytes() as i32;
+            self.builder.ins().store(MemFlags::new().with_aligned(), value, closure, offset);
+        }
+        closure
+    }
+
+    fn translate_lambda(&mut self, lambda: Lambda) -> Value {
+        let sig = self.closure_signature(lambda.params.len());
+        let code = match self.jit.module.declare_anonymous_function(&sig) {
+            Ok(code) => code,
+            Err(e) => return self.fail(format!("Can't declare a closure: {e}")),
+        };
+        let cells = lambda.captures
+                          .iter()
+                          .map(|name| self.variables[name].variable)
+                          .map(|variable| self.builder.use_var(variable))
+                          .collect();
+        self.jit.pending.push((code, Code::Closure(lambda)));
+        self.make_closure(code, cells)
+    }
+
+    /// A function defined at the top level, used as a value.
+    fn translate_function_value(&mut self, name: String) -> Value {
+        if !self.jit.is_defined(&name) {
+            return self.fail(format!("Variable '{name}' is not defined"));
+        }
+        self.adapter_closure(name)
+    }
+
+    /// The closure of a function defined at the top level. It calls an
+    /// adapter, which is shared by every use of the function.
+    fn adapter_closure(&mut self, name: String) -> Value {
+        let record = match self.jit.adapters.get(&name) {
+            Some((_, record)) => *record,
+            None => {
+                let scheme = if name == self.jit.this.0 { Some(&self.jit.this.1.scheme) } else { self.jit.types.function(&name) };
+                let Some((params, _)) = scheme.and_then(|s| abi_signature(&s.ty, self.int)) else {
+                    return self.fail(format!("The type of '{name}' isn't known, so it can't be used as a value"));
+                };
+                let sig = self.closure_signature(params.len());
+                let code = match self.jit.module.declare_anonymous_function(&sig) {
+                    Ok(code) => code,
+                    Err(e) => return self.fail(format!("Can't use '{name}' as a value: {e}")),
+                };
+                let record = match self.jit.module.declare_anonymous_data(false, false) {
+                    Ok(record) => record,
+                    Err(e) => return self.fail(format!("Can't use '{name}' as a value: {e}")),
+                };
+                self.jit.adapters.insert(name.clone(), (code, record));
+                self.jit.pending.push((code, Code::Adapter(name)));
+                self.jit.records.push((record, code));
+                record
+            }
+        };
+        let record = self.jit.module.declare_data_in_func(record, self.builder.func);
+        self.builder.ins().symbol_value(self.int, record)
+    }
+
+    /// Allocate memory on the heap, which is never freed.
+    fn allocate(&mut self, size: i64) -> Value {
+        let local_malloc = match self.malloc {
+            Some(malloc) => malloc,
+            None => {
+                let mut sig = self.jit.module.make_signature();
+                sig.params.push(AbiParam::new(self.int));
+                sig.returns.push(AbiParam::new(self.int));
+                let malloc = match self.jit.module.declare_function("malloc", Linkage::Import, &sig) {
+                    Ok(malloc) => malloc,
+                    Err(e) => return self.fail(format!("Can't allocate memory: {e}")),
+                };
+                *self.malloc.insert(self.jit.module.declare_func_in_func(malloc, self.builder.func))
+            }
+        };
+        let size = self.builder.ins().iconst(self.int, size);
+        let call = self.builder.ins().call(local_malloc, &[size]);
         self.builder.inst_results(call)[0]
     }
 
+    /// How the value of a variable is represented, given its type
+    fn variable_type(&self, name: &str) -> types::Type {
+        self.jit.this.1.variables.get(name).map_or(self.int, |t| abi_type(t, self.int))
+    }
+
+    /// Declare a variable, which holds the address of its cell if it's boxed.
+    fn declare(&mut self, name: &str, ty: types::Type, boxed: bool) -> Variable {
+        if let Some(var) = self.variables.get(name) {
+            return var.variable;
+        }
+        let variable = Variable::new(self.variables.len());
+        self.variables.insert(name.into(), Var { variable, ty, boxed });
+        self.builder.declare_var(variable, if boxed { self.int } else { ty });
+        variable
+    }
+
+    /// Declare a variable, in a cell of its own if a closure captures it,
+    /// with an initial value.
+    fn declare_initialized(&mut self, name: &str, value: Value) {
+        let ty = self.variable_type(name);
+        let value = self.coerce(value, ty);
+        let boxed = self.jit.boxed.contains(name);
+        let variable = self.declare(name, ty, boxed);
+        if boxed {
+            let cell = self.allocate(self.int.bytes() as i64);
+            self.builder.ins().store(MemFlags::new().with_aligned(), value, cell, 0);
+            self.builder.def_var(variable, cell);
+        } else {
+            self.builder.def_var(variable, value);
+        }
+    }
+
+    /// Declare every variable a body refers to which isn't declared yet,
+    /// except functions used as values. Cells are created up front, so that
+    /// closures can capture variables before they're assigned.
+    fn declare_locals(&mut self, body: &mut [Expr]) {
+        for name in closures::variables(body) {
+            if self.variables.contains_key(&name) || !self.jit.this.1.variables.contains_key(&name) {
+                continue;
+            }
+            if self.jit.boxed.contains(&name) {
+                let ty = self.variable_type(&name);
+                let zero = zero(&mut self.builder, ty);
+                self.declare_initialized(&name, zero);
+            } else {
+                let ty = self.variable_type(&name);
+                self.declare(&name, ty, false);
+            }
+        }
+    }
+
+    /// String literals become null-terminated data objects of their own.
+    fn translate_string(&mut self, s: String) -> Value {
+        let mut contents = s.into_bytes();
+        contents.push(0);
+        let mut data_ctx = DataContext::new();
+        data_ctx.define(contents.into_boxed_slice());
+        let id = match self.jit.module.declare_anonymous_data(false, false) {
+            Ok(id) => id,
+            Err(e) => return self.fail(format!("Can't declare a string literal: {e}")),
+        };
+        if let Err(e) = self.jit.module.define_data(id, &data_ctx) {
+            return self.fail(format!("Can't define a string literal: {e}"));
+        }
+        let local_id = self.jit.module.declare_data_in_func(id, self.builder.func);
+        self.builder.ins().symbol_value(self.int, local_id)
+    }
+
     fn translate_global_data_addr(&mut self, name: String) -> Value {
-        let sym = self
-            .module
-            .declare_data(&name, Linkage::Export, true, false)
-            .expect("problem declaring data object");
-        let local_id = self.module.declare_data_in_func(sym, self.builder.func);
+        if !self.jit.is_defined(&name) {
+            return self.fail(format!("Data object '{name}' is not defined"));
+        }
+        let sym = match self.jit.module.declare_data(&name, Linkage::Export, true, false) {
+            Ok(sym) => sym,
+            Err(e) => return self.fail(format!("Can't use data object '{name}': {e}")),
+        };
+        let local_id = self.jit.module.declare_data_in_func(sym, self.builder.func);
 
-        let pointer = self.module.target_config().pointer_type();
+        let pointer = self.jit.module.target_config().pointer_type();
         self.builder.ins().symbol_value(pointer, local_id)
     }
 }
 
-fn declare_variables(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    params: &[String],
-    the_return: &str,
-    stmts: &[Expr],
-    entry_block: Block,
-) -> HashMap<String, Variable> {
-    let mut variables = HashMap::new();
-    let mut index = 0;
-
-    for (i, name) in params.iter().enumerate() {
-        // TODO: cranelift_frontend should really have an API to make it easy to set
-        // up param variables.
-        let val = builder.block_params(entry_block)[i];
-        let var = declare_variable(int, builder, &mut variables, &mut index, name);
-        builder.def_var(var, val);
-    }
-    let zero = builder.ins().iconst(int, 0);
-    let return_variable = declare_variable(int, builder, &mut variables, &mut index, the_return);
-    builder.def_var(return_variable, zero);
-    for expr in stmts {
-        declare_variables_in_stmt(int, builder, &mut variables, &mut index, expr);
-    }
-
-    variables
+/// The runtime function which compiled code calls when it divides by zero.
+/// The JIT's records the error for `take_runtime_error`, and the one
+/// executables are linked with stops the program.
+pub(crate) const DIVISION_BY_ZERO: &str = "loom_division_by_zero";
+
+thread_local! {
+    /// The first error the compiled code running on this thread ran into
+    static RUNTIME_ERROR: Cell<Option<&'static str>> = const { Cell::new(None) };
 }
 
-/// Recursively descend through the AST, translating all implicit
-/// variable declarations.
-fn declare_variables_in_stmt(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    index: &mut usize,
-    expr: &Expr,
-) {
-    match *expr {
-        Expr::Assign(ref name, ref value) => {
-            declare_variables_in_stmt(int, builder, variables, index, value);
-            declare_variable(int, builder, variables, index, name);
-        }
-        Expr::IfElse(ref _condition, ref then_body, ref else_body) => {
-            for stmt in then_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
-            for stmt in else_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
+extern "C" fn division_by_zero() {
+    RUNTIME_ERROR.with(|error| {
+        if error.get().is_none() {
+            error.set(Some("Division by zero"));
         }
-        Expr::WhileLoop(ref _condition, ref loop_body) => {
-            for stmt in loop_body {
-                declare_variables_in_stmt(int, builder, variables, index, stmt);
-            }
+    });
+}
+
+/// Take the first error the compiled code running on this thread ran into
+/// since it was last taken. Compiled code carries on past an error with a
+/// placeholder value, so that the host process survives it, but what it
+/// returns is meaningless.
+pub fn take_runtime_error() -> Option<String> {
+    RUNTIME_ERROR.with(|error| error.take()).map(|e| e.to_string())
+}
+
+/// Whether the host process provides a symbol, which is where the JIT looks
+/// for anything the module doesn't define.
+#[cfg(unix)]
+fn host_symbol_exists(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else { return false; };
+    // Safety: `name` is a valid C string, and the symbol is only looked up.
+    unsafe { !libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()).is_null() }
+}
+
+#[cfg(not(unix))]
+fn host_symbol_exists(_name: &str) -> bool {
+    true
+}
+
+/// Collect the names an expression refers to, including the functions it
+/// calls.
+fn references(x: &mut Expr, names: &mut HashSet<String>) {
+    match x {
+        Expr::Identifier(name) | Expr::Call(name, ..) => {
+            names.insert(name.clone());
         }
-        _ => (),
+        _ => {}
+    }
+    for child in x.children_mut() {
+        references(child, names);
     }
 }
 
-/// Declare a single variable declaration.
-fn declare_variable(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    index: &mut usize,
-    name: &str,
-) -> Variable {
-    let var = Variable::new(*index);
-    if !variables.contains_key(name) {
-        variables.insert(name.into(), var);
-        builder.declare_var(var, int);
-        *index += 1;
-    }
-    var
+/// How values of a type are represented in Cranelift. Values of generic types
+/// are pointer-sized integers, which floats are reinterpreted as.
+fn abi_type(ty: &crate::types::Type, int: types::Type) -> types::Type {
+    match ty {
+        crate::types::Type::Float => types::F64,
+        crate::types::Type::F32 => types::F32,
+        _ => int,
+    }
+}
+
+/// The parameter and return types of a function type. A function which
+/// returns several values returns each of them.
+pub(crate) fn abi_signature(ty: &crate::types::Type, int: types::Type) -> Option<(Vec<types::Type>, Vec<types::Type>)> {
+    let crate::types::Type::Fn(params, result) = ty else {
+        return None;
+    };
+    let returns = match result.as_ref() {
+        crate::types::Type::Values(values) => values.iter().map(|v| abi_type(v, int)).collect(),
+        result => vec![abi_type(result, int)],
+    };
+    Some((params.iter().map(|p| abi_type(p, int)).collect(), returns))
+}
+
+fn zero(builder: &mut FunctionBuilder, ty: types::Type) -> Value {
+    match ty {
+        types::F64 => builder.ins().f64const(0.0),
+        types::F32 => builder.ins().f32const(0.0),
+        _ => builder.ins().iconst(ty, 0),
+    }
 }
@@ -1,2 +1,7 @@
 pub mod frontend;
+pub mod resolve;
+pub mod types;
+pub mod closures;
+pub mod tail;
 pub mod jit;
+pub mod object;
@@ -0,0 +1,181 @@
+//! Ahead-of-time compilation, to native object files and executables.
+//!
+//! The object backend translates a module just like the JIT does, but into a
+//! relocatable object file rather than into memory. The functions of the
+//! module are local to the object, so that they can have any name, `main`
+//! included. The object only exports its entry point, `loom_main`, which calls
+//! the program's main function and prints the result.
+//!
+//! The system's linker then links the object with the runtime, `runtime.c`,
+//! which calls the entry point, and with the C library, which provides
+//! everything else compiled code refers to, like `malloc`. Unlike the JIT,
+//! the compiler doesn't look for those names in its own process, since the
+//! executable is linked with different libraries than the compiler is.
+
+use crate::jit::{Compiler, abi_signature, native_isa};
+use crate::types::Type;
+use cranelift::prelude::*;
+use cranelift_module::{DataContext, DataId, Linkage, Module, default_libcall_names};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use loom_reader::parse::Exp;
+use std::error::Error;
+use std::path::Path;
+use std::{env, fs, process};
+
+/// The runtime which compiled programs are linked with.
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The name of the function which the runtime starts the program with.
+pub const ENTRY: &str = "loom_main";
+
+/// The compiler with the object backend.
+pub type ObjectCompiler = Compiler<ObjectModule>;
+
+/// What the entry point prints.
+enum Print {
+    /// A value the main function returns, by index, and its type
+    Value(usize, Type),
+    Text(DataId),
+}
+
+impl Compiler<ObjectModule> {
+    /// Create a compiler for an object file with the given name. The code is
+    /// position-independent, so it can be linked into any executable.
+    pub fn new(name: &str) -> Result<Self, String> {
+        let builder = ObjectBuilder::new(native_isa(true), name, default_libcall_names())
+            .map_err(|e| e.to_string())?;
+        // Anything the module doesn't define is imported, and it's up to the
+        // linker to find it, or to report it missing
+        Ok(Compiler::with_module(ObjectModule::new(builder), Linkage::Local, |_| true))
+    }
+
+    /// Compile the top-level forms of a module into the object, see
+    /// `define_module`. Returns the name of every function, in the order
+    /// they're written.
+    pub fn compile_module(&mut self, forms: &[Exp]) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.define_module(forms)?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Define the entry point, which calls a compiled function without
+    /// parameters, then prints what it returns unless it's nil.
+    pub fn define_entry(&mut self, name: &str) -> Result<(), String> {
+        let result = match self.types.function(name).map(|s| &s.ty) {
+            Some(Type::Fn(params, result)) if params.is_empty() => result.as_ref().clone(),
+            Some(ty) => return Err(format!("'{name}' has type {ty}, so the program can't start with it")),
+            None => return Err(format!("There's no function named '{name}' to start the program with")),
+        };
+
+        // Work out what to print first, since the text has to be defined
+        // before the entry point refers to it
+        let mut prints = Vec::new();
+        match &result {
+            Type::Nil => {}
+            Type::Values(types) => {
+                prints.push(Print::Text(self.define_text("(values")?));
+                for (i, ty) in types.iter().enumerate() {
+                    prints.push(Print::Text(self.define_text(" ")?));
+                    prints.push(self.print_value(i, ty)?);
+                }
+                prints.push(Print::Text(self.define_text(")\n")?));
+            }
+            ty => {
+                prints.push(self.print_value(0, ty)?);
+                prints.push(Print::Text(self.define_text("\n")?));
+            }
+        }
+
+        let int = self.module.target_config().pointer_type();
+        let (_, returns) = abi_signature(&Type::Fn(Vec::new(), Box::new(result)), int).unwrap_or_default();
+        let mut sig = self.module.make_signature();
+        sig.returns.extend(returns.into_iter().map(AbiParam::new));
+        let callee = self.module
+                         .declare_function(&self.symbol(name), Linkage::Import, &sig)
+                         .map_err(|e| e.to_string())?;
+        let mut printers = Vec::new();
+        for (printer, ty) in [("loom_print_int", int), ("loom_print_float", types::F64), ("loom_print_bool", int), ("loom_print_text", int)] {
+            let mut sig = self.module.make_signature();
+            sig.params.push(AbiParam::new(ty));
+            printers.push(self.module.declare_function(printer, Linkage::Import, &sig).map_err(|e| e.to_string())?);
+        }
+        let id = self.module
+                     .declare_function(ENTRY, Linkage::Export, &self.module.make_signature())
+                     .map_err(|e| e.to_string())?;
+
+        self.ctx.func.signature = self.module.make_signature();
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+        let entry_block = builder.create_block();
+        builder.switch_to_block(entry_block);
+        builder.seal_block(entry_block);
+        let local_callee = self.module.declare_func_in_func(callee, builder.func);
+        let call = builder.ins().call(local_callee, &[]);
+        let values = builder.inst_results(call).to_vec();
+        let printers: Vec<_> = printers.into_iter()
+                                       .map(|printer| self.module.declare_func_in_func(printer, builder.func))
+                                       .collect();
+        let (int_printer, float_printer, bool_printer, text_printer) = (printers[0], printers[1], printers[2], printers[3]);
+        for print in prints {
+            let (printer, arg) = match print {
+                Print::Text(text) => {
+                    let text = self.module.declare_data_in_func(text, builder.func);
+                    (text_printer, builder.ins().global_value(int, text))
+                }
+                Print::Value(i, Type::Float) => (float_printer, values[i]),
+                Print::Value(i, Type::F32) => (float_printer, builder.ins().fpromote(types::F64, values[i])),
+                Print::Value(i, Type::Bool) => (bool_printer, values[i]),
+                Print::Value(i, Type::Str) => (text_printer, values[i]),
+                Print::Value(i, _) => (int_printer, values[i]),
+            };
+            builder.ins().call(printer, &[arg]);
+        }
+        builder.ins().return_(&[]);
+        builder.finalize();
+
+        let defined = self.module.define_function(id, &mut self.ctx).map_err(|e| e.to_string());
+        self.module.clear_context(&mut self.ctx);
+        defined.map(|_| ())
+    }
+
+    /// Finish the object, and return its contents.
+    pub fn emit(self) -> Result<Vec<u8>, String> {
+        self.module.finish().emit().map_err(|e| e.to_string())
+    }
+
+    /// How the entry point prints a value of a type. Functions print as their
+    /// type, since there's nothing else to show of them.
+    fn print_value(&mut self, i: usize, ty: &Type) -> Result<Print, String> {
+        Ok(match ty {
+            Type::Fn(..) => Print::Text(self.define_text(&format!("<{ty}>"))?),
+            Type::Nil => Print::Text(self.define_text("nil")?),
+            ty => Print::Value(i, ty.clone()),
+        })
+    }
+
+    /// Define a C string for the entry point to print.
+    fn define_text(&mut self, text: &str) -> Result<DataId, String> {
+        let mut data_ctx = DataContext::new();
+        data_ctx.define(format!("{text}\0").into_bytes().into_boxed_slice());
+        let id = self.module.declare_anonymous_data(false, false).map_err(|e| e.to_string())?;
+        self.module.define_data(id, &data_ctx).map_err(|e| e.to_string())?;
+        Ok(id)
+    }
+}
+
+/// Link an object file and the runtime into an executable, with the system's
+/// C compiler, which is `cc` unless `CC` names another.
+pub fn link(object: &Path, output: &Path) -> Result<(), String> {
+    let runtime = env::temp_dir().join(format!("loom_runtime_{}.c", process::id()));
+    fs::write(&runtime, RUNTIME).map_err(|e| format!("Couldn't write the runtime: {e}"))?;
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = process::Command::new(&cc)
+                                  .arg(object)
+                                  .arg(&runtime)
+                                  .arg("-o")
+                                  .arg(output)
+                                  .status();
+    let _ = fs::remove_file(&runtime);
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("Linking {} failed, {cc} {status}", output.display())),
+        Err(e) => Err(format!("Couldn't run {cc}: {e}")),
+    }
+}
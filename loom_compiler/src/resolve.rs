@@ -0,0 +1,290 @@
+//! Name resolution for compiled functions.
+//!
+//! Every identifier in a function is resolved to one of its parameters, one of
+//! its local variables, a global (a function or data object of the module or
+//! the host process) or a builtin form, before any code is generated. Names
+//! which don't resolve to anything are reported with their location, and so
+//! are variables which shadow other variables.
+//!
+//! Like in the interpreter, `set` on a name which isn't in scope declares a
+//! local variable in the innermost scope, and `let` and `let-values` open a
+//! scope of their own, as do functions written inside the function. Variables
+//! which are only in scope in part of the function are renamed after where
+//! they're declared, so that every variable of the function has a name of its
+//! own.
+
+use loom_reader::diagnostics::{ Diagnostic, ErrorCode, Severity };
+use loom_reader::parse::{ Exp, Span };
+use crate::frontend::{ is_special_form, unique_variable };
+
+/// What an identifier refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// The parameter at an index
+    Param(usize),
+    /// The local at an index of `Resolution::locals`
+    Local(usize),
+    /// A function or data object of the module or the host process
+    Global,
+    /// An operator or special form, which is translated directly
+    Builtin,
+}
+
+/// A local variable of a function
+#[derive(Debug, Clone)]
+pub struct Local {
+    pub name: String,
+    /// Where the variable is declared
+    pub span: Span,
+    /// The name of the variable in the translated function, which is unique
+    /// unless it's declared in the outermost scope of the function
+    pub variable: String,
+}
+
+/// Everything name resolution found out about a function
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// The local variables, in the order they're declared
+    pub locals: Vec<Local>,
+    /// Every identifier, by where it's written, with what it refers to
+    pub uses: Vec<(Span, Binding)>,
+    /// Unbound names and shadowed variables
+    pub diagnostics: Vec<Diagnostic>,
+    /// The function, with every variable renamed to its `Local::variable`,
+    /// except where `let` or `let-values` binds it
+    pub function: Exp,
+}
+
+impl Resolution {
+    /// What the identifier written at a span refers to, if it was resolved
+    pub fn binding(&self, at: Span) -> Option<Binding> {
+        self.uses.iter().find(|(span, _)| *span == at).map(|(_, b)| *b)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Error)
+    }
+}
+
+/// Resolve the names in a function definition, `(fn name [params] [] body...)`.
+///
+/// `is_global` tells whether a function or data object exists, given its name.
+pub fn resolve_function(function: &Exp, is_global: &dyn Fn(&str) -> bool) -> Resolution {
+    let mut resolver = Resolver {
+        is_global,
+        scopes: vec![Vec::new()],
+        resolution: Resolution {
+            locals: Vec::new(),
+            uses: Vec::new(),
+            diagnostics: Vec::new(),
+            function: Exp::Nil(function.span()),
+        },
+    };
+    let mut function = function.clone();
+    if let Exp::SExp { args, .. } = &mut function {
+        if let Some(Exp::List(params, _)) = args.get(1) {
+            for (i, param) in params.iter().enumerate() {
+                if let Exp::Symbol(name, span) = param {
+                    resolver.declare(name, *span, Binding::Param(i));
+                }
+            }
+        }
+        for x in args.iter_mut().skip(3) {
+            resolver.expression(x);
+        }
+    }
+    resolver.resolution.function = function;
+    resolver.resolution
+}
+
+struct Resolver<'a> {
+    is_global: &'a dyn Fn(&str) -> bool,
+    /// The variables in scope, innermost last, with where they're declared
+    scopes: Vec<Vec<(String, Span, Binding)>>,
+    resolution: Resolution,
+}
+
+impl Resolver<'_> {
+    fn lookup(&self, name: &str) -> Option<(Span, Binding)> {
+        self.scopes.iter()
+                   .rev()
+                   .flat_map(|scope| scope.iter().rev())
+                   .find(|(n, ..)| n == name)
+                   .map(|(_, span, binding)| (*span, *binding))
+    }
+
+    /// Bring a variable into the innermost scope, warning if it hides another
+    fn declare(&mut self, name: &str, span: Span, binding: Binding) {
+        if let Some((shadowed, shadowed_binding)) = self.lookup(name) {
+            let kind = match shadowed_binding {
+                Binding::Param(_) => "parameter",
+                _ => "variable",
+            };
+            self.resolution.diagnostics.push(
+                Diagnostic::warning(format!("'{name}' shadows the {kind} of the same name"), span)
+                    .with_code(ErrorCode::ShadowedName)
+                    .with_note(format!("the {kind} is declared at {shadowed}"))
+            );
+        }
+        self.scopes.last_mut().unwrap().push((name.to_string(), span, binding));
+        self.resolution.uses.push((span, binding));
+    }
+
+    fn declare_local(&mut self, name: &str, span: Span, variable: String) {
+        let index = self.resolution.locals.len();
+        self.resolution.locals.push(Local { name: name.to_string(), span, variable });
+        self.declare(name, span, Binding::Local(index));
+    }
+
+    /// Declare a local variable which isn't bound by `let`, renaming it if
+    /// it's only in scope in part of the function.
+    fn declare_renamed(&mut self, name: &mut String, span: Span) {
+        let variable = match self.scopes.len() {
+            1 => name.clone(),
+            _ => unique_variable(name, span),
+        };
+        self.declare_local(name, span, variable.clone());
+        *name = variable;
+    }
+
+    /// Record what a name refers to, renaming it after the variable.
+    fn refer(&mut self, name: &mut String, span: Span, binding: Binding) {
+        self.resolution.uses.push((span, binding));
+        if let Binding::Local(i) = binding {
+            name.clone_from(&self.resolution.locals[i].variable);
+        }
+    }
+
+    fn unbound(&mut self, message: String, span: Span) {
+        self.resolution.diagnostics.push(Diagnostic::error(message, span).with_code(ErrorCode::UnboundName));
+    }
+
+    fn expression(&mut self, x: &mut Exp) {
+        match x {
+            Exp::Symbol(name, span) => self.variable(name, *span),
+            Exp::List(items, _) => items.iter_mut().for_each(|i| self.expression(i)),
+            Exp::SExp { kind, args, kwargs, .. } => {
+                match kind.as_mut() {
+                    Exp::Symbol(name, span) => self.form(name, *span, args),
+                    kind => {
+                        self.expression(kind);
+                        args.iter_mut().for_each(|a| self.expression(a));
+                    }
+                }
+                kwargs.iter_mut().for_each(|(_, v)| self.expression(v));
+            }
+            _ => {}
+        }
+    }
+
+    fn variable(&mut self, name: &mut String, span: Span) {
+        if let Some(data) = name.strip_prefix('&') {
+            if (self.is_global)(data) {
+                self.resolution.uses.push((span, Binding::Global));
+            } else {
+                self.unbound(format!("Unknown data object '{data}'"), span);
+            }
+            return;
+        }
+        match self.lookup(name) {
+            Some((_, binding)) => self.refer(name, span, binding),
+            // A function used as a value
+            None if (self.is_global)(name) => self.resolution.uses.push((span, Binding::Global)),
+            None => self.unbound(format!("Unbound variable '{name}'"), span),
+        }
+    }
+
+    /// A function written inside the function, with a scope of its own.
+    fn function(&mut self, params: &mut [Exp], body: &mut [Exp]) {
+        self.scopes.push(Vec::new());
+        for param in params {
+            match param {
+                Exp::Symbol(name, span) => self.declare_renamed(name, *span),
+                other => self.expression(other),
+            }
+        }
+        body.iter_mut().for_each(|x| self.expression(x));
+        self.scopes.pop();
+    }
+
+    fn form(&mut self, name: &mut String, span: Span, args: &mut [Exp]) {
+        if !is_special_form(name) {
+            // A variable holding a function, or a global function
+            match self.lookup(name) {
+                Some((_, binding)) => self.refer(name, span, binding),
+                None if (self.is_global)(name) => self.resolution.uses.push((span, Binding::Global)),
+                None => self.unbound(format!("Unknown function '{name}'"), span),
+            }
+            args.iter_mut().for_each(|a| self.expression(a));
+            return;
+        }
+        self.resolution.uses.push((span, Binding::Builtin));
+        match (name.as_str(), args) {
+            ("quote" | "quasiquote", _) => {}
+            ("set", [Exp::Symbol(target, target_span), value]) => {
+                self.expression(value);
+                match self.lookup(target) {
+                    Some((_, binding)) => self.refer(target, *target_span, binding),
+                    None => self.declare_renamed(target, *target_span),
+                }
+            }
+            ("let", [Exp::List(bindings, _), body @ ..]) if bindings.len() % 2 == 0 => {
+                // The values are resolved before any of the names are bound
+                for pair in bindings.chunks_mut(2) {
+                    self.expression(&mut pair[1]);
+                }
+                self.scopes.push(Vec::new());
+                for pair in bindings.chunks_mut(2) {
+                    match &mut pair[0] {
+                        Exp::Symbol(name, span) => self.declare_local(name, *span, unique_variable(name, *span)),
+                        other => self.expression(other),
+                    }
+                }
+                body.iter_mut().for_each(|x| self.expression(x));
+                self.scopes.pop();
+            }
+            ("let-values", [Exp::List(bindings, _), body @ ..]) if bindings.len() % 2 == 0 => {
+                for pair in bindings.chunks_mut(2) {
+                    self.expression(&mut pair[1]);
+                }
+                self.scopes.push(Vec::new());
+                for pair in bindings.chunks_mut(2) {
+                    let Exp::List(names, _) = &mut pair[0] else {
+                        continue;
+                    };
+                    for name in names {
+                        match name {
+                            Exp::Symbol(name, span) => self.declare_local(name, *span, unique_variable(name, *span)),
+                            other => self.expression(other),
+                        }
+                    }
+                }
+                body.iter_mut().for_each(|x| self.expression(x));
+                self.scopes.pop();
+            }
+            ("fn", [Exp::List(params, _), body @ ..]) => self.function(params, body),
+            // A named function is in scope in its own body, so it can call
+            // itself
+            ("fn", [Exp::Symbol(name, name_span), Exp::List(params, _), _, body @ ..]) => {
+                self.declare_renamed(name, *name_span);
+                self.function(params, body);
+            }
+            ("def", [Exp::Symbol(name, name_span), value]) => {
+                if value.car_symbol().as_deref() == Some("fn") {
+                    self.declare_renamed(name, *name_span);
+                    self.expression(value);
+                } else {
+                    self.expression(value);
+                    self.declare_renamed(name, *name_span);
+                }
+            }
+            ("def", [Exp::SExp { kind, args: params, .. }, body @ ..]) => {
+                if let Exp::Symbol(name, name_span) = kind.as_mut() {
+                    self.declare_renamed(name, *name_span);
+                }
+                self.function(params, body);
+            }
+            (_, args) => args.iter_mut().for_each(|a| self.expression(a)),
+        }
+    }
+}
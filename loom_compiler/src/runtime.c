@@ -0,0 +1,54 @@
+/*
+ * The runtime of Loom programs which are compiled ahead of time, see
+ * `object.rs`. It starts the program, prints the result of its main
+ * function the way `loom run` does, and stops it if it runs into an error. Everything else compiled code needs, like
+ * `malloc`, comes from the C library.
+ */
+
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Calls the program's main function, and prints its result */
+void loom_main(void);
+
+/* Called by compiled code which divides by zero */
+void loom_division_by_zero(void) {
+    fflush(stdout);
+    fputs("error: Division by zero\n", stderr);
+    exit(1);
+}
+
+void loom_print_int(int64_t x) {
+    printf("%" PRId64, x);
+}
+
+void loom_print_bool(int64_t x) {
+    fputs(x ? "true" : "false", stdout);
+}
+
+/* The shortest form which reads back as the same float, which is always
+ * written with a point or an exponent, so it doesn't look like an integer */
+void loom_print_float(double x) {
+    char text[32];
+    for (int precision = 1; precision <= 17; precision++) {
+        snprintf(text, sizeof text, "%.*g", precision, x);
+        if (strtod(text, NULL) == x) {
+            break;
+        }
+    }
+    if (strspn(text, "-0123456789") == strlen(text)) {
+        strcat(text, ".0");
+    }
+    fputs(text, stdout);
+}
+
+void loom_print_text(const char *text) {
+    fputs(text, stdout);
+}
+
+int main(void) {
+    loom_main();
+    return 0;
+}
@@ -0,0 +1,106 @@
+//! Tail calls in compiled functions.
+//!
+//! Calls in tail position don't grow the stack, however deep the recursion
+//! goes. A function which calls itself in tail position jumps back to its
+//! start instead, with the arguments as its new parameters.
+//!
+//! Any other tail call to a function of the module, or to a function value,
+//! bounces: the function stores the callee and the arguments in a buffer of
+//! the module, and returns. Whoever called it through a trampoline then makes
+//! the call, and so on until a function returns without bouncing. Closures
+//! are always called through a trampoline, and so are functions which bounce,
+//! by the function of the module which has their name, which wraps them.
+//!
+//! Calls of functions which return several values don't bounce, since the
+//! trampoline only passes a single value on. They're ordinary calls, unless
+//! the function calls itself.
+//!
+//! Since there's a single buffer, compiled code can only run on one thread at
+//! a time.
+
+use crate::frontend::Expr;
+use crate::types::Type;
+
+/// How many arguments a call can take and still bounce. Calls with more
+/// arguments are ordinary calls, even in tail position.
+pub const MAX_ARGS: usize = 16;
+
+/// Mark the calls in tail position of a function's body with `Expr::Tail`,
+/// and those of every function written in it, given the function's name and
+/// whether a name is a function of the module.
+///
+/// Returns whether the function's own body bounces.
+pub fn mark(this: &str, body: &mut [Expr], is_defined: &dyn Fn(&str) -> bool) -> bool {
+    let mut marker = Marker { this, is_defined, bounces: false };
+    if let Some(last) = body.last_mut() {
+        marker.tail(last, true);
+    }
+    for x in body.iter_mut() {
+        marker.lambdas(x);
+    }
+    marker.bounces
+}
+
+struct Marker<'a> {
+    this: &'a str,
+    is_defined: &'a dyn Fn(&str) -> bool,
+    /// Whether a tail call of the function's own body bounces
+    bounces: bool,
+}
+
+impl Marker<'_> {
+    /// Mark an expression in tail position, of the function's own body or of
+    /// a function written in it.
+    fn tail(&mut self, x: &mut Expr, own: bool) {
+        match x {
+            Expr::Located(_, inner) => self.tail(inner, own),
+            Expr::Sequence(body) => {
+                if let Some(last) = body.last_mut() {
+                    self.tail(last, own);
+                }
+            }
+            // Without an else branch, the value of an `if` isn't the value
+            // of its body
+            Expr::IfElse(_, then_body, else_body) if !else_body.is_empty() => {
+                for body in [then_body, else_body] {
+                    if let Some(last) = body.last_mut() {
+                        self.tail(last, own);
+                    }
+                }
+            }
+            Expr::Typed(ty, call) => {
+                let marked = match call.as_ref() {
+                    // Becomes a jump back to the start of the function
+                    Expr::Call(name, ..) if own && name == self.this => true,
+                    _ if matches!(ty, Type::Values(_)) => false,
+                    Expr::Call(name, args, _) if (self.is_defined)(name) && args.len() <= MAX_ARGS => {
+                        self.bounces |= own;
+                        true
+                    }
+                    Expr::CallValue(_, args) if args.len() <= MAX_ARGS => {
+                        self.bounces |= own;
+                        true
+                    }
+                    _ => false,
+                };
+                if marked {
+                    let inner = std::mem::replace(call.as_mut(), Expr::Sequence(Vec::new()));
+                    **call = Expr::Tail(Box::new(inner));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Mark the tail calls of every function written in an expression.
+    fn lambdas(&mut self, x: &mut Expr) {
+        if let Expr::Lambda(lambda) = x {
+            if let Some(last) = lambda.body.last_mut() {
+                self.tail(last, false);
+            }
+        }
+        for child in x.children_mut() {
+            self.lambdas(child);
+        }
+    }
+}
@@ -0,0 +1,823 @@
+//! Static types for compiled functions, inferred Hindley-Milner style.
+//!
+//! The types of a function's parameters, variables and result are inferred
+//! from how they're used, and functions are generalized, so a function which
+//! doesn't care about the type of a parameter can be called with any type.
+//! A function which does arithmetic on a parameter, and nothing else which
+//! decides its type, is generic over numbers: its own code treats them as
+//! ints, and it's compiled again for each other type of number it's called
+//! with, see `check_specialization`. Every function is checked before any
+//! Cranelift IR is generated for it.
+//!
+//! Types are written like so in declarations:
+//!
+//! ```text
+//! int float f32 bool str nil    ; primitives, where float is 64 bits
+//! [int]                          ; an array of ints
+//! (fn [int str] bool)            ; a function
+//! window                         ; a type declared with (deftype window)
+//! ```
+//!
+//! User-defined types are opaque to compiled code. Their values come from
+//! host functions, whose types are declared with `(extern name [params] result)`.
+//!
+//! Functions written inside a function aren't generalized: each of them has a
+//! single type, which the function they're written in decides.
+//!
+//! Only functions defined at the top level can return several values, like
+//! `(values int float)`, which are received with `let-values`. Everything
+//! else, like variables, arguments and the parameters of generic functions,
+//! holds a single value.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use loom_reader::diagnostics::{Diagnostic, ErrorCode};
+use loom_reader::parse::{Exp, Span};
+use crate::frontend::{Expr, Number};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    /// A 64-bit float
+    Float,
+    F32,
+    Bool,
+    Str,
+    /// The type of expressions without a useful value, like loops
+    Nil,
+    Array(Box<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    /// Several values at once, as a function returns them
+    Values(Vec<Type>),
+    /// A type declared with `deftype`
+    Named(String),
+    /// A type which isn't known yet, or a parameter of a generic function
+    Var(usize),
+}
+
+impl Type {
+    fn vars(&self, out: &mut Vec<usize>) {
+        match self {
+            Type::Var(v) if !out.contains(v) => out.push(*v),
+            Type::Array(t) => t.vars(out),
+            Type::Fn(params, result) => {
+                params.iter().for_each(|p| p.vars(out));
+                result.vars(out);
+            }
+            Type::Values(types) => types.iter().for_each(|t| t.vars(out)),
+            _ => {}
+        }
+    }
+
+    fn map_vars(&self, f: &mut impl FnMut(usize) -> Type) -> Type {
+        match self {
+            Type::Var(v) => f(*v),
+            Type::Array(t) => Type::Array(Box::new(t.map_vars(f))),
+            Type::Fn(params, result) => {
+                Type::Fn(params.iter().map(|p| p.map_vars(f)).collect(), Box::new(result.map_vars(f)))
+            }
+            Type::Values(types) => Type::Values(types.iter().map(|t| t.map_vars(f)).collect()),
+            _ => self.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::F32 => write!(f, "f32"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Nil => write!(f, "nil"),
+            Type::Array(t) => write!(f, "[{t}]"),
+            Type::Fn(params, result) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "(fn [{}] {result})", params.join(" "))
+            }
+            Type::Values(types) => {
+                let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+                write!(f, "(values {})", types.join(" "))
+            }
+            Type::Named(name) => write!(f, "{name}"),
+            // 'a to 'z, then 'a1 and so on
+            Type::Var(v) => {
+                let letter = (b'a' + (v % 26) as u8) as char;
+                match v / 26 {
+                    0 => write!(f, "'{letter}"),
+                    n => write!(f, "'{letter}{n}"),
+                }
+            }
+        }
+    }
+}
+
+/// A type which may be generic: its variables are numbered from 0, and stand
+/// for any type, or any type of number for the ones in `numeric`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub vars: usize,
+    pub ty: Type,
+    /// The variables which stand for numbers, in increasing order
+    pub numeric: Vec<usize>,
+}
+
+impl Scheme {
+    /// A type which isn't generic
+    pub fn mono(ty: Type) -> Self {
+        Self { vars: 0, ty, numeric: Vec::new() }
+    }
+
+    /// Generalize a type over every variable in it, given the variables
+    /// which must be numbers.
+    fn generalize(ty: &Type, numbers: &[usize]) -> Self {
+        let mut vars = Vec::new();
+        ty.vars(&mut vars);
+        let ty = ty.map_vars(&mut |v| Type::Var(vars.iter().position(|w| *w == v).unwrap()));
+        let mut numeric: Vec<usize> = numbers.iter().filter_map(|n| vars.iter().position(|v| v == n)).collect();
+        numeric.sort();
+        numeric.dedup();
+        Self { vars: vars.len(), ty, numeric }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ty)?;
+        let numbers: Vec<String> = self.numeric.iter().map(|v| Type::Var(*v).to_string()).collect();
+        match numbers.as_slice() {
+            [] => Ok(()),
+            [number] => write!(f, " where {number} is a number"),
+            _ => write!(f, " where {} are numbers", numbers.join(" ")),
+        }
+    }
+}
+
+/// The name of the copy of a function which is generic over numbers, for the
+/// given types of numbers, like `lerp[float]`. Names can't have brackets, so
+/// it can't be the name of anything else.
+pub fn specialization(name: &str, numbers: &[Type]) -> String {
+    let numbers: Vec<String> = numbers.iter().map(|t| t.to_string()).collect();
+    format!("{name}[{}]", numbers.join(" "))
+}
+
+/// The types found for a function
+#[derive(Debug, Clone)]
+pub struct FunctionTypes {
+    pub scheme: Scheme,
+    /// The type of every parameter and local variable, by name. Variables of
+    /// generic types have type variables.
+    pub variables: HashMap<String, Type>,
+    /// The functions generic over numbers which the function calls with
+    /// other numbers than ints, with the types of those numbers. The calls
+    /// are calls of their copies for those numbers, see `specialization`.
+    pub specializations: Vec<(String, Vec<Type>)>,
+}
+
+/// The types of everything compiled functions can refer to
+#[derive(Debug, Clone, Default)]
+pub struct TypeEnv {
+    functions: HashMap<String, Scheme>,
+    /// The names of user-defined types
+    types: HashSet<String>,
+}
+
+impl TypeEnv {
+    /// Give a function a type, replacing any it had before.
+    pub fn define(&mut self, name: &str, scheme: Scheme) {
+        self.functions.insert(name.to_string(), scheme);
+    }
+
+    /// The type of a function, if it's known
+    pub fn function(&self, name: &str) -> Option<&Scheme> {
+        self.functions.get(name)
+    }
+
+    /// Handle a `(deftype name)` or `(extern name [params] result)`
+    /// declaration, returning the name it declares.
+    pub fn declare(&mut self, x: &Exp) -> Result<String, Diagnostic> {
+        let span = x.span();
+        let invalid = |message: &str| Diagnostic::error(message.to_string(), span).with_code(ErrorCode::InvalidForm);
+        match x.car_symbol().as_deref() {
+            Some("deftype") => {
+                let (Some(name), None) = (x.arg_symbol(0), x.arg(1)) else {
+                    return Err(invalid("'deftype' expects the name of the type"));
+                };
+                if self.parse(&Exp::Symbol(name.clone(), span)).is_ok() {
+                    return Err(Diagnostic::error(format!("Type '{name}' is already defined"), span)
+                                   .with_code(ErrorCode::InvalidType));
+                }
+                self.types.insert(name.clone());
+                Ok(name)
+            }
+            Some("extern") => {
+                let (Some(name), Some(Exp::List(params, _)), Some(result), None) =
+                    (x.arg_symbol(0), x.arg(1), x.arg(2), x.arg(3)) else {
+                    return Err(invalid("'extern' expects a name, a list of parameter types and a result type"));
+                };
+                let params = params.iter().map(|p| self.parse(p)).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                let ty = Type::Fn(params, Box::new(self.parse(&result)?));
+                self.define(&name, Scheme::mono(ty));
+                Ok(name)
+            }
+            _ => Err(invalid(&format!("Expected a type declaration, found {x}"))),
+        }
+    }
+
+    /// Read a type as it's written in declarations.
+    pub fn parse(&self, x: &Exp) -> Result<Type, Diagnostic> {
+        let invalid = |message: String| Diagnostic::error(message, x.span()).with_code(ErrorCode::InvalidType);
+        match x {
+            Exp::Symbol(name, _) => match name.as_str() {
+                "int" => Ok(Type::Int),
+                "float" | "f64" => Ok(Type::Float),
+                "f32" => Ok(Type::F32),
+                "bool" => Ok(Type::Bool),
+                "str" => Ok(Type::Str),
+                "nil" => Ok(Type::Nil),
+                _ if self.types.contains(name) => Ok(Type::Named(name.clone())),
+                _ => Err(invalid(format!("Unknown type '{name}'"))
+                             .with_help(format!("declare it with `(deftype {name})` if it comes from the host"))),
+            },
+            Exp::Nil(_) => Ok(Type::Nil),
+            Exp::List(items, _) => match items.as_slice() {
+                [item] => Ok(Type::Array(Box::new(self.parse(item)?))),
+                _ => Err(invalid("An array type has a single element type, like [int]".to_string())),
+            },
+            Exp::SExp { args, .. } if x.car_symbol().as_deref() == Some("fn") => match args.as_slice() {
+                [Exp::List(params, _), result] => {
+                    let params = params.iter().map(|p| self.parse(p)).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                    Ok(Type::Fn(params, Box::new(self.parse(result)?)))
+                }
+                _ => Err(invalid("A function type has parameter types and a result type, like (fn [int] int)".to_string())),
+            },
+            _ => Err(invalid(format!("Expected a type, found {x}"))),
+        }
+    }
+
+    /// Infer the type of a function, given its parameters and body. The
+    /// function can call itself, and any function with a known type.
+    ///
+    /// Calls and array elements in the body are wrapped in `Expr::Typed`, so
+    /// that the backend knows what they produce. The variables of functions
+    /// written in the body are typed along with the function's own.
+    pub fn check_function(&self, name: &str, params: &[String], body: &mut [Expr]) -> Result<FunctionTypes, Diagnostic> {
+        let mut types = self.check_functions(&mut [(name, params, body)])?;
+        Ok(types.remove(0))
+    }
+
+    /// Infer the types of functions which call each other, given their names,
+    /// parameters and bodies, like `check_function` does for one function.
+    ///
+    /// Each function has a single type while the group is checked, so they
+    /// call each other with the same types, and they're generalized once the
+    /// whole group is known.
+    pub fn check_functions(&self, functions: &mut [(&str, &[String], &mut [Expr])]) -> Result<Vec<FunctionTypes>, Diagnostic> {
+        let mut checker = Checker::new(self);
+        let mut signatures = Vec::new();
+        for (name, params, _) in functions.iter() {
+            let params: Vec<Type> = params.iter().map(|_| checker.fresh()).collect();
+            let result = checker.fresh();
+            checker.functions.insert(name.to_string(), Type::Fn(params.clone(), Box::new(result.clone())));
+            signatures.push((params, result));
+        }
+        checker.check(functions, signatures)
+    }
+
+    /// Infer the types of the copy of a function which is generic over
+    /// numbers, given the function's parameters and body, its type, and the
+    /// types of its numbers, in the order of `scheme.numeric`.
+    ///
+    /// The function's calls of itself are calls of a copy too, which is this
+    /// one unless it calls itself with other numbers.
+    pub fn check_specialization(
+        &self,
+        params: &[String],
+        body: &mut [Expr],
+        scheme: &Scheme,
+        numbers: &[Type],
+    ) -> Result<FunctionTypes, Diagnostic> {
+        let mut checker = Checker::new(self);
+        let vars: Vec<Type> = (0..scheme.vars).map(|v| match scheme.numeric.iter().position(|n| *n == v) {
+            Some(i) => numbers[i].clone(),
+            None => checker.fresh(),
+        }).collect();
+        let Type::Fn(param_types, result) = scheme.ty.map_vars(&mut |v| vars[v].clone()) else {
+            return Err(checker.error(format!("{scheme} isn't a function type")));
+        };
+        let mut types = checker.check(&mut [("", params, body)], vec![(param_types, *result)])?;
+        Ok(types.remove(0))
+    }
+}
+
+/// Split functions into the groups which are checked together, given the
+/// functions each of them refers to, by index. Functions which refer to each
+/// other, directly or not, are in the same group, and every group comes after
+/// the groups it refers to.
+pub fn binding_groups(references: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    // Tarjan's algorithm, which finds each group after the ones it refers to
+    struct Search<'a> {
+        references: &'a [Vec<usize>],
+        /// The order each function is visited in, once it is
+        index: Vec<Option<usize>>,
+        visited: usize,
+        lowlink: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        groups: Vec<Vec<usize>>,
+    }
+    impl Search<'_> {
+        fn visit(&mut self, v: usize) {
+            let index = self.visited;
+            self.visited += 1;
+            self.index[v] = Some(index);
+            self.lowlink[v] = index;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.references[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.lowlink[v] = self.lowlink[v].min(index),
+                    Some(_) => {}
+                }
+            }
+            if self.lowlink[v] == index {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                group.sort();
+                self.groups.push(group);
+            }
+        }
+    }
+    let n = references.len();
+    let mut search = Search {
+        references,
+        index: vec![None; n],
+        visited: 0,
+        lowlink: vec![0; n],
+        stack: Vec::new(),
+        on_stack: vec![false; n],
+        groups: Vec::new(),
+    };
+    for v in 0..n {
+        if search.index[v].is_none() {
+            search.visit(v);
+        }
+    }
+    search.groups
+}
+
+/// The state of inference for a group of functions
+struct Checker<'a> {
+    env: &'a TypeEnv,
+    /// The functions being checked, and their types while they're inferred
+    functions: HashMap<String, Type>,
+    /// What each type variable turned out to be, if anything yet
+    substitution: Vec<Option<Type>>,
+    variables: HashMap<String, Type>,
+    /// Types which must be numbers, with where that's required
+    numeric: Vec<(Type, Option<Span>)>,
+    /// Types which must be a single value, with where that's required
+    single: Vec<(Type, Option<Span>)>,
+    /// The types of the numbers of the function generic over numbers which
+    /// the expression being checked uses, if it's one, see `Expr::Instance`
+    instance: Vec<Type>,
+    /// The innermost form being checked
+    span: Option<Span>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(env: &'a TypeEnv) -> Self {
+        Self {
+            env,
+            functions: HashMap::new(),
+            substitution: Vec::new(),
+            variables: HashMap::new(),
+            numeric: Vec::new(),
+            single: Vec::new(),
+            instance: Vec::new(),
+            span: None,
+        }
+    }
+
+    /// Check the bodies of functions, given the types of their parameters
+    /// and results, and find out what the types of everything in them are.
+    fn check(
+        &mut self,
+        functions: &mut [(&str, &[String], &mut [Expr])],
+        signatures: Vec<(Vec<Type>, Type)>,
+    ) -> Result<Vec<FunctionTypes>, Diagnostic> {
+        let mut variables = Vec::new();
+        for ((_, params, body), (param_types, result)) in functions.iter_mut().zip(&signatures) {
+            for (param, ty) in params.iter().zip(param_types) {
+                self.variables.insert(param.clone(), ty.clone());
+                self.single(ty.clone());
+            }
+            let found = self.body(body)?;
+            self.unify(result, &found)?;
+            variables.push(std::mem::take(&mut self.variables));
+        }
+
+        // Numbers whose type isn't known yet are generic in the types of the
+        // functions, before they're ints in their own code
+        let numbers: Vec<usize> = self.numeric.iter().filter_map(|(ty, _)| match self.apply(ty) {
+            Type::Var(v) => Some(v),
+            _ => None,
+        }).collect();
+        let schemes: Vec<Scheme> = signatures.into_iter().map(|(params, result)| {
+            Scheme::generalize(&self.apply(&Type::Fn(params, Box::new(result))), &numbers)
+        }).collect();
+        self.default_numbers()?;
+        self.single_values()?;
+
+        let types = functions.iter_mut().zip(variables).zip(schemes).map(|(((_, _, body), variables), scheme)| {
+            let mut specializations = Vec::new();
+            for x in body.iter_mut() {
+                self.apply_annotations(x, &mut specializations);
+            }
+            let variables = variables.iter().map(|(k, t)| (k.clone(), self.apply(t))).collect();
+            FunctionTypes { scheme, variables, specializations }
+        });
+        Ok(types.collect())
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() - 1)
+    }
+
+    /// Substitute every type variable which is known.
+    fn apply(&self, ty: &Type) -> Type {
+        ty.map_vars(&mut |v| match &self.substitution[v] {
+            Some(t) => self.apply(t),
+            None => Type::Var(v),
+        })
+    }
+
+    /// A type error in the innermost form being checked
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::error(message, self.span.unwrap_or_default()).with_code(ErrorCode::TypeMismatch)
+    }
+
+    fn unify(&mut self, expected: &Type, found: &Type) -> Result<(), Diagnostic> {
+        let (expected, found) = (self.apply(expected), self.apply(found));
+        self.unify_applied(&expected, &found)
+            .map_err(|()| self.error(format!("Expected {expected}, found {found}")))
+    }
+
+    fn unify_applied(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        match (a, b) {
+            (Type::Var(v), Type::Var(w)) if v == w => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => {
+                let t = self.apply(t);
+                let mut vars = Vec::new();
+                t.vars(&mut vars);
+                // A type can't contain itself
+                if vars.contains(v) {
+                    return Err(());
+                }
+                self.substitution[*v] = Some(t);
+                Ok(())
+            }
+            (Type::Array(a), Type::Array(b)) => {
+                let (a, b) = (self.apply(a), self.apply(b));
+                self.unify_applied(&a, &b)
+            }
+            (Type::Values(a), Type::Values(b)) => {
+                if a.len() != b.len() {
+                    return Err(());
+                }
+                for (a, b) in a.iter().zip(b) {
+                    let (a, b) = (self.apply(a), self.apply(b));
+                    self.unify_applied(&a, &b)?;
+                }
+                Ok(())
+            }
+            (Type::Fn(a_params, a_result), Type::Fn(b_params, b_result)) => {
+                if a_params.len() != b_params.len() {
+                    return Err(());
+                }
+                for (a, b) in a_params.iter().zip(b_params).chain([(a_result.as_ref(), b_result.as_ref())]) {
+                    let (a, b) = (self.apply(a), self.apply(b));
+                    self.unify_applied(&a, &b)?;
+                }
+                Ok(())
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// A copy of a generic type, with new variables for its parameters. The
+    /// variables which stand for numbers must be numbers, and they're the
+    /// instance of the expression being checked.
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let vars: Vec<Type> = (0..scheme.vars).map(|_| self.fresh()).collect();
+        vars.iter().for_each(|v| self.single(v.clone()));
+        let numbers: Vec<Type> = scheme.numeric.iter().map(|v| vars[*v].clone()).collect();
+        numbers.iter().for_each(|v| self.number(v.clone()));
+        self.instance = numbers;
+        scheme.ty.map_vars(&mut |v| vars[v].clone())
+    }
+
+    /// Require a type to be a number, once the whole function is known.
+    fn number(&mut self, ty: Type) {
+        self.numeric.push((ty, self.span));
+    }
+
+    /// Numbers whose type isn't known by the end of a function are ints in its
+    /// own code.
+    fn default_numbers(&mut self) -> Result<(), Diagnostic> {
+        for (ty, span) in std::mem::take(&mut self.numeric) {
+            self.span = span;
+            match self.apply(&ty) {
+                Type::Int | Type::Float | Type::F32 => {}
+                Type::Var(_) => self.unify(&Type::Int, &ty)?,
+                other => return Err(self.error(format!("Expected a number, found {other}"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Require a type to be a single value, once the whole function is known.
+    fn single(&mut self, ty: Type) {
+        self.single.push((ty, self.span));
+    }
+
+    /// Several values can only be returned by a function and received with
+    /// `let-values`, and nothing else can hold them.
+    fn single_values(&mut self) -> Result<(), Diagnostic> {
+        for (ty, span) in std::mem::take(&mut self.single) {
+            self.span = span;
+            let ty = self.apply(&ty);
+            if let Type::Values(_) = ty {
+                return Err(self.error(format!("Expected a single value, found {ty}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// The type of a variable, which is new if the variable is.
+    fn variable(&mut self, name: &str) -> Type {
+        if let Some(ty) = self.variables.get(name) {
+            return ty.clone();
+        }
+        let ty = self.fresh();
+        self.variables.insert(name.to_string(), ty.clone());
+        ty
+    }
+
+    /// Substitute what's known into the types of annotated expressions, and
+    /// make the uses of functions generic over numbers uses of their copies
+    /// for other numbers than ints, which are added to `specializations`.
+    fn apply_annotations(&self, x: &mut Expr, specializations: &mut Vec<(String, Vec<Type>)>) {
+        if let Expr::Instance(numbers, inner) = x {
+            let numbers: Vec<Type> = numbers.iter().map(|n| self.apply(n)).collect();
+            let mut inner = std::mem::replace(inner.as_mut(), Expr::Sequence(Vec::new()));
+            if let Some(name) = used_function(&mut inner).filter(|_| numbers.iter().any(|n| *n != Type::Int)) {
+                specializations.push((name.clone(), numbers.clone()));
+                *name = specialization(name, &numbers);
+            }
+            *x = inner;
+        }
+        if let Expr::Typed(ty, _) = x {
+            *ty = self.apply(ty);
+        }
+        for child in x.children_mut() {
+            self.apply_annotations(child, specializations);
+        }
+    }
+
+    /// The type of the last expression of a body, or nil if it's empty.
+    fn body(&mut self, body: &mut [Expr]) -> Result<Type, Diagnostic> {
+        let mut ty = Type::Nil;
+        for x in body {
+            ty = self.infer(x)?;
+        }
+        Ok(ty)
+    }
+
+    fn infer(&mut self, x: &mut Expr) -> Result<Type, Diagnostic> {
+        let ty = self.infer_inner(x)?;
+        if matches!(x, Expr::Call(..) | Expr::CallValue(..) | Expr::GetArrayElem(..)) {
+            let inner = std::mem::replace(x, Expr::Sequence(Vec::new()));
+            *x = Expr::Typed(ty.clone(), Box::new(inner));
+        }
+        let numbers = std::mem::take(&mut self.instance);
+        if !numbers.is_empty() {
+            let inner = std::mem::replace(x, Expr::Sequence(Vec::new()));
+            *x = Expr::Instance(numbers, Box::new(inner));
+        }
+        Ok(ty)
+    }
+
+    fn infer_inner(&mut self, x: &mut Expr) -> Result<Type, Diagnostic> {
+        match x {
+            Expr::Literal(_) => Ok(Type::Int),
+            Expr::Float(_) => Ok(Type::Float),
+            Expr::Bool(_) => Ok(Type::Bool),
+            Expr::Str(_) => Ok(Type::Str),
+            // Data objects are null-terminated strings, like string literals
+            Expr::GlobalDataAddr(_) => Ok(Type::Str),
+            Expr::Identifier(name) => {
+                if let Some(ty) = self.variables.get(name) {
+                    return Ok(ty.clone());
+                }
+                // A function used as a value
+                if let Some(ty) = self.functions.get(name) {
+                    return Ok(ty.clone());
+                }
+                match self.env.function(name) {
+                    Some(scheme) => Ok(self.instantiate(scheme)),
+                    None => Err(self.error(format!(
+                        "The type of '{name}' isn't known, so it can't be used as a value"
+                    )).with_help(format!("declare it with `(extern {name} [params] result)`"))),
+                }
+            }
+            Expr::Assign(name, value) => {
+                // The variable is known while its value is checked, so that
+                // a function assigned to it can call itself
+                let existing = self.variable(name);
+                let ty = self.infer(value)?;
+                self.unify(&existing, &ty)?;
+                self.single(ty.clone());
+                Ok(ty)
+            }
+            Expr::AssignValues(names, value) => {
+                let types: Vec<Type> = names.iter().map(|name| self.variable(name)).collect();
+                let found = self.infer(value)?;
+                let expected = match types.as_slice() {
+                    [ty] => ty.clone(),
+                    _ => Type::Values(types.clone()),
+                };
+                self.unify(&expected, &found)?;
+                types.into_iter().for_each(|ty| self.single(ty));
+                Ok(Type::Nil)
+            }
+            Expr::Values(values) => {
+                let types = values.iter_mut().map(|v| self.infer(v)).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                types.iter().for_each(|ty| self.single(ty.clone()));
+                Ok(Type::Values(types))
+            }
+            Expr::Lambda(lambda) => {
+                let params: Vec<Type> = lambda.params.iter().map(|_| self.fresh()).collect();
+                for (param, ty) in lambda.params.iter().zip(&params) {
+                    self.variables.insert(param.clone(), ty.clone());
+                    self.single(ty.clone());
+                }
+                // Closures return a single value, see `closures`
+                let result = self.body(&mut lambda.body)?;
+                self.single(result.clone());
+                Ok(Type::Fn(params, Box::new(result)))
+            }
+            Expr::CallValue(callee, args) => {
+                let callee = self.infer(callee)?;
+                let args = args.iter_mut().map(|a| self.infer(a)).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                args.iter().for_each(|ty| self.single(ty.clone()));
+                let result = self.fresh();
+                self.single(result.clone());
+                self.unify(&Type::Fn(args, Box::new(result.clone())), &callee)?;
+                Ok(result)
+            }
+            Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) | Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs) => {
+                let ty = self.infer(lhs)?;
+                let rhs = self.infer(rhs)?;
+                self.unify(&ty, &rhs)?;
+                self.number(ty.clone());
+                Ok(ty)
+            }
+            Expr::Neg(x) => {
+                let ty = self.infer(x)?;
+                self.number(ty.clone());
+                Ok(ty)
+            }
+            Expr::Modulo(lhs, rhs) => {
+                for x in [lhs, rhs] {
+                    let ty = self.infer(x)?;
+                    self.unify(&Type::Int, &ty)?;
+                }
+                Ok(Type::Int)
+            }
+            Expr::Eq(lhs, rhs) | Expr::Ne(lhs, rhs) => {
+                let ty = self.infer(lhs)?;
+                let rhs = self.infer(rhs)?;
+                self.unify(&ty, &rhs)?;
+                self.single(ty);
+                Ok(Type::Bool)
+            }
+            Expr::Lt(lhs, rhs) | Expr::Le(lhs, rhs) | Expr::Gt(lhs, rhs) | Expr::Ge(lhs, rhs) => {
+                let ty = self.infer(lhs)?;
+                let rhs = self.infer(rhs)?;
+                self.unify(&ty, &rhs)?;
+                self.number(ty);
+                Ok(Type::Bool)
+            }
+            Expr::IfElse(condition, then_body, else_body) => {
+                let condition = self.infer(condition)?;
+                self.unify(&Type::Bool, &condition)?;
+                let then_type = self.body(then_body)?;
+                if else_body.is_empty() {
+                    // Without an else branch, there's no value to speak of
+                    return Ok(Type::Nil);
+                }
+                let else_type = self.body(else_body)?;
+                self.unify(&then_type, &else_type)?;
+                Ok(then_type)
+            }
+            Expr::WhileLoop(condition, body) => {
+                let condition = self.infer(condition)?;
+                self.unify(&Type::Bool, &condition)?;
+                self.body(body)?;
+                Ok(Type::Nil)
+            }
+            Expr::Call(name, args, _) => {
+                let callee = match self.functions.get(name) {
+                    Some(ty) => Some(ty.clone()),
+                    None => self.env.function(name).map(|scheme| self.instantiate(scheme)),
+                };
+                // The arguments are checked on their own
+                let instance = std::mem::take(&mut self.instance);
+                let args = args.iter_mut().map(|a| self.infer(a)).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                self.instance = instance;
+                args.iter().for_each(|ty| self.single(ty.clone()));
+                let Some(callee) = callee else {
+                    // Host functions without a declared type take anything,
+                    // and return a pointer-sized integer
+                    return Ok(Type::Int);
+                };
+                let Type::Fn(params, result) = self.apply(&callee) else {
+                    return Err(self.error(format!("'{name}' isn't a function")));
+                };
+                if params.len() != args.len() {
+                    return Err(self.error(format!("'{name}' expects {} arguments, got {}", params.len(), args.len())));
+                }
+                for (param, arg) in params.iter().zip(&args) {
+                    self.unify(param, arg)?;
+                }
+                Ok(*result)
+            }
+            Expr::Sequence(body) => self.body(body),
+            Expr::MakeArray(_) => {
+                let element = self.fresh();
+                self.single(element.clone());
+                Ok(Type::Array(Box::new(element)))
+            }
+            Expr::GetArrayElem(array, index) => {
+                let element = self.fresh();
+                let found = self.infer(array)?;
+                self.unify(&Type::Array(Box::new(element.clone())), &found)?;
+                let index = self.infer(index)?;
+                self.unify(&Type::Int, &index)?;
+                self.single(element.clone());
+                Ok(element)
+            }
+            Expr::SetArrayElem(array, index, value) => {
+                let element = self.fresh();
+                let found = self.infer(array)?;
+                self.unify(&Type::Array(Box::new(element.clone())), &found)?;
+                let index = self.infer(index)?;
+                self.unify(&Type::Int, &index)?;
+                let value = self.infer(value)?;
+                self.unify(&element, &value)?;
+                self.single(value);
+                Ok(Type::Nil)
+            }
+            Expr::Convert(number, x) => {
+                let ty = self.infer(x)?;
+                self.number(ty);
+                Ok(match number {
+                    Number::Int => Type::Int,
+                    Number::F32 => Type::F32,
+                    Number::F64 => Type::Float,
+                })
+            }
+            Expr::Located(span, x) => {
+                let outer = self.span.replace(*span);
+                let ty = self.infer(x)?;
+                self.span = outer;
+                Ok(ty)
+            }
+            Expr::Typed(_, x) | Expr::Tail(x) | Expr::Instance(_, x) => self.infer_inner(x),
+        }
+    }
+}
+
+/// The name of the function a call or a function value uses, see
+/// `Expr::Instance`.
+fn used_function(x: &mut Expr) -> Option<&mut String> {
+    match x {
+        Expr::Call(name, ..) | Expr::Identifier(name) => Some(name),
+        Expr::Typed(_, x) => used_function(x),
+        _ => None,
+    }
+}
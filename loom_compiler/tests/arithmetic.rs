@@ -0,0 +1,72 @@
+use core::mem;
+use loom_compiler::jit::{JIT, take_runtime_error};
+
+/// Compile a function of two integers, and call it with each pair of
+/// arguments, returning its results, or the error it ran into.
+fn call(code: &str, args: &[(i64, i64)]) -> Vec<Result<i64, String>> {
+    let mut jit = JIT::default();
+    let code = jit.compile(code).unwrap_or_else(|e| panic!("{e}"));
+    let f = unsafe { mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(code) };
+    args.iter().map(|(a, b)| {
+        let result = f(*a, *b);
+        take_runtime_error().map_or(Ok(result), Err)
+    }).collect()
+}
+
+#[test]
+fn division_by_zero_is_an_error() {
+    let division = "(fn divide [a b] [] (/ a b))";
+    assert_eq!(call(division, &[(7, 2), (7, 0), (-7, 2)]), vec![
+        Ok(3), Err("Division by zero".to_string()), Ok(-3),
+    ]);
+    let remainder = "(fn remainder [a b] [] (% a b))";
+    assert_eq!(call(remainder, &[(7, 0), (-7, 2)]), vec![Err("Division by zero".to_string()), Ok(-1)]);
+    // Only the first error is kept, and taking it clears it
+    let twice = "(fn twice [a b] [] (+ (/ a b) (% a b)))";
+    assert_eq!(call(twice, &[(1, 0), (1, 1)]), vec![Err("Division by zero".to_string()), Ok(1)]);
+}
+
+#[test]
+fn dividing_by_minus_one_wraps_around() {
+    let both = "(fn both [a b] [] (+ (* (/ a b) 10) (% a b)))";
+    assert_eq!(call(both, &[(7, -1), (-7, -1)]), vec![Ok(-70), Ok(70)]);
+    let division = "(fn divide [a b] [] (/ a b))";
+    assert_eq!(call(division, &[(i64::MIN, -1)]), vec![Ok(i64::MIN)]);
+    let remainder = "(fn remainder [a b] [] (% a b))";
+    assert_eq!(call(remainder, &[(i64::MIN, -1)]), vec![Ok(0)]);
+}
+
+/// Compile a function of a float, and return its code.
+fn compile_float(code: &str) -> extern "C" fn(f64) -> f64 {
+    let mut jit = JIT::default();
+    let code = jit.compile(code).unwrap_or_else(|e| panic!("{e}"));
+    unsafe { mem::transmute::<*const u8, extern "C" fn(f64) -> f64>(code) }
+}
+
+#[test]
+fn numbers_convert() {
+    // Floats to integers round towards zero, and saturate
+    let to_int = "(fn to_int [a b] [] (int (/ (float a) (float b))))";
+    assert_eq!(call(to_int, &[(29, 10), (-29, 10), (1, 0), (-1, 0)]), vec![
+        Ok(2), Ok(-2), Ok(i64::MAX), Ok(i64::MIN),
+    ]);
+    // Through single precision, which loses the low bits of large integers
+    let round_trip = "(fn round_trip [a b] [] (+ (int (f32 a)) (int (f64 (f32 b)))))";
+    assert_eq!(call(round_trip, &[(16777217, 0), (0, 3)]), vec![Ok(16777216), Ok(3)]);
+
+    let half = compile_float("(fn half [x] [] (* x 0.5))");
+    assert_eq!(half(3.0), 1.5);
+    let mut jit = JIT::default();
+    let single = jit.compile("(fn single [x] [] (- (* x (f32 0.5)) (f32 (f64 (f32 0.1)))))").unwrap();
+    let single = unsafe { mem::transmute::<*const u8, extern "C" fn(f32) -> f32>(single) };
+    assert_eq!(single(3.0), 1.5 - 0.1);
+    let mixed = compile_float("(fn mixed [x] [] (+ (- x) (float (int x)) (/ x 4.0)))");
+    assert_eq!(mixed(2.5), -2.5 + 2.0 + 0.625);
+}
+
+#[test]
+fn floats_and_integers_dont_mix() {
+    let mut jit = JIT::default();
+    let e = jit.compile("(fn mixed [x] [] (+ x 1.5 2))").unwrap_err();
+    assert!(e.to_string().ends_with("Expected float, found int"), "{e}");
+}
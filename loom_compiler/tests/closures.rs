@@ -0,0 +1,140 @@
+use core::mem;
+use std::collections::HashSet;
+use std::{env, fs, process};
+use loom_compiler::closures;
+use loom_compiler::frontend::{Expr, Lambda};
+use loom_compiler::jit::JIT;
+use loom_compiler::object::{ObjectCompiler, link};
+use loom_reader::parse::read_expressions;
+
+/// Programs whose `main` returns an integer, and what it returns
+const PROGRAMS: &[(&str, &str, i64)] = &[
+    ("adder", "
+        (fn adder [n] [] (fn [x] (+ x n)))
+        (fn main [] []
+            (set add5 (adder 5))
+            (+ (add5 10) ((adder 100) 1))
+        )
+    ", 116),
+    // Captured variables live in cells, which every closure capturing them
+    // shares, and which outlive the function which made them
+    ("counter", "
+        (fn counter [] [] (set count 0) (fn [] (set count (+ count 1)) count))
+        (fn main [] []
+            (set next (counter))
+            (next)
+            (next)
+            (set other (counter))
+            (+ (* (next) 10) (other))
+        )
+    ", 31),
+    // A closure which mutates its function's variable, seen after it runs
+    ("mutation", "
+        (fn main [] []
+            (set total 0)
+            (set add (fn [x] (set total (+ total x))))
+            (add 5)
+            (add 7)
+            total
+        )
+    ", 12),
+    // Capturing from two levels up, through a closure which doesn't use the
+    // variable itself
+    ("nested", "
+        (fn outer [a] [] (fn [b] (fn [c] (+ (* a 100) (* b 10) c))))
+        (fn main [] []
+            (set total 0)
+            (set f (fn [b]
+                (set g (fn [c] (set total (+ total b c))))
+                (g 1)
+                (g 2)
+            ))
+            (f 10)
+            (+ (* total 1000) (((outer 1) 2) 3))
+        )
+    ", 23123),
+];
+
+/// Convert the body of a function with the given parameters, returning the
+/// variables which live in cells, and the body.
+fn convert(params: &[&str], code: &str) -> (HashSet<String>, Vec<Expr>) {
+    let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+    let mut body: Vec<Expr> = read_expressions(code.to_string()).unwrap()
+                                                                .iter()
+                                                                .map(Expr::from_exp)
+                                                                .collect::<Result<_, _>>()
+                                                                .unwrap();
+    let boxed = closures::convert(&params, &mut body).unwrap();
+    (boxed, body)
+}
+
+/// The lambda assigned by a statement, `(set name (fn ...))`
+fn lambda(x: &Expr) -> &Lambda {
+    match x {
+        Expr::Located(_, x) => lambda(x),
+        Expr::Assign(_, value) => lambda(value),
+        Expr::Lambda(lambda) => lambda,
+        x => panic!("Expected a lambda, found {x:?}"),
+    }
+}
+
+#[test]
+fn only_captured_variables_live_in_cells() {
+    let (boxed, body) = convert(&["a", "b"], "
+        (set total 0)
+        (set unused 1)
+        (set add (fn [x] (set total (+ total x a))))
+        (add b)
+        total
+    ");
+    assert_eq!(boxed, HashSet::from(["total".to_string(), "a".to_string()]));
+    let mut captures = lambda(&body[2]).captures.clone();
+    captures.sort();
+    assert_eq!(captures, vec!["a", "total"]);
+
+    let (boxed, _) = convert(&["a"], "(set f (fn [x] (+ x 1))) (f a)");
+    assert!(boxed.is_empty());
+}
+
+#[test]
+fn closures_capture_through_closures_which_dont_use_the_variable() {
+    let (boxed, body) = convert(&["a"], "(set f (fn [b] (set g (fn [c] (+ a b c))) (g 1)))");
+    // The parameters of closures are in cells too, if they're captured
+    assert_eq!(boxed, HashSet::from(["a".to_string(), "b".to_string()]));
+    let outer = lambda(&body[0]);
+    assert_eq!(outer.captures, vec!["a"]);
+    let mut inner = lambda(&outer.body[0]).captures.clone();
+    inner.sort();
+    assert_eq!(inner, vec!["a", "b"]);
+}
+
+#[test]
+fn closures_in_the_jit() {
+    for (name, code, expected) in PROGRAMS {
+        let mut jit = JIT::default();
+        let forms = read_expressions(code.to_string()).unwrap();
+        let compiled = jit.compile_module(&forms).unwrap_or_else(|e| panic!("{name}: {e}"));
+        let (_, main) = compiled.into_iter().find(|(n, _)| n == "main").unwrap();
+        let main = unsafe { mem::transmute::<*const u8, extern "C" fn() -> i64>(main) };
+        assert_eq!(main(), *expected, "{name}");
+    }
+}
+
+#[test]
+fn closures_in_executables() {
+    let dir = env::temp_dir().join(format!("loom_closures_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (name, code, expected) in PROGRAMS {
+        let mut compiler = ObjectCompiler::new(name).unwrap();
+        let forms = read_expressions(code.to_string()).unwrap();
+        compiler.compile_module(&forms).unwrap_or_else(|e| panic!("{name}: {e}"));
+        compiler.define_entry("main").unwrap();
+        let object = dir.join(format!("{name}.o"));
+        let executable = dir.join(name);
+        fs::write(&object, compiler.emit().unwrap()).unwrap();
+        link(&object, &executable).unwrap();
+        let output = process::Command::new(&executable).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{expected}\n"), "{name}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
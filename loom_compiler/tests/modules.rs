@@ -0,0 +1,53 @@
+use core::mem;
+use loom_compiler::jit::JIT;
+use loom_reader::parse::read_expressions;
+
+/// Compile every function of a module, and return the code of the one named
+/// `name`, which takes an integer and returns an integer.
+fn compile(jit: &mut JIT, code: &str, name: &str) -> Result<extern "C" fn(i64) -> i64, String> {
+    let forms = read_expressions(code.to_string()).map_err(|e| e.to_string())?;
+    let compiled = jit.compile_module(&forms).map_err(|e| e.to_string())?;
+    let (_, code) = compiled.into_iter().find(|(n, _)| n == name).expect("no such function");
+    Ok(unsafe { mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) })
+}
+
+#[test]
+fn functions_call_each_other_in_any_order() {
+    let mut jit = JIT::default();
+    let run = compile(&mut jit, "
+        (fn run [n] [] (+ (even? n) (twice n)))
+        (fn even? [n] [] (if (= n 0) 1 (odd? (- n 1))))
+        (fn twice [x] [] (* x 2))
+        (fn odd? [n] [] (if (= n 0) 0 (even? (- n 1))))
+    ", "run").unwrap();
+    assert_eq!(run(1_000_000), 2_000_001);
+    assert_eq!(run(7), 14);
+}
+
+#[test]
+fn failed_modules_leave_nothing_behind() {
+    let mut jit = JIT::default();
+    let e = compile(&mut jit, "
+        (fn run [n] [] (helper n))
+        (fn helper [n] [] (+ n true))
+    ", "run").unwrap_err();
+    assert!(e.contains("bool"), "{e}");
+
+    // The same names can be compiled again once the module is fixed
+    let run = compile(&mut jit, "
+        (fn helper [n] [] (+ n 1))
+        (fn run [n] [] (helper n))
+    ", "run").unwrap();
+    assert_eq!(run(41), 42);
+}
+
+#[test]
+fn functions_can_be_defined_like_in_the_interpreter() {
+    let mut jit = JIT::default();
+    let run = compile(&mut jit, "
+        (def (inc a) (+ a 1))
+        (def twice (fn [a] (* a 2)))
+        (fn run [n] [] (twice (inc n)))
+    ", "run").unwrap();
+    assert_eq!(run(20), 42);
+}
@@ -0,0 +1,92 @@
+use std::{env, fs, process};
+use loom_compiler::jit::JIT;
+use loom_compiler::object::{ObjectCompiler, link};
+use loom_reader::parse::read_expressions;
+
+/// Compile a module into an executable which starts with its `main`
+/// function, run it, and return its output, or why it couldn't be linked.
+fn build_and_run(name: &str, code: &str) -> Result<process::Output, String> {
+    let forms = read_expressions(code.to_string()).unwrap();
+    let mut compiler = ObjectCompiler::new(name).unwrap();
+    compiler.compile_module(&forms).unwrap_or_else(|e| panic!("{e}"));
+    compiler.define_entry("main").unwrap();
+
+    let dir = env::temp_dir().join(format!("loom_object_{name}_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let object = dir.join(format!("{name}.o"));
+    let executable = dir.join(name);
+    fs::write(&object, compiler.emit().unwrap()).unwrap();
+    let output = link(&object, &executable).map(|_| process::Command::new(&executable).output().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+/// Build and run an executable which should succeed, and return what it
+/// prints.
+fn run(name: &str, code: &str) -> String {
+    let output = build_and_run(name, code).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn executables_print_the_result_of_main() {
+    let output = run("parity", "
+        (fn main [] [] (values (even? 1000001) (half 5.0) (twice 21)))
+        (fn even? [n] [] (if (= n 0) true (odd? (- n 1))))
+        (fn odd? [n] [] (if (= n 0) false (even? (- n 1))))
+        (fn half [x] [] (/ x 2.0))
+        (fn twice [x] []
+            (set f (fn [y] (* y 2)))
+            (f x)
+        )
+    ");
+    assert_eq!(output, "(values false 2.5 42)\n");
+}
+
+#[test]
+fn names_are_not_looked_up_in_the_compiler() {
+    // Only the runtime the executable is linked with has `loom_print_int`,
+    // the compiler's own process doesn't
+    let forms = read_expressions("
+        (extern loom_print_int [int] nil)
+        (fn show [] [] (loom_print_int 7))
+    ".to_string()).unwrap();
+    assert!(JIT::default().compile_module(&forms).is_err());
+    let mut compiler = ObjectCompiler::new("runtime").unwrap();
+    compiler.compile_module(&forms).unwrap_or_else(|e| panic!("{e}"));
+    assert!(compiler.is_defined("loom_print_int"));
+}
+
+#[test]
+fn the_linker_reports_missing_functions() {
+    let error = build_and_run("missing", "
+        (extern loom_no_such_function [int] int)
+        (fn main [] [] (loom_no_such_function 1))
+    ").unwrap_err();
+    assert!(error.starts_with("Linking"), "{error}");
+}
+
+#[test]
+fn division_by_zero_stops_the_program() {
+    let output = build_and_run("division", "
+        (fn main [] [] (values (/ 7 -1) (/ 1 (- 2 2))))
+    ").unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "error: Division by zero\n");
+}
+
+#[test]
+fn executables_print_several_values() {
+    let output = run("divmod", "
+        (fn divmod [a b] [] (values (/ a b) (% a b)))
+        (fn half [x] [] (/ x 2.0))
+        (fn main [] []
+            (let-values [[q r] (divmod 17 5)]
+                (values q r (f32 0.5) (= q 3) (if (< q 0) q) half)
+            )
+        )
+    ");
+    assert_eq!(output, "(values 3 2 0.5 true nil <(fn [float] float)>)\n");
+}
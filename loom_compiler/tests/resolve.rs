@@ -0,0 +1,66 @@
+use loom_compiler::resolve::{Binding, Resolution, resolve_function};
+use loom_reader::diagnostics::{ErrorCode, Severity};
+use loom_reader::parse::{Location, Span, read_expressions};
+
+/// Resolve a function, in a module which also has the function `helper` and
+/// the data object `table`.
+fn resolve(code: &str) -> Resolution {
+    let function = read_expressions(code.to_string()).unwrap().remove(0);
+    resolve_function(&function, &|name| matches!(name, "helper" | "table"))
+}
+
+/// The code, severity, message and start of every diagnostic.
+fn diagnostics(resolution: &Resolution) -> Vec<(Option<ErrorCode>, Severity, String, Location)> {
+    resolution.diagnostics.iter().map(|d| (d.code, d.severity, d.message.clone(), d.span.start)).collect()
+}
+
+#[test]
+fn unbound_names_are_errors() {
+    let resolution = resolve("(fn f [a] []\n    (+ a b)\n    (missing a)\n    (helper &table &nowhere))");
+    assert_eq!(diagnostics(&resolution), vec![
+        (Some(ErrorCode::UnboundName), Severity::Error, "Unbound variable 'b'".to_string(), Location::new(2, 10)),
+        (Some(ErrorCode::UnboundName), Severity::Error, "Unknown function 'missing'".to_string(), Location::new(3, 6)),
+        (Some(ErrorCode::UnboundName), Severity::Error, "Unknown data object 'nowhere'".to_string(), Location::new(4, 20)),
+    ]);
+    assert_eq!(resolution.errors().count(), 3);
+    // The span covers the name, up to its last character
+    let d = &resolution.diagnostics[1];
+    assert_eq!(d.span.end, Location::new(3, 12));
+}
+
+#[test]
+fn names_are_only_bound_in_their_scope() {
+    let resolution = resolve("(fn f [] [] (let [x 1] x) x (set g (fn [y] y)) y)");
+    let unbound: Vec<String> = resolution.errors().map(|d| d.message.clone()).collect();
+    assert_eq!(unbound, vec!["Unbound variable 'x'", "Unbound variable 'y'"]);
+    // `set` declares, and the let values are resolved outside the let
+    let resolution = resolve("(fn f [] [] (set x 1) (let [x (+ x 1) z x] z))");
+    assert_eq!(diagnostics(&resolution), vec![(
+        Some(ErrorCode::ShadowedName), Severity::Warning,
+        "'x' shadows the variable of the same name".to_string(), Location::new(1, 29),
+    )]);
+}
+
+#[test]
+fn shadowing_is_a_warning() {
+    let resolution = resolve("(fn f [a] []\n    (let [a 2]\n        (set g (fn [a] a))\n        a))");
+    assert_eq!(diagnostics(&resolution), vec![
+        (Some(ErrorCode::ShadowedName), Severity::Warning, "'a' shadows the parameter of the same name".to_string(), Location::new(2, 11)),
+        (Some(ErrorCode::ShadowedName), Severity::Warning, "'a' shadows the variable of the same name".to_string(), Location::new(3, 21)),
+    ]);
+    assert_eq!(resolution.diagnostics[0].notes, vec!["the parameter is declared at 1:8".to_string()]);
+    assert_eq!(resolution.diagnostics[1].notes, vec!["the variable is declared at 2:11".to_string()]);
+    assert_eq!(resolution.errors().count(), 0);
+}
+
+#[test]
+fn uses_are_bound_where_they_are_written() {
+    let resolution = resolve("(fn f [a] [] (set b a) (helper b))");
+    let at = |column| Span::new(Default::default(), Location::new(1, column), Location::new(1, column));
+    assert_eq!(resolution.binding(at(21)), Some(Binding::Param(0)));
+    assert_eq!(resolution.binding(at(19)), Some(Binding::Local(0)));
+    assert_eq!(resolution.binding(at(32)), Some(Binding::Local(0)));
+    let helper = Span::new(Default::default(), Location::new(1, 25), Location::new(1, 30));
+    assert_eq!(resolution.binding(helper), Some(Binding::Global));
+    assert_eq!(resolution.locals[0].name, "b");
+}
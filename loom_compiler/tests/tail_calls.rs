@@ -0,0 +1,61 @@
+use core::mem;
+use loom_compiler::jit::JIT;
+
+/// Far deeper than the stack would allow without tail calls
+const ITERATIONS: i64 = 1_000_000;
+
+/// Compile a function of one integer parameter which returns an integer.
+fn compile(jit: &mut JIT, code: &str) -> extern "C" fn(i64) -> i64 {
+    let code = jit.compile(code).unwrap_or_else(|e| panic!("{e}"));
+    unsafe { mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) }
+}
+
+#[test]
+fn self_recursion_is_a_loop() {
+    let mut jit = JIT::default();
+    jit.compile("(fn count [n acc] [] (if (= n 0) acc (count (- n 1) (+ acc 2))))").unwrap();
+    let run = compile(&mut jit, "(fn run [n] [] (count n 0))");
+    assert_eq!(run(ITERATIONS), 2 * ITERATIONS);
+}
+
+#[test]
+fn closure_calling_itself() {
+    // Like the README's `tick`
+    let mut jit = JIT::default();
+    let run = compile(&mut jit, "
+        (fn run [n] []
+            (def (tick i total) (if (= i 0) total (tick (- i 1) (+ total 1))))
+            (tick n 0)
+        )
+    ");
+    assert_eq!(run(ITERATIONS), ITERATIONS);
+}
+
+#[test]
+fn mutual_recursion_bounces() {
+    let mut jit = JIT::default();
+    let parity = compile(&mut jit, "
+        (fn parity [n] []
+            (set odd? (fn [i] 0))
+            (fn even? [i] [] (if (= i 0) 1 (odd? (- i 1))))
+            (set odd? (fn [i] (if (= i 0) 0 (even? (- i 1)))))
+            (even? n)
+        )
+    ");
+    assert_eq!(parity(ITERATIONS), 1);
+    assert_eq!(parity(ITERATIONS + 1), 0);
+}
+
+#[test]
+fn bounces_through_functions_of_the_module() {
+    let mut jit = JIT::default();
+    jit.compile("(fn step [f n] [] (if (= n 0) 0 (f (- n 1))))").unwrap();
+    let run = compile(&mut jit, "
+        (fn run [n] []
+            (set loop (fn [i] i))
+            (set loop (fn [i] (step loop i)))
+            (+ (loop n) 7)
+        )
+    ");
+    assert_eq!(run(ITERATIONS), 7);
+}
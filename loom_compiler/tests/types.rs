@@ -0,0 +1,108 @@
+use core::mem;
+use loom_compiler::jit::JIT;
+use loom_reader::diagnostics::{Diagnostic, ErrorCode};
+use loom_reader::parse::{Location, read_expressions};
+
+/// Check a module, and return the diagnostic it fails with.
+fn error(code: &str) -> Diagnostic {
+    let forms = read_expressions(code.to_string()).unwrap();
+    let e = JIT::default().check_module(&forms).expect_err("the module should fail to check");
+    match e.downcast::<Diagnostic>() {
+        Ok(d) => *d,
+        Err(e) => panic!("Expected a diagnostic, found {e}"),
+    }
+}
+
+/// Check a module, and return the type of each function.
+fn types(code: &str) -> Vec<String> {
+    let forms = read_expressions(code.to_string()).unwrap();
+    let functions = JIT::default().check_module(&forms).unwrap_or_else(|e| panic!("{e}"));
+    functions.iter().map(|(name, scheme)| format!("{name} : {scheme}")).collect()
+}
+
+#[test]
+fn type_errors_point_at_the_innermost_form() {
+    let d = error("(fn f [x] []\n    (let [y 1]\n        (+ y true)))");
+    assert_eq!(d.code, Some(ErrorCode::TypeMismatch));
+    assert_eq!(d.message, "Expected int, found bool");
+    assert_eq!((d.span.start, d.span.end), (Location::new(3, 9), Location::new(3, 18)));
+    assert_eq!(d.to_string(), "3:9: Expected int, found bool");
+
+    let d = error("(fn f [] [] 1)\n(fn g [] [] (if (f) 2 3))");
+    assert_eq!(d.code, Some(ErrorCode::TypeMismatch));
+    assert_eq!(d.message, "Expected bool, found int");
+    assert_eq!(d.span.start, Location::new(2, 13));
+}
+
+#[test]
+fn declarations_are_checked() {
+    let d = error("(extern puts [window] int)");
+    assert_eq!(d.code, Some(ErrorCode::InvalidType));
+    assert_eq!(d.message, "Unknown type 'window'");
+    assert_eq!(d.span.start, Location::new(1, 15));
+    assert!(d.help.is_some());
+
+    let d = error("(deftype window)\n(deftype window)");
+    assert_eq!(d.code, Some(ErrorCode::InvalidType));
+    assert_eq!(d.span.start, Location::new(2, 1));
+
+    let d = error("(deftype)");
+    assert_eq!(d.code, Some(ErrorCode::InvalidForm));
+}
+
+#[test]
+fn unresolved_names_are_diagnostics_too() {
+    let d = error("(fn f [] [] (+ 1 missing))");
+    assert_eq!(d.code, Some(ErrorCode::UnboundName));
+    assert_eq!(d.span.start, Location::new(1, 18));
+}
+
+#[test]
+fn named_functions_need_keyword_parameters() {
+    let d = error("(fn f [] [] 1)\n(fn g [a] (+ a 1))");
+    assert_eq!(d.code, Some(ErrorCode::InvalidForm));
+    assert_eq!(d.message, "Expected a keyword parameter list for 'g'");
+    assert_eq!(d.span.start, Location::new(2, 1));
+}
+
+const LERP: &str = "(fn lerp [a b t] [] (+ a (* (- b a) t)))";
+
+#[test]
+fn functions_can_be_generic_over_numbers() {
+    assert_eq!(types(&format!("{LERP}\n(fn mid [a b] [] (lerp a b 0.5))\n(fn inc [a] [] (+ a 1))")), vec![
+        "lerp : (fn ['a 'a 'a] 'a) where 'a is a number",
+        "mid : (fn [float float] float)",
+        "inc : (fn [int] int)",
+    ]);
+    assert_eq!(types("(fn scale [x n] [] (values (* x x) (* n n)))"), vec![
+        "scale : (fn ['a 'b] (values 'a 'b)) where 'a 'b are numbers",
+    ]);
+
+    let d = error(&format!("{LERP}\n(fn f [] [] (lerp \"a\" \"b\" \"c\"))"));
+    assert_eq!(d.code, Some(ErrorCode::TypeMismatch));
+    assert_eq!(d.message, "Expected a number, found str");
+    assert_eq!(d.span.start, Location::new(2, 13));
+}
+
+#[test]
+fn generic_numbers_are_compiled_for_each_type() {
+    let source = format!("
+        {LERP}
+        (fn sum_to [x n] [] (if (= n 0) x (sum_to (+ x x) (- n 1))))
+        (fn floats [] [] (+ (lerp 2.0 4.0 0.25) (sum_to 1.5 3) ((fn [y] (lerp y 1.0 0.5)) 0.0)))
+        (fn ints [] [] (+ (lerp 2 10 3) (sum_to 1 3)))
+        (fn value [] [] (set g lerp) (g (f32 1) (f32 2) (f32 0.5)))
+    ");
+    let forms = read_expressions(source).unwrap();
+    let mut jit = JIT::default();
+    let compiled = jit.compile_module(&forms).unwrap_or_else(|e| panic!("{e}"));
+    let names: Vec<&str> = compiled.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["lerp", "sum_to", "floats", "ints", "value"]);
+    unsafe {
+        assert_eq!(mem::transmute::<*const u8, extern "C" fn() -> f64>(compiled[2].1)(), 2.5 + 12.0 + 0.5);
+        assert_eq!(mem::transmute::<*const u8, extern "C" fn() -> i64>(compiled[3].1)(), 26 + 8);
+        assert_eq!(mem::transmute::<*const u8, extern "C" fn() -> f32>(compiled[4].1)(), 1.5);
+        // The function itself takes ints
+        assert_eq!(mem::transmute::<*const u8, extern "C" fn(i64, i64, i64) -> i64>(compiled[0].1)(1, 3, 2), 5);
+    }
+}
@@ -0,0 +1,61 @@
+use core::mem;
+use loom_compiler::jit::JIT;
+use loom_reader::parse::read_expressions;
+
+const SOURCE: &str = "
+    (fn divmod [a b] [] (values (/ a b) (% a b)))
+    (fn combine [a b] []
+        (let-values [[q r] (divmod a b)
+                     [x y] (values 1.5 true)]
+            (if y (+ (* q 10) r) 0)
+        )
+    )
+    (fn sorted [a b] [] (if (< a b) (values a b 0.5) (values b a 2.5)))
+";
+
+fn compile(jit: &mut JIT) -> Vec<(String, *const u8)> {
+    let forms = read_expressions(SOURCE.to_string()).unwrap();
+    jit.compile_module(&forms).unwrap_or_else(|e| panic!("{e}"))
+}
+
+#[test]
+fn values_are_received_by_name() {
+    let mut jit = JIT::default();
+    let (_, combine) = compile(&mut jit).into_iter().find(|(name, _)| name == "combine").unwrap();
+    let combine = unsafe { mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(combine) };
+    assert_eq!(combine(17, 5), 32);
+    assert_eq!(combine(-17, 5), -32);
+}
+
+#[test]
+fn values_are_written_for_the_host() {
+    let mut jit = JIT::default();
+    compile(&mut jit);
+    let writer = jit.compile_values_writer("divmod").unwrap();
+    let writer = unsafe { mem::transmute::<*const u8, extern "C" fn(i64, i64, *mut u64)>(writer) };
+    let mut words = [0u64; 2];
+    writer(17, 5, words.as_mut_ptr());
+    assert_eq!(words, [3, 2]);
+
+    // Floats are written as their bits, from either branch
+    let writer = jit.compile_values_writer("sorted").unwrap();
+    let writer = unsafe { mem::transmute::<*const u8, extern "C" fn(i64, i64, *mut u64)>(writer) };
+    let mut words = [0u64; 3];
+    writer(9, 4, words.as_mut_ptr());
+    assert_eq!(words, [4, 9, 2.5f64.to_bits()]);
+    writer(4, 9, words.as_mut_ptr());
+    assert_eq!(words, [4, 9, 0.5f64.to_bits()]);
+
+    assert!(jit.compile_values_writer("missing").is_err());
+}
+
+#[test]
+fn values_must_be_received_whole() {
+    let mut jit = JIT::default();
+    let forms = read_expressions("
+        (fn divmod [a b] [] (values (/ a b) (% a b)))
+        (fn wrong [] [] (let-values [[q r s] (divmod 17 5)] q))
+    ".to_string()).unwrap();
+    let e = jit.compile_module(&forms).unwrap_err().to_string();
+    assert!(e.ends_with("found (values int int)"), "{e}");
+}
@@ -0,0 +1,3 @@
+pub mod protocol;
+pub mod symbols;
+pub mod server;
@@ -0,0 +1,15 @@
+use std::io;
+use std::process::ExitCode;
+use loom_lsp::server::Server;
+
+fn main() -> ExitCode {
+    let stdin = io::stdin();
+    match Server::default().run(stdin.lock(), io::stdout().lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! The wire format of the Language Server Protocol: JSON-RPC messages with
+//! HTTP-like headers, and positions counted in UTF-16 code units.
+
+use std::io::{self, BufRead, Write};
+use loom_reader::parse::{ Location, Span };
+use serde_json::{ Value, json };
+
+/// Read a message, or nothing if the input is closed.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Message is missing its Content-Length"));
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::from)
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+/// The position of a location's character in a document
+pub fn position(text: &str, location: Location) -> Value {
+    let line = location.line.saturating_sub(1);
+    let character = utf16_len(text, line, location.column.saturating_sub(1));
+    json!({ "line": line, "character": character })
+}
+
+/// The range covered by a span, which unlike the span doesn't include its
+/// end
+pub fn range(text: &str, span: Span) -> Value {
+    let end_line = span.end.line.saturating_sub(1);
+    let end = json!({ "line": end_line, "character": utf16_len(text, end_line, span.end.column) });
+    json!({ "start": position(text, span.start), "end": end })
+}
+
+/// The range covering a whole document
+pub fn whole_range(text: &str) -> Value {
+    let lines = text.split('\n').count();
+    let last = text.rsplit('\n').next().unwrap_or_default();
+    json!({
+        "start": { "line": 0, "character": 0 },
+        "end": { "line": lines - 1, "character": last.encode_utf16().count() },
+    })
+}
+
+/// The byte offset of a position, if it's in the document
+pub fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+// How many UTF-16 code units the first `chars` characters of a line take
+fn utf16_len(text: &str, line: usize, chars: usize) -> usize {
+    let line = text.split('\n').nth(line).unwrap_or_default();
+    line.chars().take(chars).map(char::len_utf16).sum()
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use loom_reader::cst::{ self, SyntaxKind };
+use loom_reader::format::format;
+use loom_reader::parse::{ FileId, ParseError, Span, read_all_expressions };
+use serde_json::{ Value, json };
+use crate::protocol::{ self, read_message, write_message };
+use crate::symbols::{ self, Symbol, SymbolKind };
+
+// JSON-RPC error codes
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// The result of a request, or an error code and message
+type RequestResult<T> = Result<T, (i64, String)>;
+
+/// A language server for `.loom` files, which keeps the open documents in
+/// memory and answers requests about them.
+#[derive(Debug, Default)]
+pub struct Server {
+    /// The text of every open document, by URI
+    documents: HashMap<String, String>,
+    shutting_down: bool,
+}
+
+impl Server {
+    /// Answer messages from `input` until the client says to exit, returning
+    /// whether it asked to shut down first, as it should have.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+        while let Some(message) = read_message(&mut input)? {
+            if message["method"] == "exit" {
+                return Ok(self.shutting_down);
+            }
+            for reply in self.handle(&message) {
+                write_message(&mut output, &reply)?;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Handle a request or notification, returning the messages to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutting_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{method}'"))),
+        };
+        vec![match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        }]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                // Documents are always synced in full
+                let Some(text) = params["contentChanges"].as_array()
+                                                         .and_then(|c| c.last())
+                                                         .and_then(|c| c["text"].as_str()) else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        let text = &self.documents[&uri];
+        let (_, errors) = read_all_expressions(text.clone(), FileId::default());
+        let diagnostics = errors.iter().map(|e| diagnostic(text, e)).collect();
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+
+    fn document(&self, params: &Value) -> RequestResult<(&str, &String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get_key_value(uri) {
+            Some((uri, text)) => Ok((uri, text)),
+            None => Err((INVALID_PARAMS, format!("Unknown document '{uri}'"))),
+        }
+    }
+
+    // The symbols of a document, and the symbol under the cursor with the
+    // span of the name there
+    fn lookup(&self, params: &Value) -> RequestResult<Option<(Symbol, Span)>> {
+        let (_, text) = self.document(params)?;
+        let symbols = symbols::symbols(&read_all_expressions(text.clone(), FileId::default()).0);
+        let Some(offset) = protocol::offset(text, &params["position"]) else {
+            return Ok(None);
+        };
+        let (root, _) = cst::parse(text, FileId::default());
+        // The cursor can be just after the name too
+        let token = [offset, offset.saturating_sub(1)].into_iter()
+                                                       .filter_map(|o| root.token_at(o))
+                                                       .find(|t| t.kind() == SyntaxKind::Symbol);
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let at = token.span().start;
+        let symbol = symbols::defined_at(&symbols, at)
+            .or_else(|| symbols::resolve(&symbols, token.text(), at))
+            .cloned();
+        Ok(symbol.map(|s| (s, token.span())))
+    }
+
+    fn definition(&self, params: &Value) -> RequestResult<Value> {
+        let symbol = self.lookup(params)?;
+        let (uri, text) = self.document(params)?;
+        Ok(match symbol {
+            Some((symbol, _)) => json!({ "uri": uri, "range": protocol::range(text, symbol.name_span) }),
+            None => Value::Null,
+        })
+    }
+
+    fn hover(&self, params: &Value) -> RequestResult<Value> {
+        let symbol = self.lookup(params)?;
+        let (_, text) = self.document(params)?;
+        let Some((symbol, span)) = symbol else {
+            return Ok(Value::Null);
+        };
+        let mut contents = format!("```loom\n{symbol}\n```");
+        if let Some(arity) = symbol.arity() {
+            contents.push_str(&format!("\n\n`{}` {arity}", symbol.name));
+        }
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": protocol::range(text, span),
+        }))
+    }
+
+    fn document_symbols(&self, params: &Value) -> RequestResult<Value> {
+        let (_, text) = self.document(params)?;
+        let symbols = symbols::symbols(&read_all_expressions(text.clone(), FileId::default()).0);
+        Ok(Value::Array(symbols.iter().map(|s| document_symbol(text, s)).collect()))
+    }
+
+    fn formatting(&self, params: &Value) -> RequestResult<Value> {
+        let (_, text) = self.document(params)?;
+        let Ok(formatted) = format(text, FileId::default()) else {
+            return Err((REQUEST_FAILED, "Can't format a document with syntax errors".to_string()));
+        };
+        if formatted == *text {
+            return Ok(json!([]));
+        }
+        Ok(json!([{ "range": protocol::whole_range(text), "newText": formatted }]))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // Full documents are sent on every change
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "documentFormattingProvider": true,
+        },
+        "serverInfo": { "name": "loom-lsp", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn diagnostic(text: &str, e: &ParseError) -> Value {
+    let d = e.diagnostic();
+    let mut message = d.message.clone();
+    for note in &d.notes {
+        message.push_str(&format!("\nnote: {note}"));
+    }
+    if let Some(help) = &d.help {
+        message.push_str(&format!("\nhelp: {help}"));
+    }
+    json!({
+        "range": protocol::range(text, d.span),
+        "severity": 1,
+        "code": d.code.map(|c| c.to_string()),
+        "source": "loom",
+        "message": message,
+    })
+}
+
+fn document_symbol(text: &str, symbol: &Symbol) -> Value {
+    // Functions, variables and operators in the protocol's numbering
+    let kind = match symbol.kind {
+        SymbolKind::Function => 12,
+        SymbolKind::Variable => 13,
+        SymbolKind::Macro => 25,
+    };
+    json!({
+        "name": symbol.name,
+        "detail": symbol.to_string(),
+        "kind": kind,
+        "range": protocol::range(text, symbol.span),
+        "selectionRange": protocol::range(text, symbol.name_span),
+        "children": symbol.children.iter().map(|s| document_symbol(text, s)).collect::<Vec<Value>>(),
+    })
+}
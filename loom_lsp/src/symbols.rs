@@ -0,0 +1,193 @@
+//! The definitions in a document, found by walking its expressions.
+
+use std::fmt;
+use loom_reader::parse::{ Exp, Location, Span };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Macro,
+}
+
+/// A parameter of a function or macro, and whether it has a default value
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub optional: bool,
+}
+
+/// Something defined with `def`, `fn` or `defmacro`
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Where the name is written in the definition
+    pub name_span: Span,
+    /// The whole definition, which is also the scope of the symbols defined
+    /// inside it
+    pub span: Span,
+    /// The parameters of functions and macros
+    pub params: Option<Vec<Param>>,
+    /// The symbols defined inside this one, like local functions
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    /// How many arguments the symbol takes, as a human would put it
+    pub fn arity(&self) -> Option<String> {
+        let params = self.params.as_ref()?;
+        let required = params.iter().filter(|p| !p.optional).count();
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        Some(match (required, params.len()) {
+            (0, 0) => "takes no arguments".to_string(),
+            (n, m) if n == m => format!("takes {n} argument{}", plural(n)),
+            (n, m) => format!("takes {n} to {m} arguments"),
+        })
+    }
+}
+
+impl fmt::Display for Symbol {
+    /// The signature of the symbol, like `(fn add [a b])`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let form = match self.kind {
+            SymbolKind::Function => "fn",
+            SymbolKind::Variable => "def",
+            SymbolKind::Macro => "defmacro",
+        };
+        match &self.params {
+            Some(params) => {
+                let params: Vec<String> = params.iter().map(|p| {
+                    if p.optional { format!("[{} ...]", p.name) } else { p.name.clone() }
+                }).collect();
+                write!(f, "({form} {} [{}])", self.name, params.join(" "))
+            }
+            None => write!(f, "({form} {})", self.name),
+        }
+    }
+}
+
+/// Every definition among some expressions, with the ones defined inside
+/// other definitions as their children.
+pub fn symbols(expressions: &[Exp]) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for x in expressions {
+        collect(x, &mut symbols);
+    }
+    symbols
+}
+
+fn collect(x: &Exp, symbols: &mut Vec<Symbol>) {
+    if let Some(mut symbol) = definition(x) {
+        for item in x.args().unwrap_or_default() {
+            collect(&item, &mut symbol.children);
+        }
+        symbols.push(symbol);
+        return;
+    }
+    match x {
+        Exp::SExp { kind, args, kwargs, .. } => {
+            if matches!(kind.as_symbol().as_deref(), Some("quote") | Some("quasiquote")) {
+                return;
+            }
+            collect(kind, symbols);
+            for arg in args {
+                collect(arg, symbols);
+            }
+            for (_, v) in kwargs {
+                collect(v, symbols);
+            }
+        }
+        Exp::List(items, _) => {
+            for item in items {
+                collect(item, symbols);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The symbol a form defines, if it's a definition
+fn definition(x: &Exp) -> Option<Symbol> {
+    let symbol = |name: &Exp, kind, params: Option<Vec<Param>>| {
+        let Exp::Symbol(name_text, name_span) = name else {
+            return None;
+        };
+        Some(Symbol {
+            name: name_text.clone(),
+            kind,
+            name_span: *name_span,
+            span: x.span(),
+            params,
+            children: Vec::new(),
+        })
+    };
+    match x.car_symbol()?.as_str() {
+        "def" => match x.arg(0)? {
+            // `(def (name params...) body...)`
+            Exp::SExp { kind, args, .. } => symbol(&kind, SymbolKind::Function, Some(params(&args))),
+            name => {
+                // `(def name (fn [params] body...))` defines a function
+                let params = match x.arg(1) {
+                    Some(value) if value.car_symbol().as_deref() == Some("fn") => match value.arg(0) {
+                        Some(Exp::List(items, _)) => Some(params(&items)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let kind = if params.is_some() { SymbolKind::Function } else { SymbolKind::Variable };
+                symbol(&name, kind, params)
+            }
+        },
+        "fn" => {
+            // Only named functions define anything
+            let (name @ Exp::Symbol(..), Some(Exp::List(items, _))) = (x.arg(0)?, x.arg(1)) else {
+                return None;
+            };
+            symbol(&name, SymbolKind::Function, Some(params(&items)))
+        }
+        "defmacro" => {
+            let Some(Exp::List(items, _)) = x.arg(1) else {
+                return None;
+            };
+            symbol(&x.arg(0)?, SymbolKind::Macro, Some(params(&items)))
+        }
+        _ => None,
+    }
+}
+
+fn params(items: &[Exp]) -> Vec<Param> {
+    items.iter().filter_map(|p| match p {
+        Exp::Symbol(name, _) => Some(Param { name: name.clone(), optional: false }),
+        Exp::List(contents, _) => match contents.first() {
+            Some(Exp::Symbol(name, _)) => Some(Param { name: name.clone(), optional: true }),
+            _ => None,
+        },
+        _ => None,
+    }).collect()
+}
+
+/// The definition a name refers to at a location: the one in the innermost
+/// definition containing the location, or else a top-level one.
+pub fn resolve<'a>(symbols: &'a [Symbol], name: &str, at: Location) -> Option<&'a Symbol> {
+    let inner = symbols.iter()
+                       .filter(|s| contains(s.span, at))
+                       .find_map(|s| resolve(&s.children, name, at));
+    inner.or_else(|| symbols.iter().rev().find(|s| s.name == name))
+}
+
+/// The symbol whose name is written at a location
+pub fn defined_at(symbols: &[Symbol], at: Location) -> Option<&Symbol> {
+    symbols.iter().find_map(|s| {
+        if contains(s.name_span, at) {
+            Some(s)
+        } else {
+            defined_at(&s.children, at)
+        }
+    })
+}
+
+fn contains(span: Span, at: Location) -> bool {
+    let key = |l: Location| (l.line, l.column);
+    key(span.start) <= key(at) && key(at) <= key(span.end)
+}
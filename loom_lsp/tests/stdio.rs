@@ -0,0 +1,148 @@
+use std::io::{BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use loom_lsp::protocol::{read_message, write_message};
+use serde_json::{Value, json};
+
+const URI: &str = "file:///scene.loom";
+
+const BROKEN: &str = "(fn square [x] []\n    (* x x)\n\n(fn main [] [] (square 4))\n";
+
+const FIXED: &str = "\
+(fn square [x] []
+    (* x x)
+)
+
+(fn main [] []
+    (def cube (fn [x [y 1]] (* x x x y)))
+    (+ (square 4)   (cube 2))
+)
+";
+
+/// A client which talks to the server binary over its stdin and stdout
+struct Client {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    next_id: i64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_loom-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("the server should start");
+        let input = server.stdin.take().unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        Self { server, input, output, next_id: 1 }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.input, &message).unwrap();
+    }
+
+    /// Send a request and wait for its response, returning its result
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        write_message(&mut self.input, &message).unwrap();
+        loop {
+            let reply = self.receive();
+            if reply["id"] == id {
+                assert!(reply.get("error").is_none(), "{method} failed: {reply}");
+                return reply["result"].clone();
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.output).unwrap().expect("the server shouldn't hang up")
+    }
+
+    /// Wait for the diagnostics of the document
+    fn diagnostics(&mut self) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                assert_eq!(message["params"]["uri"], URI);
+                return message["params"]["diagnostics"].as_array().unwrap().clone();
+            }
+        }
+    }
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+#[test]
+fn scripted_session() {
+    let mut client = Client::start();
+
+    let init = client.request("initialize", json!({ "capabilities": {} }));
+    let capabilities = &init["capabilities"];
+    assert_eq!(capabilities["textDocumentSync"], 1);
+    for provider in ["definitionProvider", "hoverProvider", "documentSymbolProvider", "documentFormattingProvider"] {
+        assert_eq!(capabilities[provider], true, "{provider} should be enabled");
+    }
+    client.notify("initialized", json!({}));
+
+    // The first function is never closed
+    client.notify("textDocument/didOpen", json!({
+        "textDocument": { "uri": URI, "languageId": "loom", "version": 1, "text": BROKEN },
+    }));
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "E0001");
+    assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 0, "character": 0 }));
+
+    client.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": FIXED }],
+    }));
+    assert!(client.diagnostics().is_empty());
+
+    // `square` in `(square 4)` goes to its definition
+    let definition = client.request("textDocument/definition", at(6, 10));
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(definition["range"], json!({
+        "start": { "line": 0, "character": 4 },
+        "end": { "line": 0, "character": 10 },
+    }));
+    // A local function, from the end of its name
+    let definition = client.request("textDocument/definition", at(6, 25));
+    assert_eq!(definition["range"]["start"], json!({ "line": 5, "character": 9 }));
+
+    let hover = client.request("textDocument/hover", at(6, 8));
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("(fn square [x])"), "{contents}");
+    assert!(contents.contains("takes 1 argument"), "{contents}");
+    let hover = client.request("textDocument/hover", at(5, 10));
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("takes 1 to 2 arguments"), "{contents}");
+    assert_eq!(client.request("textDocument/hover", at(1, 0)), Value::Null);
+
+    let symbols = client.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } }));
+    let names: Vec<&str> = symbols.as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["square", "main"]);
+    assert_eq!(symbols[1]["children"][0]["name"], "cube");
+    assert_eq!(symbols[1]["children"][0]["kind"], 12);
+
+    let edits = client.request("textDocument/formatting", json!({
+        "textDocument": { "uri": URI },
+        "options": { "tabSize": 4, "insertSpaces": true },
+    }));
+    let edits = edits.as_array().unwrap();
+    assert_eq!(edits.len(), 1);
+    assert!(edits[0]["newText"].as_str().unwrap().contains("(+ (square 4) (cube 2))"));
+    assert_eq!(edits[0]["range"]["end"], json!({ "line": 8, "character": 0 }));
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    client.input.flush().unwrap();
+    let status = client.server.wait().unwrap();
+    assert!(status.success(), "the server should exit cleanly after a shutdown");
+}
@@ -5,7 +5,6 @@ use loom_reader::{
     parse::{
         Exp, read_expressions
     },
-    forms::Form,
 };
 use std::collections::HashMap;
 
@@ -92,7 +91,7 @@ impl Function {
     }
     pub fn add_exp(&mut self, x: Exp) -> ID {
         match x {
-            Exp::Nil => {
+            Exp::Nil(_) => {
                 self.add_nil()
             }
             Exp::SExp { .. } => {
@@ -108,19 +107,15 @@ impl Function {
                     "fn" => {
                         let mut f = Function::new();
                         // Extract parameters
-                        let Some(Exp::List(params)) = x.arg(0) else { todo!() };
+                        let Some(Exp::List(params, _)) = x.arg(0) else { todo!() };
                         for p in params {
                             let Some(p_name) = p.as_symbol() else { todo!() };
                             f.add_param(p_name);
                         }
                         // Extract body expressions
                         if let Some(body) = x.args() {
-                            let mut bi = 0;
-                            for b in body {
-                                if bi > 0 {
-                                    f.add_exp(b.clone());
-                                }
-                                bi += 1;
+                            for b in body.into_iter().skip(1) {
+                                f.add_exp(b);
                             }
                         }
                         self.add_function(f)
@@ -144,8 +139,8 @@ impl Function {
                     }
                 }
             }
-            Exp::Atom(v) => {
-                self.add_value(v)
+            Exp::Int(..) | Exp::Float(..) | Exp::Bool(..) | Exp::Str(..) | Exp::Symbol(..) => {
+                self.add_value(x.to_string())
             }
             _ => {
                 ID::Nil
@@ -159,11 +154,11 @@ fn debug_fn(f: &Function, nesting: usize) -> String {
     for (i, v) in f.variables.iter().enumerate() {
         let indent = " ".repeat(nesting * 4);
         let val = match v {
-            Prim::Nil => format!("NIL"),
-            Prim::Value(val) => format!("{val}"),
+            Prim::Nil => "NIL".to_string(),
+            Prim::Value(val) => val.to_string(),
             Prim::Op { kind, args } => {
                 let args: Vec<String> = args.iter().map(|x| format!("{x}")).collect();
-                let args_string = args.connect(", ");
+                let args_string = args.join(", ");
                 format!("{kind}({args_string})")
             }
             Prim::Fn(f) => {
@@ -0,0 +1,63 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use loom_reader::cst::{self, SyntaxElement};
+use loom_reader::parse::{Exp, FileId, read_all_expressions};
+
+/// Print the syntax tree of each file given, and check that it reproduces the
+/// file exactly and reads the same expressions as the reader.
+fn main() -> Result<(), Box<dyn Error>> {
+    for path in env::args().skip(1) {
+        let source = fs::read_to_string(&path)?;
+        let (root, errors) = cst::parse(&source, FileId::default());
+        print_tree(&SyntaxElement::Node(root.clone()), 0);
+
+        assert_eq!(root.green().to_string(), source, "{path} doesn't round-trip");
+        let (expected, read_errors) = read_all_expressions(source.clone(), FileId::default());
+        assert_eq!(errors.len(), read_errors.len(), "{path} has different errors");
+        if errors.is_empty() {
+            let exps = root.to_exps()?;
+            let same = exps.len() == expected.len()
+                && exps.iter().zip(&expected).all(|(a, b)| same(a, b));
+            assert!(same, "{path} reads differently");
+        }
+        println!("{path}: ok, {} bytes, {} errors\n", source.len(), errors.len());
+    }
+    Ok(())
+}
+
+fn print_tree(element: &SyntaxElement, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match element {
+        SyntaxElement::Node(node) => {
+            println!("{indent}{:?}@{:?}", node.kind(), node.range());
+            for child in node.children_with_tokens() {
+                print_tree(&child, depth + 1);
+            }
+        }
+        SyntaxElement::Token(token) => {
+            println!("{indent}{:?}@{:?} {:?}", token.kind(), token.range(), token.text());
+        }
+    }
+}
+
+// Whether two expressions are the same, down to their spans
+fn same(a: &Exp, b: &Exp) -> bool {
+    if a.span() != b.span() {
+        return false;
+    }
+    let all_same = |a: &[Exp], b: &[Exp]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b));
+    match (a, b) {
+        (
+            Exp::SExp { kind, args, kwargs, .. },
+            Exp::SExp { kind: other_kind, args: other_args, kwargs: other_kwargs, .. },
+        ) => {
+            same(kind, other_kind)
+                && all_same(args, other_args)
+                && kwargs.len() == other_kwargs.len()
+                && kwargs.iter().zip(other_kwargs).all(|((k, v), (o, w))| k == o && same(v, w))
+        }
+        (Exp::List(items, _), Exp::List(other_items, _)) => all_same(items, other_items),
+        _ => a.to_string() == b.to_string(),
+    }
+}
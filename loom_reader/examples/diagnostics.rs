@@ -0,0 +1,27 @@
+use std::io::{self, IsTerminal};
+use loom_reader::{
+    diagnostics::SourceMap,
+    parse::{
+        read_all_expressions
+    },
+};
+
+const BROKEN: &[(&str, &str)] = &[
+    ("unclosed.loom", "(def x 1)\n(print (+ x 2)\n"),
+    ("mismatched.loom", "(fn add [a b) [] (+ a b))\n"),
+    ("stray.loom", "(def xs [1 2 3]])\n"),
+    ("several.loom", "(def a 1))\n(def b [2)\n(def c (+ a\n(def d 4)\n"),
+];
+
+fn main() {
+    let mut sources = SourceMap::new();
+    let color = io::stdout().is_terminal();
+    for (name, source) in BROKEN {
+        let file = sources.add(name, source);
+        let (expressions, errors) = read_all_expressions(source.to_string(), file);
+        println!("{name}: {} expressions, {} errors\n", expressions.len(), errors.len());
+        for e in errors {
+            println!("{}", e.diagnostic().render(&sources, color));
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use loom_reader::format::format;
+use loom_reader::parse::{Exp, FileId, read_file_expressions};
+
+/// Format each file given, checking that formatting again changes nothing and
+/// that the formatted code means the same as the original.
+fn main() -> Result<(), Box<dyn Error>> {
+    for path in env::args().skip(1) {
+        let source = fs::read_to_string(&path)?;
+        let Ok(formatted) = format(&source, FileId::default()) else {
+            return Err(format!("{path} has syntax errors").into());
+        };
+        let Ok(again) = format(&formatted, FileId::default()) else {
+            return Err(format!("{path} has syntax errors once formatted").into());
+        };
+        assert_eq!(formatted, again, "formatting {path} isn't idempotent");
+
+        let before = read_file_expressions(source, FileId::default())?;
+        let after = read_file_expressions(formatted.clone(), FileId::default())?;
+        let same_meaning = before.iter().map(Exp::to_string).eq(after.iter().map(Exp::to_string));
+        assert!(same_meaning, "formatting {path} changes its meaning");
+        println!("{path}: ok\n{formatted}");
+    }
+    Ok(())
+}
@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::fs;
 use std::error::Error;
 use std::collections::HashMap;
@@ -5,7 +7,6 @@ use loom_reader::{
     parse::{
         Exp, read_expressions
     },
-    forms::Form,
 };
 
 #[derive(Debug)]
@@ -33,15 +34,17 @@ enum Image {
 impl Prim {
     pub fn from_exp(x: Exp) -> Self {
         match x {
-            Exp::Nil => Self::Nil,
-            Exp::Atom(contents) => {
+            Exp::Nil(_) => Self::Nil,
+            Exp::Symbol(contents, _) => {
                 if let Some(keyword) = contents.strip_prefix('@') {
                     Self::Keyword(keyword.to_string())
                 } else {
                     Self::Value(contents.to_string())
                 }
             }
-            Exp::SExp { kind, args, kwargs } => {
+            Exp::Str(contents, _) => Self::Value(contents),
+            Exp::Int(..) | Exp::Float(..) | Exp::Bool(..) => Self::Value(x.to_string()),
+            Exp::SExp { kind, kwargs, .. } => {
                 let mut props: HashMap<String, Prim> = HashMap::new();
                 for (k, v) in kwargs.iter() {
                     props.insert(k.clone(), Prim::from_exp(v.clone()));
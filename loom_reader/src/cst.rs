@@ -0,0 +1,564 @@
+//! A lossless syntax tree, which keeps every byte of the source: whitespace,
+//! comments and broken code included.
+//!
+//! Like in rust-analyzer, the tree comes in two layers. Green nodes only know
+//! their kind, their text and their children, which they hold through `Rc`s,
+//! so a subtree is cheap to clone. Red nodes (`SyntaxNode`) are built on
+//! demand on top of them, and know their parent and where they are in the
+//! source. Every parse builds a new tree: nothing is interned, or reused from
+//! an earlier parse.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use crate::lexer::Lexer;
+use crate::parse::{
+    Exp, FileId, Location, ParseError, Span, Token,
+    dangling_quote_error, keyword_errors, mismatched_error, process_atom, unclosed_error,
+    unexpected_error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    /// A whole source file
+    Root,
+    /// An expression in parentheses
+    Form,
+    /// A list in brackets
+    List,
+    /// A quote and the expression it quotes, like `'x`
+    Quoted,
+
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Symbol,
+    Str,
+    Comment,
+    Quote,
+    Whitespace,
+    /// Text which isn't part of any token, like an unterminated string
+    Error,
+}
+
+impl SyntaxKind {
+    /// Whether this kind of token doesn't affect the meaning of the code
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Self::Whitespace | Self::Comment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenToken {
+    pub kind: SyntaxKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenNode {
+    pub kind: SyntaxKind,
+    /// The length of the node's text in bytes
+    pub len: usize,
+    pub children: Vec<GreenElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        Self { kind, len, children }
+    }
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            Self::Node(node) => node.kind,
+            Self::Token(token) => token.kind,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Node(node) => node.len,
+            Self::Token(token) => token.text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{node}")?,
+                GreenElement::Token(token) => write!(f, "{}", token.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// What every red node of a tree shares, to turn byte offsets into locations
+#[derive(Debug)]
+struct Tree {
+    file: FileId,
+    source: String,
+    /// The byte offset of the start of each line
+    lines: Vec<usize>,
+}
+
+impl Tree {
+    fn location(&self, offset: usize) -> Location {
+        let line = self.lines.partition_point(|&start| start <= offset);
+        let start = self.lines[line - 1];
+        Location::new(line, self.source[start..offset].chars().count() + 1)
+    }
+
+    // The span from the first character of a range to its last one
+    fn span(&self, range: Range<usize>) -> Span {
+        let start = self.location(range.start);
+        let last = self.source[range.start..range.end].chars().next_back().map(char::len_utf8);
+        let end = match last {
+            Some(len) => self.location(range.end - len),
+            None => start,
+        };
+        Span::new(self.file, start, end)
+    }
+}
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+    tree: Rc<Tree>,
+}
+
+/// A node of the syntax tree, which knows where it is in the source
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+/// A token of the syntax tree, which knows where it is in the source
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    fn new_root(green: Rc<GreenNode>, tree: Tree) -> Self {
+        Self(Rc::new(NodeData { green, offset: 0, parent: None, tree: Rc::new(tree) }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    /// The bytes of the source this node covers
+    pub fn range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.len
+    }
+
+    pub fn span(&self) -> Span {
+        self.0.tree.span(self.range())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.0.tree.source[self.range()]
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    pub fn children_with_tokens(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        self.0.green.children.iter().map(|child| {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset,
+                    parent: Some(self.clone()),
+                    tree: self.0.tree.clone(),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset,
+                    parent: self.clone(),
+                }),
+            };
+            offset += child.len();
+            element
+        }).collect()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxNode> {
+        self.children_with_tokens().into_iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        }).collect()
+    }
+
+    /// The children which are expressions, i.e. not trivia or brackets
+    pub fn expressions(&self) -> Vec<SyntaxElement> {
+        self.children_with_tokens().into_iter().filter(|child| child.is_expression()).collect()
+    }
+
+    /// The innermost token containing a byte offset
+    pub fn token_at(&self, offset: usize) -> Option<SyntaxToken> {
+        for child in self.children_with_tokens() {
+            if !child.range().contains(&offset) {
+                continue;
+            }
+            return match child {
+                SyntaxElement::Node(node) => node.token_at(offset),
+                SyntaxElement::Token(token) => Some(token),
+            };
+        }
+        None
+    }
+
+    /// Convert the expressions in this node, e.g. every top-level expression
+    /// of a file if it's the root.
+    pub fn to_exps(&self) -> Result<Vec<Exp>, Box<dyn Error>> {
+        let mut exps = Vec::new();
+        for child in self.expressions() {
+            if let Some(x) = child.to_exp()? {
+                exps.push(x);
+            }
+        }
+        Ok(exps)
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+
+    pub fn span(&self) -> Span {
+        self.parent.0.tree.span(self.range())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    // The token as the lexer would read it
+    fn lex(&self) -> Option<Token> {
+        let tree = &self.parent.0.tree;
+        let mut lexer = Lexer::at(&tree.source, tree.file, self.offset);
+        lexer.next().map(|l| l.token)
+    }
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            Self::Node(node) => node.kind(),
+            Self::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            Self::Node(node) => node.range(),
+            Self::Token(token) => token.range(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Node(node) => node.span(),
+            Self::Token(token) => token.span(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Node(node) => node.text(),
+            Self::Token(token) => token.text(),
+        }
+    }
+
+    pub fn is_expression(&self) -> bool {
+        matches!(
+            self.kind(),
+            SyntaxKind::Form | SyntaxKind::List | SyntaxKind::Quoted | SyntaxKind::Symbol | SyntaxKind::Str
+        )
+    }
+
+    /// Convert an expression to an `Exp`, or nothing if it isn't one.
+    pub fn to_exp(&self) -> Result<Option<Exp>, Box<dyn Error>> {
+        let node = match self {
+            Self::Token(token) => {
+                return match token.kind() {
+                    SyntaxKind::Symbol | SyntaxKind::Str => match token.lex() {
+                        Some(t) => process_atom(&t),
+                        None => Ok(None),
+                    },
+                    _ => Ok(None),
+                };
+            }
+            Self::Node(node) => node,
+        };
+        let span = node.span();
+        match node.kind() {
+            SyntaxKind::Form => {
+                let contents = node.to_exps()?;
+                if contents.is_empty() {
+                    Ok(Some(Exp::Nil(span)))
+                } else {
+                    Ok(Some(Exp::new_sexp(contents, span)))
+                }
+            }
+            SyntaxKind::List => Ok(Some(Exp::List(node.to_exps()?, span))),
+            SyntaxKind::Quoted => {
+                let quote = node.children_with_tokens().into_iter().find_map(|child| match child {
+                    SyntaxElement::Token(token) if token.kind() == SyntaxKind::Quote => token.lex(),
+                    _ => None,
+                });
+                let Some(Token::Quote { form, .. }) = &quote else {
+                    return Ok(None);
+                };
+                let Some(quoted) = node.to_exps()?.pop() else {
+                    return Err(Box::new(dangling_quote_error(quote.as_ref().unwrap())));
+                };
+                Ok(Some(Exp::SExp {
+                    kind: Box::new(Exp::Symbol(form.to_string(), span)),
+                    args: vec![quoted],
+                    kwargs: Default::default(),
+                    span,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// A node being built, with the token which opened it
+struct Frame {
+    kind: SyntaxKind,
+    opening: Option<Token>,
+    children: Vec<GreenElement>,
+}
+
+/// Build the syntax tree of a source file, along with every syntax error in
+/// it. The tree always covers the whole source, errors or not.
+///
+/// Errors are recovered from like `read_all_expressions` does, so if the
+/// file ends with forms left open, a form starting at the beginning of a line
+/// is taken as a top-level one.
+pub fn parse(source: &str, file: FileId) -> (SyntaxNode, Vec<ParseError>) {
+    let (root, mut errors) = match build(source, file, false) {
+        (_, _, true) => {
+            let (root, errors, _) = build(source, file, true);
+            (root, errors)
+        }
+        (root, errors, false) => (root, errors),
+    };
+    find_keyword_errors(&root, &mut errors);
+    errors.sort_by_key(|e| (e.span.start.line, e.span.start.column));
+    (root, errors)
+}
+
+// Collect the problems with the keyword arguments of every form in a tree,
+// like `read_all_expressions` does
+fn find_keyword_errors(node: &SyntaxNode, errors: &mut Vec<ParseError>) {
+    if node.kind() == SyntaxKind::Form {
+        // Only keywords matter, so anything else can stand in for the rest
+        let contents: Vec<Exp> = node.expressions()
+                                     .iter()
+                                     .map(|x| match x {
+                                         SyntaxElement::Token(_) => x.to_exp().ok().flatten(),
+                                         SyntaxElement::Node(_) => None,
+                                     }.unwrap_or(Exp::Nil(x.span())))
+                                     .collect();
+        errors.extend(keyword_errors(&contents));
+    }
+    for child in node.children() {
+        find_keyword_errors(&child, errors);
+    }
+}
+
+/// Build the syntax tree of a source file, and tell whether it ends with
+/// forms left open. With `resync`, a form starting at the beginning of a line
+/// is always a top-level one.
+fn build(source: &str, file: FileId, resync: bool) -> (SyntaxNode, Vec<ParseError>, bool) {
+    let mut stack = vec![Frame { kind: SyntaxKind::Root, opening: None, children: Vec::new() }];
+    let mut errors = Vec::new();
+    let mut lexer = Lexer::new(source, file);
+    let mut offset = 0;
+    // Whether the top-level form being read has already been reported as
+    // broken
+    let mut broken = false;
+
+    let token = |kind: SyntaxKind, text: &str| {
+        GreenElement::Token(Rc::new(GreenToken { kind, text: text.to_string() }))
+    };
+    // Whatever the lexer skipped over is whitespace, or else broken code
+    let gap = |stack: &mut Vec<Frame>, text: &str| {
+        let code = text.trim_start();
+        let whitespace = &text[..text.len() - code.len()];
+        let parent = stack.last_mut().unwrap();
+        if !whitespace.is_empty() {
+            parent.children.push(token(SyntaxKind::Whitespace, whitespace));
+        }
+        if !code.is_empty() {
+            parent.children.push(token(SyntaxKind::Error, code));
+        }
+    };
+
+    for lexeme in lexer.by_ref() {
+        gap(&mut stack, &source[offset..lexeme.range.start]);
+        offset = lexeme.range.end;
+        let text = &source[lexeme.range.clone()];
+        let t = lexeme.token;
+        match t {
+            Token::LParen {..} | Token::LBracket {..} => {
+                if resync && stack.len() > 1 && t.get_location().column == 1 {
+                    // The previous form was most likely never closed
+                    if let (false, Some(opening)) = (broken, outermost(&stack)) {
+                        errors.push(unclosed_error(opening));
+                    }
+                    close_all(&mut stack);
+                }
+                if stack.len() == 1 {
+                    broken = false;
+                }
+                let kind = if let Token::LParen {..} = t { SyntaxKind::Form } else { SyntaxKind::List };
+                let opening = token(if kind == SyntaxKind::Form { SyntaxKind::LParen } else { SyntaxKind::LBracket }, text);
+                stack.push(Frame { kind, opening: Some(t), children: vec![opening] });
+            }
+            Token::RParen {..} | Token::RBracket {..} => {
+                let kind = if let Token::RParen {..} = t { SyntaxKind::RParen } else { SyntaxKind::RBracket };
+                // Quotes can't be closed, so any left open are dangling
+                while stack.last().unwrap().kind == SyntaxKind::Quoted {
+                    let quoted = stack.pop().unwrap();
+                    errors.push(dangling_quote_error(quoted.opening.as_ref().unwrap()));
+                    finish(&mut stack, quoted);
+                }
+                let top = stack.last_mut().unwrap();
+                let Some(opening) = &top.opening else {
+                    errors.push(unexpected_error(&t));
+                    top.children.push(token(kind, text));
+                    continue;
+                };
+                let closes_list = kind == SyntaxKind::RBracket;
+                if closes_list != (top.kind == SyntaxKind::List) && !broken {
+                    // Mismatched, but close the innermost one anyway
+                    errors.push(mismatched_error(opening, &t));
+                    broken = true;
+                }
+                top.children.push(token(kind, text));
+                let frame = stack.pop().unwrap();
+                finish(&mut stack, frame);
+            }
+            Token::Quote {..} => {
+                let quote = token(SyntaxKind::Quote, text);
+                stack.push(Frame { kind: SyntaxKind::Quoted, opening: Some(t), children: vec![quote] });
+            }
+            Token::Comment {..} => {
+                stack.last_mut().unwrap().children.push(token(SyntaxKind::Comment, text));
+            }
+            Token::Symbol {..} | Token::StrLit {..} => {
+                let kind = if let Token::Symbol {..} = t { SyntaxKind::Symbol } else { SyntaxKind::Str };
+                stack.last_mut().unwrap().children.push(token(kind, text));
+                close_quotes(&mut stack);
+            }
+        }
+    }
+    gap(&mut stack, &source[offset..]);
+    errors.extend(lexer.into_errors());
+
+    // Close everything left open at the end of the file
+    let unbalanced = outermost(&stack).is_some();
+    match outermost(&stack) {
+        Some(opening) if !broken => errors.push(unclosed_error(opening)),
+        Some(_) => {}
+        None => {
+            if let Some(quote) = stack.get(1).and_then(|f| f.opening.as_ref()) {
+                errors.push(dangling_quote_error(quote));
+            }
+        }
+    }
+    close_all(&mut stack);
+    errors.sort_by_key(|e| (e.span.start.line, e.span.start.column));
+
+    let root = stack.pop().unwrap();
+    let green = Rc::new(GreenNode::new(SyntaxKind::Root, root.children));
+    let mut lines = vec![0];
+    lines.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    let tree = Tree { file, source: source.to_string(), lines };
+    (SyntaxNode::new_root(green, tree), errors, unbalanced)
+}
+
+// The opening token of the outermost form or list being read
+fn outermost(stack: &[Frame]) -> Option<&Token> {
+    stack.iter()
+         .find(|f| matches!(f.kind, SyntaxKind::Form | SyntaxKind::List))
+         .and_then(|f| f.opening.as_ref())
+}
+
+// Finish every node being read, leaving only the root
+fn close_all(stack: &mut Vec<Frame>) {
+    while stack.len() > 1 {
+        let frame = stack.pop().unwrap();
+        let node = GreenNode::new(frame.kind, frame.children);
+        stack.last_mut().unwrap().children.push(GreenElement::Node(Rc::new(node)));
+    }
+}
+
+// Add a finished node to its parent, which completes any quotes waiting for it
+fn finish(stack: &mut Vec<Frame>, frame: Frame) {
+    let node = GreenNode::new(frame.kind, frame.children);
+    stack.last_mut().unwrap().children.push(GreenElement::Node(Rc::new(node)));
+    if frame.kind != SyntaxKind::Quoted {
+        close_quotes(stack);
+    }
+}
+
+// Finish every quote which now has its expression
+fn close_quotes(stack: &mut Vec<Frame>) {
+    while stack.last().is_some_and(|f| f.kind == SyntaxKind::Quoted) {
+        let quoted = stack.pop().unwrap();
+        let node = GreenNode::new(quoted.kind, quoted.children);
+        stack.last_mut().unwrap().children.push(GreenElement::Node(Rc::new(node)));
+    }
+}
@@ -0,0 +1,279 @@
+use std::error::Error;
+use std::fmt;
+use crate::parse::{ FileId, ParseError, Span };
+
+/// Identifies a kind of error, so it can be looked up and explained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// An expression is never closed
+    UnclosedParen,
+    /// A list is never closed, or closed with a paren
+    UnclosedBracket,
+    /// A closing paren without a matching opening one
+    UnexpectedParen,
+    /// A closing bracket without a matching opening one
+    UnexpectedBracket,
+    /// Several atoms outside of any expression
+    MissingOpening,
+    /// A well-formed expression which isn't a valid form
+    InvalidForm,
+    /// A number which can't be represented
+    InvalidLiteral,
+    /// An unknown or malformed escape sequence in a string
+    InvalidEscape,
+    /// A string which is never closed
+    UnterminatedString,
+    /// A quote, like `'`, without an expression to quote
+    DanglingQuote,
+    /// A malformed macro definition or template
+    InvalidMacro,
+    /// A macro use which none of the macro's rules match
+    NoMatchingRule,
+    /// The same keyword argument given twice in one form
+    DuplicateKeyword,
+    /// A keyword at the end of a form, without a value
+    MissingKeywordValue,
+    /// A name which isn't defined where it's used
+    UnboundName,
+    /// A variable with the same name as another one in scope
+    ShadowedName,
+    /// An expression whose type isn't the one it's used as
+    TypeMismatch,
+    /// A type which isn't known, or is written wrong
+    InvalidType,
+}
+
+impl ErrorCode {
+    /// The general advice for fixing this kind of error
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            Self::UnclosedParen => Some("add a `)` where the expression should end"),
+            Self::UnclosedBracket => Some("add a `]` where the list should end"),
+            Self::UnexpectedParen => Some("remove this `)`, or add a matching `(` before it"),
+            Self::UnexpectedBracket => Some("remove this `]`, or add a matching `[` before it"),
+            Self::MissingOpening => Some("wrap the expression in parentheses"),
+            Self::InvalidForm => None,
+            Self::InvalidLiteral => None,
+            Self::InvalidEscape => None,
+            Self::UnterminatedString => Some("add a closing `\"`"),
+            Self::DanglingQuote => Some("remove the quote, or add an expression after it"),
+            Self::InvalidMacro => None,
+            Self::NoMatchingRule => None,
+            Self::DuplicateKeyword => Some("remove one of the values"),
+            Self::MissingKeywordValue => Some("add a value after the keyword, or remove it"),
+            Self::UnboundName => Some("check the spelling, or define the name before it's used"),
+            Self::ShadowedName => Some("rename one of them if they aren't meant to be the same"),
+            Self::TypeMismatch => None,
+            Self::InvalidType => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = match self {
+            Self::UnclosedParen => 1,
+            Self::UnclosedBracket => 2,
+            Self::UnexpectedParen => 3,
+            Self::UnexpectedBracket => 4,
+            Self::MissingOpening => 5,
+            Self::InvalidForm => 6,
+            Self::InvalidLiteral => 7,
+            Self::InvalidEscape => 8,
+            Self::UnterminatedString => 9,
+            Self::DanglingQuote => 10,
+            Self::InvalidMacro => 11,
+            Self::NoMatchingRule => 12,
+            Self::DuplicateKeyword => 13,
+            Self::MissingKeywordValue => 14,
+            Self::UnboundName => 15,
+            Self::ShadowedName => 16,
+            Self::TypeMismatch => 17,
+            Self::InvalidType => 18,
+        };
+        write!(f, "E{n:04}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Error => "\x1b[1;31m",
+            Self::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A source file which diagnostics can point into
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+/// Every source file known to the reader, indexed by `FileId`
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, source: &str) -> FileId {
+        self.files.push(SourceFile { name: name.to_string(), source: source.to_string() });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+
+    /// A line of a file, counting from 1 like locations do
+    pub fn line(&self, file: FileId, line: usize) -> Option<&str> {
+        self.get(file)?.source.lines().nth(line.checked_sub(1)?)
+    }
+}
+
+/// A message about a span of source code, with optional advice
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<ErrorCode>,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: None,
+            message,
+            span,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn warning(message: String, span: Span) -> Self {
+        Self { severity: Severity::Warning, ..Self::error(message, span) }
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    /// Render the diagnostic like so, with ANSI colors if `color` is set:
+    ///
+    /// ```text
+    /// error[E0001]: Missing closing parentheses
+    ///  --> test.loom:1:1
+    ///   |
+    /// 1 | (foo (bar)
+    ///   | ^
+    ///   = help: add a `)` where the expression should end
+    /// ```
+    pub fn render(&self, sources: &SourceMap, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color { format!("{style}{text}\x1b[0m") } else { text.to_string() }
+        };
+        let accent = self.severity.color();
+        let gutter_style = "\x1b[1;34m";
+
+        let mut heading = self.severity.to_string();
+        if let Some(code) = self.code {
+            heading.push_str(&format!("[{code}]"));
+        }
+        let mut out = format!(
+            "{}{}\n",
+            paint(accent, &heading),
+            paint("\x1b[1m", &format!(": {}", self.message)),
+        );
+
+        let start = self.span.start;
+        let line = sources.line(self.span.file, start.line);
+        let width = start.line.to_string().len();
+        let gutter = paint(gutter_style, &format!("{:width$} |", ""));
+        let name = sources.get(self.span.file).map(|f| f.name.as_str()).unwrap_or("<input>");
+        out.push_str(&format!(
+            "{}{} {name}:{start}\n",
+            " ".repeat(width),
+            paint(gutter_style, "-->"),
+        ));
+
+        if let Some(line) = line {
+            // Underline up to the end of the span, or of the line if the span
+            // continues past it
+            let length = line.chars().count();
+            let first = start.column.max(1);
+            let last = if self.span.end.line == start.line {
+                self.span.end.column.max(first)
+            } else {
+                length.max(first)
+            };
+            let marker = "^".repeat(last - first + 1);
+            out.push_str(&format!("{gutter}\n"));
+            out.push_str(&format!(
+                "{} {line}\n",
+                paint(gutter_style, &format!("{} |", start.line)),
+            ));
+            out.push_str(&format!("{gutter} {}{}\n", " ".repeat(first - 1), paint(accent, &marker)));
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{}{} {note}\n", " ".repeat(width + 1), paint("\x1b[1m", "= note:")));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("{}{} {help}\n", " ".repeat(width + 1), paint("\x1b[1m", "= help:")));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl Error for Diagnostic {}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Self {
+        let mut diagnostic = Diagnostic::error(e.message.clone(), e.span).with_code(e.code);
+        diagnostic.notes = e.notes.clone();
+        diagnostic.help = e.help.clone().or_else(|| e.code.help().map(str::to_string));
+        diagnostic
+    }
+}
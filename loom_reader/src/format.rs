@@ -0,0 +1,309 @@
+//! The canonical layout of Loom code, as printed by `loom fmt`.
+//!
+//! An expression stays on one line if it fits. Otherwise its head and the
+//! arguments which belong with it (like the name and parameters of a `fn`)
+//! stay on the first line, every other argument goes on its own indented
+//! line, and the closing paren gets a line of its own. Keyword arguments stay
+//! next to their values, in the order they were written, and comments and
+//! blank lines between expressions are kept.
+
+use crate::cst::{ self, SyntaxElement, SyntaxKind, SyntaxNode };
+use crate::parse::{ FileId, ParseError };
+
+/// How long lines can get before expressions are broken over several lines
+pub const WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+/// Format a source file, unless it has syntax errors.
+pub fn format(source: &str, file: FileId) -> Result<String, Vec<ParseError>> {
+    let (root, errors) = cst::parse(source, file);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut out = String::new();
+    for item in items(&root) {
+        match item {
+            Item::Blank => {
+                newline(&mut out);
+                out.push('\n');
+            }
+            Item::Comment(text, true) if !out.is_empty() => {
+                out.push(' ');
+                out.push_str(&text);
+            }
+            Item::Comment(text, _) => {
+                newline(&mut out);
+                out.push_str(&text);
+            }
+            Item::Exp(x, same_line) => {
+                if same_line && !out.is_empty() {
+                    out.push(' ');
+                } else {
+                    newline(&mut out);
+                }
+                let col = last_line(&out).chars().count();
+                out.push_str(&render(&x, 0, col));
+            }
+        }
+    }
+    newline(&mut out);
+    Ok(out)
+}
+
+// Something between the brackets of a node, or at the top level
+enum Item {
+    /// An expression, and whether it was on the same line as the previous
+    /// item
+    Exp(SyntaxElement, bool),
+    /// A comment, and whether it followed an item on the same line
+    Comment(String, bool),
+    /// A blank line between two items
+    Blank,
+}
+
+fn items(node: &SyntaxNode) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut newlines = 0;
+    for child in node.children_with_tokens() {
+        let first = items.is_empty();
+        match child.kind() {
+            SyntaxKind::Whitespace => newlines += child.text().matches('\n').count(),
+            SyntaxKind::Comment => {
+                if newlines > 1 && !first {
+                    items.push(Item::Blank);
+                }
+                items.push(Item::Comment(child.text().trim_end().to_string(), newlines == 0 && !first));
+                newlines = 0;
+            }
+            _ if child.is_expression() => {
+                if newlines > 1 && !first {
+                    items.push(Item::Blank);
+                }
+                items.push(Item::Exp(child, newlines == 0));
+                newlines = 0;
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+// Render an expression starting at column `col` of a line indented by
+// `indent`
+fn render(x: &SyntaxElement, indent: usize, col: usize) -> String {
+    if let Some(flat) = flat(x) {
+        if col + flat.chars().count() <= WIDTH {
+            return flat;
+        }
+    }
+    let SyntaxElement::Node(node) = x else {
+        return x.text().to_string();
+    };
+    match node.kind() {
+        SyntaxKind::Quoted => render_quoted(node, indent, col),
+        SyntaxKind::Form | SyntaxKind::List => {
+            hang(node, indent, col).unwrap_or_else(|| render_broken(node, indent, col))
+        }
+        _ => node.text().to_string(),
+    }
+}
+
+// An expression on a single line, unless it has to be broken up
+fn flat(x: &SyntaxElement) -> Option<String> {
+    let SyntaxElement::Node(node) = x else {
+        return Some(x.text().to_string());
+    };
+    if always_broken(node) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    let mut quote = String::new();
+    for child in node.children_with_tokens() {
+        match child.kind() {
+            SyntaxKind::Comment => return None,
+            SyntaxKind::Quote => quote = child.text().to_string(),
+            _ if child.is_expression() => parts.push(flat(&child)?),
+            _ => {}
+        }
+    }
+    Some(match node.kind() {
+        SyntaxKind::Quoted => format!("{quote}{}", parts.concat()),
+        SyntaxKind::List => format!("[{}]", parts.join(" ")),
+        _ => format!("({})", parts.join(" ")),
+    })
+}
+
+// Function bodies, and bodies of several expressions, always get a line per
+// expression
+fn always_broken(node: &SyntaxNode) -> bool {
+    if node.kind() != SyntaxKind::Form {
+        return false;
+    }
+    let expressions = node.expressions();
+    let Some(head) = expressions.first() else {
+        return false;
+    };
+    let body = expressions.len() - 1 - header_length(&expressions);
+    match head.text() {
+        "fn" | "defmacro" => body > 0,
+        "do" | "while" | "let" | "let*" | "let-values" | "when" | "unless" | "cond" => body > 1,
+        _ => false,
+    }
+}
+
+// How many arguments of a form stay on the same line as its head
+fn header_length(expressions: &[SyntaxElement]) -> usize {
+    let Some(head) = expressions.first() else {
+        return 0;
+    };
+    let args = &expressions[1..];
+    let positional = args.iter().take_while(|a| !is_keyword(a)).count();
+    let length = match head.text() {
+        "fn" => {
+            // `(fn name [params] [kwparams] ...)`, where the name is optional
+            let named = args.first().is_some_and(|a| a.kind() == SyntaxKind::Symbol) as usize;
+            named + args[named..].iter().take(2).take_while(|a| a.kind() == SyntaxKind::List).count()
+        }
+        "defmacro" => 2,
+        "def" | "set" | "if" | "while" | "when" | "unless" | "let" | "let*" | "let-values"
+        | "define-syntax" | "syntax-rules" | "signal" => 1,
+        _ => 0,
+    };
+    length.min(positional)
+}
+
+fn is_keyword(x: &SyntaxElement) -> bool {
+    x.kind() == SyntaxKind::Symbol && x.text().len() > 1 && x.text().starts_with(':')
+}
+
+// Put a function on the first line of the form it's the last argument of,
+// when that's its only argument besides the header, like
+// `(def main (fn [] ...))`
+fn hang(node: &SyntaxNode, indent: usize, col: usize) -> Option<String> {
+    if node.kind() != SyntaxKind::Form || always_broken(node) {
+        return None;
+    }
+    let items = items(node);
+    if items.iter().any(|i| !matches!(i, Item::Exp(..))) {
+        return None;
+    }
+    let expressions = node.expressions();
+    let header = header_length(&expressions);
+    let [first @ .., last] = expressions.as_slice() else {
+        return None;
+    };
+    let SyntaxElement::Node(function) = last else {
+        return None;
+    };
+    let is_function = function.kind() == SyntaxKind::Form
+        && function.expressions().first().is_some_and(|head| head.text() == "fn");
+    if header == 0 || expressions.len() != header + 2 || !is_function {
+        return None;
+    }
+    let mut line = String::from("(");
+    for (i, x) in first.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        line.push_str(&flat(x)?);
+    }
+    line.push(' ');
+    let hung = render(last, indent, col + line.chars().count());
+    if col + line.chars().count() + hung.lines().next()?.chars().count() > WIDTH {
+        return None;
+    }
+    Some(format!("{line}{hung})"))
+}
+
+fn render_broken(node: &SyntaxNode, indent: usize, col: usize) -> String {
+    let (open, close) = if node.kind() == SyntaxKind::List { ("[", "]") } else { ("(", ")") };
+    let items = items(node);
+    let expressions = node.expressions();
+    let header = if node.kind() == SyntaxKind::Form { 1 + header_length(&expressions) } else { 0 };
+
+    let mut out = String::from(open);
+    let mut rest = items.into_iter().peekable();
+    // The head and header stay on the first line, up to the first comment
+    let mut taken = 0;
+    while taken < header {
+        let Some(Item::Exp(x, _)) = rest.next_if(|i| matches!(i, Item::Exp(..))) else {
+            break;
+        };
+        if taken > 0 {
+            out.push(' ');
+        }
+        let col = column(&out, col);
+        out.push_str(&render(&x, indent, col));
+        taken += 1;
+    }
+
+    let inner = indent + INDENT;
+    while let Some(item) = rest.next() {
+        match item {
+            Item::Blank => out.push('\n'),
+            Item::Comment(text, true) if out.len() > open.len() => {
+                out.push(' ');
+                out.push_str(&text);
+            }
+            Item::Comment(text, _) => {
+                out.push_str(&format!("\n{}{text}", " ".repeat(inner)));
+            }
+            Item::Exp(x, _) => {
+                out.push_str(&format!("\n{}", " ".repeat(inner)));
+                let next_is_value = matches!(rest.peek(), Some(Item::Exp(..)));
+                if is_keyword(&x) && next_is_value {
+                    // Keep keyword arguments with their values
+                    let Some(Item::Exp(value, _)) = rest.next() else {
+                        unreachable!();
+                    };
+                    out.push_str(x.text());
+                    out.push(' ');
+                    let col = column(&out, col);
+                    out.push_str(&render(&value, inner, col));
+                } else {
+                    out.push_str(&render(&x, inner, inner));
+                }
+            }
+        }
+    }
+    out.push_str(&format!("\n{}{close}", " ".repeat(indent)));
+    out
+}
+
+// A quote and the expression it quotes, keeping any comments between them
+fn render_quoted(node: &SyntaxNode, indent: usize, col: usize) -> String {
+    let mut out = String::new();
+    for child in node.children_with_tokens() {
+        match child.kind() {
+            SyntaxKind::Quote => out.push_str(child.text()),
+            SyntaxKind::Comment => {
+                out.push_str(child.text().trim_end());
+                out.push_str(&format!("\n{}", " ".repeat(indent)));
+            }
+            _ if child.is_expression() => {
+                let col = column(&out, col);
+                out.push_str(&render(&child, indent, col));
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// The column at the end of some output, which started at column `col`
+fn column(out: &str, col: usize) -> usize {
+    match out.rfind('\n') {
+        Some(i) => out[i + 1..].chars().count(),
+        None => col + out.chars().count(),
+    }
+}
+
+fn last_line(out: &str) -> &str {
+    out.rsplit('\n').next().unwrap_or_default()
+}
+
+fn newline(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
@@ -1,4 +1,6 @@
-use crate::parse::Exp::{ self, * };
+use std::error::Error;
+use crate::diagnostics::ErrorCode;
+use crate::parse::{ ParseError, Exp::{ self, * } };
 
 #[derive(Debug)]
 pub enum Form {
@@ -7,24 +9,37 @@ pub enum Form {
 }
 
 impl Form {
-    pub fn from_exp(x: Exp) -> Self {
+    pub fn from_exp(x: Exp) -> Result<Self, Box<dyn Error>> {
         match x {
-            SExp { kind, args, kwargs } => {
+            SExp { kind, args, span, .. } => {
                 match *kind {
-                    Atom(atom) => {
+                    Symbol(atom, _) => {
                         match atom.as_str() {
                             "def" => {
-                                let Some(Atom(key)) = args.get(0) else { todo!() };
-                                let Some(value) = args.get(1) else { todo!() };
-                                Self::Define(key.clone(), value.clone())
+                                let Some(Symbol(key, _)) = args.first() else {
+                                    let span = args.first().map(Exp::span).unwrap_or(span);
+                                    return Err(ParseError::boxed(
+                                        ErrorCode::InvalidForm,
+                                        "Expected a name to define".to_string(),
+                                        span,
+                                    ));
+                                };
+                                let Some(value) = args.get(1) else {
+                                    return Err(ParseError::boxed(
+                                        ErrorCode::InvalidForm,
+                                        format!("Missing a value for '{key}'"),
+                                        span,
+                                    ));
+                                };
+                                Ok(Self::Define(key.clone(), value.clone()))
                             }
-                            _ => Self::Unknown
+                            _ => Ok(Self::Unknown)
                         }
                     }
-                    _ => Self::Unknown
+                    _ => Ok(Self::Unknown)
                 }
             }
-            _ => Self::Unknown
+            _ => Ok(Self::Unknown)
         }
     }
 }
@@ -0,0 +1,297 @@
+use std::ops::Range;
+use crate::diagnostics::ErrorCode;
+use crate::parse::{ FileId, Location, ParseError, Span, Token };
+
+/// A token, along with the bytes of the source it was read from
+#[derive(Debug, Clone)]
+pub struct Lexeme {
+    pub token: Token,
+    pub range: Range<usize>,
+}
+
+/// Reads tokens from a source string one at a time.
+///
+/// Tokens have spans in lines and columns (counting characters), and byte
+/// ranges into the source. Errors in string literals don't stop the lexer,
+/// they're collected until `errors` is called.
+///
+/// After an edit, the source only has to be lexed again from the start of the
+/// first token the edit touched:
+///
+/// ```
+/// use loom_reader::lexer::Lexer;
+///
+/// let source = "(print (+ 1 2))";
+/// let edited = "(print (+ 10 2))";
+/// let tokens: Vec<_> = Lexer::new(source, Default::default()).collect();
+/// // The edit is at byte 11, in the fifth token
+/// let start = tokens[4].range.start;
+/// let relexed: Vec<_> = Lexer::at(edited, Default::default(), start).collect();
+/// assert_eq!(relexed[0].token.to_string(), "10");
+/// assert_eq!(relexed[0].token.span().start.column, 11);
+/// ```
+#[derive(Debug)]
+pub struct Lexer<'a> {
+    source: &'a str,
+    file: FileId,
+    /// The byte offset of the next character
+    offset: usize,
+    /// The location of the last character read, with a column of 0 at the
+    /// start of a line
+    location: Location,
+    errors: Vec<ParseError>,
+}
+
+// An escape sequence being read in a string, with the location of its
+// backslash
+enum Escape {
+    Start(Location),
+    /// `\u`, then the hex digits once the opening brace has been read
+    Unicode(Location, Option<String>),
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str, file: FileId) -> Self {
+        Self {
+            source,
+            file,
+            offset: 0,
+            location: Location::new(1, 0),
+            errors: Vec::new(),
+        }
+    }
+
+    /// A lexer starting at a byte offset, which should be the start of a token
+    /// or whitespace between tokens, not the middle of a string or comment.
+    ///
+    /// Panics if the offset isn't on a character boundary.
+    pub fn at(source: &'a str, file: FileId, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count();
+        Self { offset, location: Location::new(line, column), ..Self::new(source, file) }
+    }
+
+    /// The byte offset of the next character to read
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The errors found so far
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<ParseError> {
+        self.errors
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    // Read the next character, returning it with its location
+    fn bump(&mut self) -> Option<(char, Location)> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.location = Location::new(self.location.line + 1, 0);
+        } else {
+            self.location.column += 1;
+        }
+        Some((c, self.location))
+    }
+
+    fn span(&self, start: Location, end: Location) -> Span {
+        Span::new(self.file, start, end)
+    }
+
+    // Read the token starting with `c`, or nothing if it's a broken string
+    fn token(&mut self, c: char, here: Location) -> Option<Token> {
+        let point = self.span(here, here);
+        Some(match c {
+            '(' => Token::LParen { span: point },
+            ')' => Token::RParen { span: point },
+            '[' => Token::LBracket { span: point },
+            ']' => Token::RBracket { span: point },
+            '\"' => return self.string(here, None),
+            ';' => {
+                let start = self.offset;
+                let mut end = here;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    (_, end) = self.bump()?;
+                }
+                let content = self.source[start..self.offset].to_string();
+                Token::Comment { content, span: self.span(here, end) }
+            }
+            '\'' => Token::Quote { form: "quote", span: point },
+            '`' => Token::Quote { form: "quasiquote", span: point },
+            ',' if self.peek() == Some('@') => {
+                // `,@` splices the list into the enclosing one
+                let (_, end) = self.bump()?;
+                Token::Quote { form: "unquote-splicing", span: self.span(here, end) }
+            }
+            ',' => Token::Quote { form: "unquote", span: point },
+            _ => {
+                let mut content = String::from(c);
+                let mut end = here;
+                while let Some(c) = self.peek().filter(|c| !ends_symbol(*c)) {
+                    content.push(c);
+                    (_, end) = self.bump()?;
+                }
+                // `r"..."`, or `r#"..."#` with any number of `#`s
+                let raw_hashes = content.strip_prefix('r')
+                                        .filter(|h| h.chars().all(|c| c == '#'))
+                                        .map(|h| h.len());
+                if let (Some('\"'), Some(hashes)) = (self.peek(), raw_hashes) {
+                    self.bump();
+                    return self.string(here, Some(hashes));
+                }
+                Token::Symbol { content, span: self.span(here, end) }
+            }
+        })
+    }
+
+    // Read the rest of a string, which is raw if it has a number of `#`s
+    fn string(&mut self, start: Location, raw: Option<usize>) -> Option<Token> {
+        let mut content = String::new();
+        let mut escape: Option<Escape> = None;
+        loop {
+            let prev = self.location;
+            let Some((c, here)) = self.bump() else {
+                let closing = format!("\"{}", "#".repeat(raw.unwrap_or(0)));
+                self.errors.push(ParseError::new(
+                    ErrorCode::UnterminatedString,
+                    "String is never closed".to_string(),
+                    self.span(start, start),
+                ).with_help(format!("add a closing `{closing}`")));
+                return None;
+            };
+            if let Some(hashes) = raw {
+                let closing = "#".repeat(hashes);
+                if c == '\"' && self.source[self.offset..].starts_with(&closing) {
+                    let mut end = here;
+                    for _ in 0..hashes {
+                        (_, end) = self.bump()?;
+                    }
+                    return Some(Token::StrLit { content, span: self.span(start, end) });
+                }
+                content.push(c);
+                continue;
+            }
+            if let Some(e) = escape.take() {
+                let consumed;
+                (escape, consumed) = continue_escape(e, c, prev, here, self.file, &mut content, &mut self.errors);
+                if consumed {
+                    continue;
+                }
+            }
+            match c {
+                '\\' => {
+                    escape = Some(Escape::Start(here));
+                }
+                '\"' => {
+                    return Some(Token::StrLit { content, span: self.span(start, here) });
+                }
+                _ => {
+                    content.push(c);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Lexeme;
+
+    fn next(&mut self) -> Option<Lexeme> {
+        loop {
+            while self.peek().is_some_and(char::is_whitespace) {
+                self.bump();
+            }
+            let start = self.offset;
+            let (c, here) = self.bump()?;
+            // Broken strings are left out, there's an error for them instead
+            if let Some(token) = self.token(c, here) {
+                return Some(Lexeme { token, range: start..self.offset });
+            }
+        }
+    }
+}
+
+fn ends_symbol(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '\"' | ';')
+}
+
+// Continue reading an escape sequence with the character `c`, returning the
+// escape if it isn't done yet, and whether `c` was part of it
+fn continue_escape(
+    escape: Escape,
+    c: char,
+    prev: Location,
+    here: Location,
+    file: FileId,
+    content: &mut String,
+    errors: &mut Vec<ParseError>,
+) -> (Option<Escape>, bool) {
+    match escape {
+        Escape::Start(start) => {
+            match c {
+                '\"' => content.push('\"'),
+                '\\' => content.push('\\'),
+                'n' => content.push('\n'),
+                't' => content.push('\t'),
+                'r' => content.push('\r'),
+                'u' => return (Some(Escape::Unicode(start, None)), true),
+                _ => {
+                    errors.push(ParseError::new(
+                        ErrorCode::InvalidEscape,
+                        format!("Unknown escape sequence '\\{c}'"),
+                        Span::new(file, start, here),
+                    ).with_note("the supported escapes are \\\" \\\\ \\n \\t \\r and \\u{...}".to_string())
+                     .with_help("write a backslash as `\\\\`".to_string()));
+                }
+            }
+            (None, true)
+        }
+        Escape::Unicode(start, None) => {
+            if c == '{' {
+                return (Some(Escape::Unicode(start, Some(String::new()))), true);
+            }
+            errors.push(ParseError::new(
+                ErrorCode::InvalidEscape,
+                "Expected '{' after '\\u'".to_string(),
+                Span::new(file, start, prev),
+            ).with_help("write unicode escapes like `\\u{1F600}`".to_string()));
+            (None, false)
+        }
+        Escape::Unicode(start, Some(mut digits)) => {
+            if c.is_ascii_hexdigit() {
+                digits.push(c);
+                return (Some(Escape::Unicode(start, Some(digits))), true);
+            }
+            if c != '}' {
+                errors.push(ParseError::new(
+                    ErrorCode::InvalidEscape,
+                    "Unicode escape is missing its closing '}'".to_string(),
+                    Span::new(file, start, prev),
+                ).with_help("write unicode escapes like `\\u{1F600}`".to_string()));
+                return (None, false);
+            }
+            let code = u32::from_str_radix(&digits, 16).ok().filter(|_| digits.len() <= 6);
+            match code.and_then(char::from_u32) {
+                Some(ch) => content.push(ch),
+                None => {
+                    errors.push(ParseError::new(
+                        ErrorCode::InvalidEscape,
+                        format!("Invalid unicode escape '\\u{{{digits}}}'"),
+                        Span::new(file, start, here),
+                    ).with_note("unicode escapes must be 1 to 6 hex digits naming a character".to_string()));
+                }
+            }
+            (None, true)
+        }
+    }
+}
+
@@ -1,2 +1,7 @@
+pub mod lexer;
+pub mod cst;
+pub mod format;
 pub mod parse;
 pub mod forms;
+pub mod diagnostics;
+pub mod macros;
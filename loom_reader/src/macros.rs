@@ -0,0 +1,599 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use crate::diagnostics::ErrorCode;
+use crate::parse::{ Exp, FileId, ParseError, Span, read_file_expressions };
+
+/// Macros which are always available, written in Loom
+pub const PRELUDE: &str = r#"
+(define-syntax when
+    (syntax-rules ()
+        ((_ test body ...) (if test (do body ...)))))
+
+(define-syntax unless
+    (syntax-rules ()
+        ((_ test body ...) (if test nil (do body ...)))))
+
+(define-syntax cond
+    (syntax-rules (else)
+        ((_) nil)
+        ((_ (else body ...)) (do body ...))
+        ((_ (test body ...) clause ...) (if test (do body ...) (cond clause ...)))))
+
+(define-syntax let*
+    (syntax-rules ()
+        ((_ [] body ...) (let [] body ...))
+        ((_ [name value rest ...] body ...) (let [name value] (let* [rest ...] body ...)))))
+"#;
+
+/// How many times a form may be expanded before giving up, which catches
+/// macros expanding into themselves
+const MAX_EXPANSIONS: usize = 1000;
+
+/// A `syntax-rules` macro
+#[derive(Debug, Clone)]
+pub struct SyntaxRules {
+    /// Symbols which match themselves, instead of being pattern variables
+    literals: Vec<String>,
+    rules: Vec<(Exp, Exp)>,
+}
+
+/// A macro written in Loom, given the whole form it's used in and returning
+/// its expansion. The reader can't run Loom code, so these are defined by
+/// whoever evaluates `defmacro`.
+pub type Procedure = Rc<dyn Fn(&Exp) -> Result<Exp, Box<dyn Error>>>;
+
+#[derive(Clone)]
+enum Macro {
+    Rules(SyntaxRules),
+    Procedure(Procedure),
+}
+
+impl fmt::Debug for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rules(rules) => write!(f, "{rules:?}"),
+            Self::Procedure(_) => write!(f, "<procedure>"),
+        }
+    }
+}
+
+// What a pattern variable matched, with one level of nesting per ellipsis
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Exp),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+/// Expands macro uses in expressions, remembering the macros defined with
+/// `define-syntax` along the way.
+#[derive(Debug, Clone)]
+pub struct Expander {
+    macros: HashMap<String, Macro>,
+    /// Used to give fresh names to the variables a macro introduces
+    renames: usize,
+    /// The free symbols macros have inserted into the expression being
+    /// expanded, which refer to where the macro was defined, so mustn't be
+    /// captured by the variables around the macro's use
+    inserted: HashSet<String>,
+}
+
+impl Default for Expander {
+    fn default() -> Self {
+        let mut expander = Self::empty();
+        let prelude = read_file_expressions(PRELUDE.to_string(), FileId::default())
+            .expect("The prelude should be valid");
+        for x in prelude {
+            expander.expand_top(&x).expect("The prelude should be valid");
+        }
+        expander
+    }
+}
+
+impl Expander {
+    /// An expander without the prelude's macros
+    pub fn empty() -> Self {
+        Self { macros: HashMap::new(), renames: 0, inserted: HashSet::new() }
+    }
+
+    pub fn is_macro(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    /// Define a procedural macro
+    pub fn define_procedure(&mut self, name: &str, procedure: Procedure) {
+        self.macros.insert(name.to_string(), Macro::Procedure(procedure));
+    }
+
+    /// Expand every top-level form, leaving out macro definitions.
+    pub fn expand_all(&mut self, forms: Vec<Exp>) -> Result<Vec<Exp>, Box<dyn Error>> {
+        let mut expanded = Vec::new();
+        for x in forms {
+            if let Some(x) = self.expand_top(&x)? {
+                expanded.push(x);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Expand a top-level form, or define a macro if it's a `define-syntax`
+    /// form, in which case there's nothing left to evaluate.
+    pub fn expand_top(&mut self, x: &Exp) -> Result<Option<Exp>, Box<dyn Error>> {
+        if x.car_symbol().as_deref() == Some("define-syntax") {
+            self.define(x)?;
+            return Ok(None);
+        }
+        self.inserted.clear();
+        Ok(Some(self.expand(x)?))
+    }
+
+    /// Expand every macro use in an expression.
+    pub fn expand(&mut self, x: &Exp) -> Result<Exp, Box<dyn Error>> {
+        let mut x = x.clone();
+        let mut expansions = 0;
+        while let Some(name) = x.car_symbol().filter(|name| self.is_macro(name)) {
+            expansions += 1;
+            if expansions > MAX_EXPANSIONS {
+                return Err(ParseError::boxed(
+                    ErrorCode::InvalidMacro,
+                    format!("Macro '{name}' keeps expanding into itself"),
+                    x.span(),
+                ));
+            }
+            x = self.expand_once(&name, &x)?;
+        }
+
+        // A variable the expression binds would capture a symbol a macro
+        // inserts into its body, so rename the variable and expand again
+        let binders = local_binders(&x);
+        if binders.is_empty() {
+            return self.expand_parts(x);
+        }
+        let outer = std::mem::take(&mut self.inserted);
+        let mut expanded = self.expand_parts(x.clone())?;
+        let captured: Vec<String> = binders.into_iter().filter(|b| self.inserted.contains(b)).collect();
+        if !captured.is_empty() {
+            let renamed = self.rename_binders(&x, &captured);
+            expanded = self.expand_parts(renamed)?;
+        }
+        self.inserted.extend(outer);
+        Ok(expanded)
+    }
+
+    // Expand every macro use in the parts of an expression which isn't a
+    // macro use itself
+    fn expand_parts(&mut self, x: Exp) -> Result<Exp, Box<dyn Error>> {
+        match x {
+            Exp::SExp { kind, args, kwargs, span } => {
+                if matches!(kind.as_symbol().as_deref(), Some("quote") | Some("define-syntax")) {
+                    return Ok(Exp::SExp { kind, args, kwargs, span });
+                }
+                let kind = Box::new(self.expand(&kind)?);
+                let args = args.iter()
+                               .map(|a| self.expand(a))
+                               .collect::<Result<Vec<Exp>, _>>()?;
+                let mut expanded = Vec::new();
+                for (k, v) in kwargs {
+                    expanded.push((k, self.expand(&v)?));
+                }
+                Ok(Exp::SExp { kind, args, kwargs: expanded, span })
+            }
+            Exp::List(items, span) => {
+                let items = items.iter()
+                                 .map(|i| self.expand(i))
+                                 .collect::<Result<Vec<Exp>, _>>()?;
+                Ok(Exp::List(items, span))
+            }
+            _ => Ok(x)
+        }
+    }
+
+    /// `(define-syntax name (syntax-rules (literals...) (pattern template)...))`
+    fn define(&mut self, x: &Exp) -> Result<(), Box<dyn Error>> {
+        let invalid = |message: &str, span: Span| {
+            ParseError::boxed(ErrorCode::InvalidMacro, message.to_string(), span)
+        };
+        let Some(name) = x.arg_symbol(0) else {
+            return Err(invalid("Expected a name for the macro", x.span()));
+        };
+        let Some(rules) = x.arg(1) else {
+            return Err(invalid("Expected the macro's syntax-rules", x.span()));
+        };
+        if rules.car_symbol().as_deref() != Some("syntax-rules") {
+            return Err(invalid("Only syntax-rules macros are supported", rules.span()));
+        }
+        let rules_args = rules.args().unwrap_or_default();
+        let Some((literals, clauses)) = rules_args.split_first() else {
+            return Err(invalid("Expected a list of literals", rules.span()));
+        };
+        let literals = match literals {
+            Exp::Nil(_) => Vec::new(),
+            Exp::List(items, _) => items.clone(),
+            Exp::SExp { .. } => literals.items().unwrap_or_default(),
+            _ => return Err(invalid("Expected a list of literals", literals.span())),
+        };
+        let literals = literals.iter().map(|l| {
+            l.as_symbol().ok_or_else(|| invalid("Literals must be symbols", l.span()))
+        }).collect::<Result<Vec<String>, _>>()?;
+
+        let mut parsed = Vec::new();
+        for clause in clauses {
+            let items = match clause {
+                Exp::List(items, _) => items.clone(),
+                _ => clause.items().unwrap_or_default(),
+            };
+            let [pattern, template] = items.as_slice() else {
+                return Err(invalid("Expected a rule like (pattern template)", clause.span()));
+            };
+            if !matches!(pattern, Exp::SExp { .. }) {
+                return Err(invalid("A rule's pattern must be a form like (_ args...)", pattern.span()));
+            }
+            parsed.push((pattern.clone(), template.clone()));
+        }
+        self.macros.insert(name, Macro::Rules(SyntaxRules { literals, rules: parsed }));
+        Ok(())
+    }
+
+    // Expand a single use of a macro, using its first matching rule
+    fn expand_once(&mut self, name: &str, x: &Exp) -> Result<Exp, Box<dyn Error>> {
+        let syntax = match &self.macros[name] {
+            Macro::Rules(syntax) => syntax,
+            Macro::Procedure(procedure) => return procedure(x),
+        };
+        let items = x.items().unwrap_or_default();
+        for (pattern, template) in &syntax.rules {
+            let patterns = pattern.items().unwrap_or_default();
+            let mut bindings = Bindings::new();
+            // The macro's own name is never matched
+            if !match_sequence(&patterns[1..], &items[1..], &syntax.literals, &mut bindings) {
+                continue;
+            }
+            let mut binders = HashSet::new();
+            find_binders(template, &bindings, &mut binders);
+            self.renames += 1;
+            let renames: HashMap<String, String> = binders.into_iter()
+                .map(|b| (b.clone(), format!("{b}%{}", self.renames)))
+                .collect();
+            free_symbols(template, &bindings, &renames, &mut self.inserted);
+            return instantiate(template, &bindings, &renames, x.span());
+        }
+        Err(ParseError::new(
+            ErrorCode::NoMatchingRule,
+            format!("No rule of macro '{name}' matches {x}"),
+            x.span(),
+        ).with_note(format!(
+            "the rules are: {}",
+            syntax.rules.iter().map(|(p, _)| p.to_string()).collect::<Vec<String>>().join(", ")
+        )).into())
+    }
+
+    // Give the variables a `fn`, `let` or `let-values` form binds fresh names,
+    // in the form's body. The values of a `let` are outside of its scope.
+    fn rename_binders(&mut self, x: &Exp, binders: &[String]) -> Exp {
+        self.renames += 1;
+        let renames: HashMap<String, String> = binders.iter()
+            .map(|b| (b.clone(), format!("{b}%{}", self.renames)))
+            .collect();
+        let Exp::SExp { kind, args, kwargs, span } = x else {
+            return x.clone();
+        };
+        let mut args = args.clone();
+        match kind.as_symbol().as_deref() {
+            Some("fn") => {
+                // A function's name isn't local to it
+                let params = args.iter().position(|a| matches!(a, Exp::List(..))).unwrap_or(args.len());
+                for arg in &mut args[params..] {
+                    *arg = rename(arg, &renames);
+                }
+            }
+            _ => {
+                if let Some(Exp::List(pairs, pairs_span)) = args.first() {
+                    let pairs = pairs.iter()
+                                     .enumerate()
+                                     .map(|(i, x)| if i % 2 == 0 { rename(x, &renames) } else { x.clone() })
+                                     .collect();
+                    args[0] = Exp::List(pairs, *pairs_span);
+                }
+                for arg in &mut args[1..] {
+                    *arg = rename(arg, &renames);
+                }
+            }
+        }
+        Exp::SExp { kind: kind.clone(), args, kwargs: kwargs.clone(), span: *span }
+    }
+}
+
+fn is_ellipsis(x: &Exp) -> bool {
+    x.as_symbol().as_deref() == Some("...")
+}
+
+// The items of a form or list, for matching
+fn sequence(x: &Exp) -> Option<Vec<Exp>> {
+    match x {
+        Exp::List(items, _) => Some(items.clone()),
+        Exp::SExp { .. } => x.items(),
+        Exp::Nil(_) => Some(Vec::new()),
+        _ => None
+    }
+}
+
+fn match_pattern(pattern: &Exp, x: &Exp, literals: &[String], bindings: &mut Bindings) -> bool {
+    match pattern {
+        Exp::Symbol(name, _) => {
+            if name == "_" {
+                true
+            } else if literals.contains(name) {
+                x.as_symbol().as_deref() == Some(name)
+            } else {
+                bindings.insert(name.clone(), Binding::One(x.clone()));
+                true
+            }
+        }
+        Exp::List(patterns, _) => match x {
+            Exp::List(items, _) => match_sequence(patterns, items, literals, bindings),
+            _ => false
+        },
+        Exp::SExp { .. } => match (x, sequence(pattern), sequence(x)) {
+            (Exp::SExp { .. }, Some(patterns), Some(items)) => {
+                match_sequence(&patterns, &items, literals, bindings)
+            }
+            _ => false
+        },
+        // Other atoms only match themselves
+        _ => pattern.to_string() == x.to_string()
+    }
+}
+
+// Match items against patterns, where a pattern followed by `...` matches
+// any number of items
+fn match_sequence(patterns: &[Exp], items: &[Exp], literals: &[String], bindings: &mut Bindings) -> bool {
+    let Some(ellipsis) = patterns.iter().position(is_ellipsis) else {
+        return patterns.len() == items.len()
+            && patterns.iter().zip(items).all(|(p, x)| match_pattern(p, x, literals, bindings));
+    };
+    if ellipsis == 0 {
+        return false;
+    }
+    let before = &patterns[..ellipsis - 1];
+    let repeated = &patterns[ellipsis - 1];
+    let after = &patterns[ellipsis + 1..];
+    if items.len() < before.len() + after.len() {
+        return false;
+    }
+    let rest = items.len() - after.len();
+    if !before.iter().zip(items).all(|(p, x)| match_pattern(p, x, literals, bindings)) {
+        return false;
+    }
+    if !after.iter().zip(&items[rest..]).all(|(p, x)| match_pattern(p, x, literals, bindings)) {
+        return false;
+    }
+
+    // Each repetition binds the pattern's variables separately
+    let mut matches: Vec<Bindings> = Vec::new();
+    for x in &items[before.len()..rest] {
+        let mut b = Bindings::new();
+        if !match_pattern(repeated, x, literals, &mut b) {
+            return false;
+        }
+        matches.push(b);
+    }
+    for name in pattern_variables(repeated, literals) {
+        let each = matches.iter_mut()
+                          .map(|b| b.remove(&name).unwrap_or(Binding::Many(Vec::new())))
+                          .collect();
+        bindings.insert(name, Binding::Many(each));
+    }
+    true
+}
+
+fn pattern_variables(pattern: &Exp, literals: &[String]) -> Vec<String> {
+    match pattern {
+        Exp::Symbol(name, _) if name != "_" && name != "..." && !literals.contains(name) => {
+            vec![name.clone()]
+        }
+        _ => sequence(pattern).unwrap_or_default()
+                              .iter()
+                              .flat_map(|p| pattern_variables(p, literals))
+                              .collect()
+    }
+}
+
+// The variables a `fn`, `let` or `let-values` form binds in its body
+fn local_binders(x: &Exp) -> Vec<String> {
+    let name = |x: &Exp| match x {
+        Exp::List(items, _) => items.first().and_then(Exp::as_symbol),
+        _ => x.as_symbol(),
+    };
+    let args = x.args().unwrap_or_default();
+    let names: Vec<&Exp> = match (x.car_symbol().as_deref(), args.as_slice()) {
+        (Some("fn"), [Exp::List(params, _), ..]) | (Some("fn"), [_, Exp::List(params, _), ..]) => {
+            params.iter().collect()
+        }
+        (Some("let") | Some("let*"), [Exp::List(pairs, _), ..]) => pairs.iter().step_by(2).collect(),
+        (Some("let-values"), [Exp::List(pairs, _), ..]) => {
+            pairs.iter()
+                 .step_by(2)
+                 .flat_map(|names| match names {
+                     Exp::List(names, _) => names.iter().collect(),
+                     _ => Vec::new(),
+                 })
+                 .collect()
+        }
+        _ => Vec::new(),
+    };
+    names.into_iter().filter_map(name).filter(|n| n != "...").collect()
+}
+
+// Collect the symbols a template binds as variables (e.g. the parameters of a
+// `fn` or the names of a `let` or `let-values`) which don't come from the
+// macro's arguments. These are renamed in every expansion, so they can't
+// capture the user's variables.
+fn find_binders(template: &Exp, bindings: &Bindings, binders: &mut HashSet<String>) {
+    let mut names = local_binders(template);
+    let args = template.args().unwrap_or_default();
+    match (template.car_symbol().as_deref(), args.as_slice()) {
+        (Some("fn"), [name @ Exp::Symbol(..), Exp::List(..), ..]) => names.extend(name.as_symbol()),
+        (Some("set") | Some("def"), [Exp::SExp { kind, .. }, ..]) => names.extend(kind.as_symbol()),
+        (Some("set") | Some("def"), [target, ..]) => names.extend(target.as_symbol()),
+        _ => {}
+    }
+    binders.extend(names.into_iter().filter(|n| !bindings.contains_key(n)));
+    if template.car_symbol().as_deref() == Some("quote") {
+        return;
+    }
+    for x in sequence(template).unwrap_or_default() {
+        find_binders(&x, bindings, binders);
+    }
+}
+
+// Collect the symbols a template refers to which aren't pattern variables or
+// variables of its own
+fn free_symbols(
+    template: &Exp,
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+    symbols: &mut HashSet<String>,
+) {
+    match template {
+        Exp::Symbol(name, _) => {
+            if !bindings.contains_key(name) && !renames.contains_key(name) && name != "..." && name != "_" {
+                symbols.insert(name.clone());
+            }
+        }
+        _ if template.car_symbol().as_deref() == Some("quote") => {}
+        _ => {
+            for x in sequence(template).unwrap_or_default() {
+                free_symbols(&x, bindings, renames, symbols);
+            }
+        }
+    }
+}
+
+// Rename symbols everywhere in an expression but in quotes
+fn rename(x: &Exp, renames: &HashMap<String, String>) -> Exp {
+    match x {
+        Exp::Symbol(name, span) => Exp::Symbol(renames.get(name).unwrap_or(name).clone(), *span),
+        Exp::List(items, span) => Exp::List(items.iter().map(|i| rename(i, renames)).collect(), *span),
+        Exp::SExp { kind, .. } if kind.as_symbol().as_deref() == Some("quote") => x.clone(),
+        Exp::SExp { kind, args, kwargs, span } => Exp::SExp {
+            kind: Box::new(rename(kind, renames)),
+            args: args.iter().map(|a| rename(a, renames)).collect(),
+            kwargs: kwargs.iter().map(|(k, v)| (k.clone(), rename(v, renames))).collect(),
+            span: *span,
+        },
+        _ => x.clone(),
+    }
+}
+
+// Fill in a template with the bindings of a match. Everything the template
+// adds gets the span of the macro's use.
+fn instantiate(
+    template: &Exp,
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+    span: Span,
+) -> Result<Exp, Box<dyn Error>> {
+    match template {
+        Exp::Symbol(name, _) => {
+            match bindings.get(name) {
+                Some(Binding::One(x)) => Ok(x.clone()),
+                Some(Binding::Many(_)) => Err(ParseError::boxed(
+                    ErrorCode::InvalidMacro,
+                    format!("Pattern variable '{name}' must be followed by '...' in the template"),
+                    span,
+                )),
+                None => {
+                    let name = renames.get(name).unwrap_or(name);
+                    Ok(Exp::Symbol(name.clone(), span))
+                }
+            }
+        }
+        Exp::List(items, _) => {
+            Ok(Exp::List(instantiate_sequence(items, bindings, renames, span)?, span))
+        }
+        Exp::SExp { .. } => {
+            let items = template.items().unwrap_or_default();
+            // `(... ...)` stands for a literal ellipsis
+            if let [first, second] = items.as_slice() {
+                if is_ellipsis(first) && is_ellipsis(second) {
+                    return Ok(Exp::Symbol("...".to_string(), span));
+                }
+            }
+            let items = instantiate_sequence(&items, bindings, renames, span)?;
+            if items.is_empty() {
+                Ok(Exp::Nil(span))
+            } else {
+                Ok(Exp::new_sexp(items, span))
+            }
+        }
+        Exp::Nil(_) => Ok(Exp::Nil(span)),
+        Exp::Int(n, _) => Ok(Exp::Int(*n, span)),
+        Exp::Float(n, _) => Ok(Exp::Float(*n, span)),
+        Exp::Bool(b, _) => Ok(Exp::Bool(*b, span)),
+        Exp::Str(s, _) => Ok(Exp::Str(s.clone(), span)),
+        Exp::Keyword(k, _) => Ok(Exp::Keyword(k.clone(), span)),
+    }
+}
+
+fn instantiate_sequence(
+    templates: &[Exp],
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+    span: Span,
+) -> Result<Vec<Exp>, Box<dyn Error>> {
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < templates.len() {
+        let template = &templates[i];
+        if !templates.get(i + 1).is_some_and(is_ellipsis) {
+            items.push(instantiate(template, bindings, renames, span)?);
+            i += 1;
+            continue;
+        }
+
+        // Repeat the template once for each match of its variables
+        let repeated: Vec<(&String, &Vec<Binding>)> = template_variables(template)
+            .iter()
+            .filter_map(|name| match bindings.get_key_value(name) {
+                Some((name, Binding::Many(each))) => Some((name, each)),
+                _ => None
+            })
+            .collect();
+        let Some(count) = repeated.first().map(|(_, each)| each.len()) else {
+            return Err(ParseError::boxed(
+                ErrorCode::InvalidMacro,
+                format!("No pattern variable to repeat in {template}"),
+                span,
+            ));
+        };
+        if repeated.iter().any(|(_, each)| each.len() != count) {
+            return Err(ParseError::boxed(
+                ErrorCode::InvalidMacro,
+                format!("Pattern variables repeat a different number of times in {template}"),
+                span,
+            ));
+        }
+        for n in 0..count {
+            let mut inner = bindings.clone();
+            for (name, each) in &repeated {
+                inner.insert(name.to_string(), each[n].clone());
+            }
+            items.push(instantiate(template, &inner, renames, span)?);
+        }
+        i += 2;
+    }
+    Ok(items)
+}
+
+fn template_variables(template: &Exp) -> Vec<String> {
+    match template {
+        Exp::Symbol(name, _) => vec![name.clone()],
+        _ => sequence(template).unwrap_or_default()
+                               .iter()
+                               .flat_map(template_variables)
+                               .collect()
+    }
+}
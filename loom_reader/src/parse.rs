@@ -1,9 +1,10 @@
 use std::error::Error;
 use std::fmt;
-use std::collections::HashMap;
+use crate::diagnostics::{ Diagnostic, ErrorCode };
+use crate::lexer::Lexer;
 
 /// The location of a token/expression in the source code
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Location {
     pub line: usize,
     pub column: usize,
@@ -18,31 +19,82 @@ impl Location {
     }
 }
 
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Identifies the source file a span belongs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub usize);
+
+/// The range of source code covered by a token/expression, from the first
+/// character to the last one (inclusive)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: FileId,
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: Location, end: Location) -> Self {
+        Self {
+            file,
+            start,
+            end,
+        }
+    }
+
+    /// A span covering a single character
+    pub fn point(file: FileId, location: Location) -> Self {
+        Self::new(file, location, location)
+    }
+
+    /// The smallest span covering both spans
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.file, self.start, other.end)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Token {
     LParen {
-        location: Location,
+        span: Span,
     },
     RParen {
-        location: Location,
+        span: Span,
     },
     LBracket {
-        location: Location,
+        span: Span,
     },
     RBracket {
-        location: Location,
+        span: Span,
     },
     Symbol {
         content: String,
-        location: Location,
+        span: Span,
     },
     StrLit {
         content: String,
-        location: Location,
+        span: Span,
     },
     Comment {
         content: String,
-        location: Location,
+        span: Span,
+    },
+    /// A reader macro which wraps the next expression in a form, like `'x`
+    /// for `(quote x)`
+    Quote {
+        form: &'static str,
+        span: Span,
     },
 }
 
@@ -56,72 +108,129 @@ impl fmt::Display for Token {
             Token::Symbol { content, .. } => { write!(f, "{content}") }
             Token::StrLit { content, .. } => { write!(f, "\"{content}\"") }
             Token::Comment { content, .. } => { write!(f, "{content}") }
+            Token::Quote { form, .. } => {
+                match *form {
+                    "quote" => write!(f, "'"),
+                    "quasiquote" => write!(f, "`"),
+                    "unquote" => write!(f, ","),
+                    _ => write!(f, ",@"),
+                }
+            }
         }
     }
 }
 
 impl Token {
-    fn get_location(&self) -> Location {
+    pub fn get_location(&self) -> Location {
+        self.span().start
+    }
+
+    pub fn span(&self) -> Span {
         match self {
-            Self::LParen { location } => { *location }
-            Self::RParen { location } => { *location }
-            Self::LBracket { location } => { *location }
-            Self::RBracket { location } => { *location }
-            Self::Symbol { location, .. } => { *location }
-            Self::StrLit { location, .. } => { *location }
-            Self::Comment { location, .. } => { *location }
+            Self::LParen { span } => { *span }
+            Self::RParen { span } => { *span }
+            Self::LBracket { span } => { *span }
+            Self::RBracket { span } => { *span }
+            Self::Symbol { span, .. } => { *span }
+            Self::StrLit { span, .. } => { *span }
+            Self::Comment { span, .. } => { *span }
+            Self::Quote { span, .. } => { *span }
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Exp {
-    Nil,
+    Nil(Span),
     SExp {
         kind: Box<Exp>,
         args: Vec<Exp>,
-        kwargs: HashMap<String, Exp>,
+        /// Keyword arguments, in the order they were written
+        kwargs: Vec<(String, Exp)>,
+        span: Span,
     },
-    List(Vec<Exp>),
-    Atom(String),
+    List(Vec<Exp>, Span),
+    Int(i64, Span),
+    Float(f64, Span),
+    Bool(bool, Span),
+    Str(String, Span),
+    /// A keyword, like `:name`, without its colon
+    Keyword(String, Span),
+    Symbol(String, Span),
 }
 
 impl Exp {
-    fn new_sexp(contents: Vec<Exp>) -> Self {
-        let mut kind = Exp::Nil;
+    /// Build an s-expression from its items: the first one is its kind, and
+    /// every keyword followed by a value is a keyword argument. A repeated
+    /// keyword replaces the earlier value, and a keyword without a value is
+    /// left out, which `keyword_errors` reports.
+    pub fn new_sexp(contents: Vec<Exp>, span: Span) -> Self {
+        let mut kind = Exp::Nil(span);
         let mut args: Vec<Exp> = Vec::new();
-        let mut kwargs: HashMap<String, Exp> = HashMap::new();
-        let mut i: usize = 0;
+        let mut kwargs: Vec<(String, Exp)> = Vec::new();
         let mut in_kwarg = false;
         let mut this_kwarg = String::new();
-        for c in contents {
+        for (i, c) in contents.into_iter().enumerate() {
             if i == 0 {
                 kind = c;
             } else {
                 if in_kwarg {
                     // We're expecting a single value for the current kwarg
-                    kwargs.insert(this_kwarg.clone(), c);
+                    match kwargs.iter_mut().find(|(k, _)| *k == this_kwarg) {
+                        Some((_, value)) => *value = c,
+                        None => kwargs.push((this_kwarg.clone(), c)),
+                    }
                     in_kwarg = false;
+                } else if let Exp::Keyword(kwarg_name, _) = c {
+                    in_kwarg = true;
+                    this_kwarg = kwarg_name;
                 } else {
-                    if let Some(s) = c.as_symbol() {
-                        if let Some(kwarg_name) = s.strip_prefix(':') {
-                            in_kwarg = true;
-                            this_kwarg = kwarg_name.to_string();
-                        } else {
-                            args.push(c);
-                        };
-                    } else {
-                        args.push(c);
-                    };
+                    args.push(c);
                 }
             }
-            i += 1;
         }
-        Self::SExp { kind: Box::new(kind), args, kwargs }
+        Self::SExp { kind: Box::new(kind), args, kwargs, span }
+    }
+    /// The value of a keyword argument
+    pub fn kwarg(&self, name: &str) -> Option<&Exp> {
+        match self {
+            Exp::SExp { kwargs, .. } => kwargs.iter().find(|(k, _)| k == name).map(|(_, v)| v),
+            _ => None
+        }
+    }
+    /// The source code this expression was read from
+    pub fn span(&self) -> Span {
+        match self {
+            Exp::Nil(span) => *span,
+            Exp::SExp { span, .. } => *span,
+            Exp::List(_, span) => *span,
+            Exp::Int(_, span) => *span,
+            Exp::Float(_, span) => *span,
+            Exp::Bool(_, span) => *span,
+            Exp::Str(_, span) => *span,
+            Exp::Keyword(_, span) => *span,
+            Exp::Symbol(_, span) => *span,
+        }
+    }
+    /// The items of an s-expression as they were written, the inverse of
+    /// `new_sexp`
+    pub fn items(&self) -> Option<Vec<Exp>> {
+        match self {
+            Exp::SExp { kind, args, kwargs, .. } => {
+                let mut items = vec![(**kind).clone()];
+                items.extend(args.iter().cloned());
+                for (k, v) in kwargs {
+                    items.push(Exp::Keyword(k.clone(), v.span()));
+                    items.push(v.clone());
+                }
+                Some(items)
+            }
+            _ => None
+        }
     }
     pub fn as_symbol(&self) -> Option<String> {
         match self {
-            Exp::Atom(s) => Some(s.clone()),
+            Exp::Symbol(s, _) => Some(s.clone()),
             _ => None
         }
     }
@@ -142,7 +251,7 @@ impl Exp {
     pub fn arg(&self, index: usize) -> Option<Exp> {
         match self {
             Exp::SExp { args, .. } => {
-                let Some(arg) = args.get(index) else { return None; };
+                let arg = args.get(index)?;
                 Some(arg.clone())
             }
             _ => None
@@ -159,10 +268,10 @@ impl Exp {
 impl fmt::Display for Exp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Exp::Nil => {
+            Exp::Nil(_) => {
                 write!(f, "nil")
             }
-            Exp::SExp { kind, args, kwargs } => {
+            Exp::SExp { kind, args, kwargs, .. } => {
                 let content = if args.is_empty() && kwargs.is_empty() {
                     format!("({kind})")
                 } else {
@@ -185,15 +294,44 @@ impl fmt::Display for Exp {
                 };
                 write!(f, "{content}")
             }
-            Exp::List(contents) => {
+            Exp::List(contents, _) => {
                 let inner = contents.iter()
                                     .map(|x| {format!("{x}")})
                                     .collect::<Vec<String>>()
                                     .join(" ");
                 write!(f, "[{inner}]")
             }
-            Exp::Atom(contents) => {
-                write!(f, "{contents}")
+            Exp::Int(n, _) => {
+                write!(f, "{n}")
+            }
+            Exp::Float(n, _) => {
+                // Debug formatting always has a decimal point or an exponent,
+                // so the number reads back as a float
+                write!(f, "{n:?}")
+            }
+            Exp::Bool(b, _) => {
+                write!(f, "{b}")
+            }
+            Exp::Str(contents, _) => {
+                write!(f, "\"")?;
+                for c in contents.chars() {
+                    match c {
+                        '\"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        _ if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                        _ => write!(f, "{c}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Exp::Keyword(name, _) => {
+                write!(f, ":{name}")
+            }
+            Exp::Symbol(name, _) => {
+                write!(f, "{name}")
             }
         }
     }
@@ -201,14 +339,40 @@ impl fmt::Display for Exp {
 
 #[derive(Debug)]
 pub struct ParseError {
+    pub code: ErrorCode,
     pub message: String,
-    pub location: Location,
+    pub span: Span,
+    pub notes: Vec<String>,
+    /// Advice specific to this error, instead of the code's general advice
+    pub help: Option<String>,
     pub cause: Option<Box<dyn Error>>,
 }
 
 impl ParseError {
-    fn new(message: String, location: Location) -> Box<dyn Error> {
-        Box::new(Self { message, location, cause: None })
+    pub fn new(code: ErrorCode, message: String, span: Span) -> Self {
+        Self { code, message, span, notes: Vec::new(), help: None, cause: None }
+    }
+
+    pub fn boxed(code: ErrorCode, message: String, span: Span) -> Box<dyn Error> {
+        Box::new(Self::new(code, message, span))
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::from(self)
+    }
+
+    pub fn location(&self) -> Location {
+        self.span.start
     }
 }
 
@@ -218,7 +382,7 @@ impl Error for ParseError {
     }
 
     fn cause(&self) -> Option<&dyn Error> {
-        self.cause.as_ref().map(|e| &**e)
+        self.cause.as_deref()
     }
 }
 
@@ -228,238 +392,374 @@ impl fmt::Display for ParseError {
     }
 }
 
-pub enum ParseMode {
-    String,
-    Normal,
-    Comment,
+pub fn tokenize(source: &str) -> Vec<Token> {
+    tokenize_file(source, FileId::default())
 }
 
-pub fn tokenize(source: String) -> Vec<Token> {
-    // Eat up those characters
-    let mut mode = ParseMode::Normal;
-    let mut in_symbol = false;
-    let mut current_symbol = String::new();
-    let mut current_string = String::new();
-    let mut current_comment = String::new();
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut line: usize = 1;
-    let mut this_line: usize = 1;
-    let mut column: usize = 0;
-    let mut this_column: usize = 0;
-    let mut mark_pos = true;
+pub fn tokenize_file(source: &str, file: FileId) -> Vec<Token> {
+    tokenize_checked(source, file).0
+}
 
-    for c in source.chars() {
-        if let '\n' = c {
-            line += 1;
-            column = 0;
-        } else {
-            column += 1;
-        }
-        if mark_pos {
-            this_line = line;
-            this_column = column;
-        }
-        //println!("[{line}:{column}] ({this_line}:{this_column}) '{c}'");
-        match mode {
-            ParseMode::String => {
-                match c {
-                    '\"' => {
-                        mode = ParseMode::Normal;
-                        tokens.push(Token::StrLit {
-                            content: current_string.clone(),
-                            location: Location::new(this_line, this_column),
-                        });
-                        current_string = String::new();
-                        mark_pos = false;
-                        continue;
-                    }
-                    _ => {
-                        mark_pos = false;
-                    }
-                }
-                current_string.push(c);
-            }
-            ParseMode::Normal => {
-                match c {
-                    '(' => {
-                        tokens.push(Token::LParen {
-                            location: Location::new(line, column),
-                        });
-                        mark_pos = true;
-                    }
-                    ')' => {
-                        if in_symbol {
-                            tokens.push(Token::Symbol {
-                                content: current_symbol.clone(),
-                                location: Location::new(this_line, this_column),
-                            });
-                            current_symbol = String::new();
-                            in_symbol = false;
-                        }
-                        tokens.push(Token::RParen {
-                            location: Location::new(line, column),
-                        });
-                        mark_pos = true;
-                    }
-                    '[' => {
-                        tokens.push(Token::LBracket {
-                            location: Location::new(line, column),
-                        });
-                        mark_pos = true;
-                    }
-                    ']' => {
-                        if in_symbol {
-                            tokens.push(Token::Symbol {
-                                content: current_symbol.clone(),
-                                location: Location::new(this_line, this_column),
-                            });
-                            current_symbol = String::new();
-                            in_symbol = false;
-                        }
-                        tokens.push(Token::RBracket {
-                            location: Location::new(line, column),
-                        });
-                        mark_pos = true;
-                    }
-                    '\"' => {
-                        mode = ParseMode::String;
-                        mark_pos = false;
-                    }
-                    ' ' | '\n' => {
-                        if in_symbol {
-                            if !current_symbol.is_empty() {
-                                tokens.push(Token::Symbol {
-                                    content: current_symbol.clone(),
-                                    location: Location::new(this_line, this_column),
-                                });
-                                current_symbol = String::new();
-                                in_symbol = false;
-                            }
-                        }
-                        mark_pos = true;
-                    }
-                    ';' => {
-                        mode = ParseMode::Comment;
-                    }
-                    _ => {
-                        in_symbol = true;
-                        current_symbol.push(c);
-                        mark_pos = current_symbol == "";
-                    }
-                }
-            }
-            ParseMode::Comment => {
-                match c {
-                    '\n' => {
-                        // This comment is done, save it if needed
-                        if !current_comment.is_empty() {
-                            tokens.push(Token::Comment {
-                                content: current_comment.clone(),
-                                location: Location::new(this_line, this_column),
-                            });
-                            current_comment = String::new();
-                        }
-                        mode = ParseMode::Normal;
-                        mark_pos = true;
-                    }
-                    _ => {
-                        current_comment.push(c);
-                        mark_pos = current_comment == "";
-                    }
-                }
-            }
-        }
+/// Like `tokenize_file`, but also returns the errors found in string literals
+pub fn tokenize_checked(source: &str, file: FileId) -> (Vec<Token>, Vec<ParseError>) {
+    let mut lexer = Lexer::new(source, file);
+    let tokens = lexer.by_ref().map(|l| l.token).collect();
+    (tokens, lexer.into_errors())
+}
+
+// Reads a list of expressions from a source string
+pub fn read_expressions(source: String) -> Result<Vec<Exp>, Box<dyn Error>> {
+    read_file_expressions(source, FileId::default())
+}
+
+// Reads a list of expressions from a source file, with spans in that file
+pub fn read_file_expressions(source: String, file: FileId) -> Result<Vec<Exp>, Box<dyn Error>> {
+    let (expressions, mut errors) = read_all_expressions(source, file);
+    if errors.is_empty() {
+        Ok(expressions)
+    } else {
+        Err(Box::new(errors.swap_remove(0)))
     }
+}
 
-    if in_symbol {
-        tokens.push(Token::Symbol {
-            content: current_symbol.clone(),
-            location: Location::new(this_line, this_column),
-        });
+/// Reads every well-formed expression from a source file, along with every
+/// syntax error, instead of stopping at the first one.
+///
+/// After an error the reader skips to the next top-level form: the end of the
+/// broken form if it can be found. If the file ends with forms left open, a
+/// `(` at the start of a line is taken as the start of the next top-level
+/// form instead, since the form before it was most likely never closed.
+pub fn read_all_expressions(source: String, file: FileId) -> (Vec<Exp>, Vec<ParseError>) {
+    let (tokens, mut errors) = tokenize_checked(&source, file);
+    let (mut expressions, mut form_errors, unbalanced) = read_forms(&tokens, false);
+    if unbalanced {
+        (expressions, form_errors, _) = read_forms(&tokens, true);
     }
+    errors.extend(form_errors);
 
-    tokens
+    // Report the errors from the tokenizer and from the parser in order
+    errors.sort_by_key(|e| (e.span.start.line, e.span.start.column));
+    (expressions, errors)
 }
 
-// Reads a list of expressions from a source string
-pub fn read_expressions(source: String) -> Result<Vec<Exp>, Box<dyn Error>> {
-    let tokens = tokenize(source);
+/// Read the forms of a file, and whether it ends with forms left open. With
+/// `resync`, a `(` at the start of a line always starts a top-level form.
+fn read_forms(tokens: &[Token], resync: bool) -> (Vec<Exp>, Vec<ParseError>, bool) {
+    let mut errors = Vec::new();
     let mut expressions: Vec<Exp> = Vec::new();
-    let mut nesting: usize = 0;
+    // The opening tokens of the form being read, innermost last
+    let mut open: Vec<&Token> = Vec::new();
     let mut start: usize = 0;
-    let mut i: usize = 0;
+    // Whether the form being read has already been reported as broken
+    let mut broken = false;
+    // The first quote before the next top-level expression, which wraps it
+    let mut quote_start: Option<usize> = None;
 
-    for t in &tokens {
+    for (i, t) in tokens.iter().enumerate() {
         match t {
             Token::LParen {..} | Token::LBracket {..} => {
-                if nesting == 0 {
-                    start = i;
+                if let Some(first) = open.first() {
+                    if resync && t.get_location().column == 1 {
+                        // A new form at the start of a line, so the previous
+                        // one was most likely never closed
+                        if !broken {
+                            errors.push(unclosed_error(first));
+                        }
+                        open.clear();
+                    }
                 }
-                nesting += 1;
+                if open.is_empty() {
+                    start = quote_start.take().unwrap_or(i);
+                    broken = false;
+                }
+                open.push(t);
             }
             Token::RParen {..} | Token::RBracket {..} => {
-                if nesting > 0 {
-                    nesting -= 1;
-                } else {
-                    todo!() // Oh, what horror!
+                let closes_list = matches!(t, Token::RBracket {..});
+                let Some(opening) = open.pop() else {
+                    // Nothing to close, so skip it
+                    if let Some(q) = quote_start.take() {
+                        errors.push(dangling_quote_error(&tokens[q]));
+                    }
+                    errors.push(unexpected_error(t));
+                    continue;
+                };
+                let opens_list = matches!(opening, Token::LBracket {..});
+                if opens_list != closes_list && !broken {
+                    // Mismatched, but treat it as closing the innermost
+                    // expression anyway, to find where the form ends
+                    errors.push(mismatched_error(opening, t));
+                    broken = true;
                 }
-                
-                if nesting == 0 {
-                    if let Some(x) = parse_expression(&tokens, start, i)? {
-                        expressions.push(x);
-                    };
+
+                if open.is_empty() && !broken {
+                    match read_expression(tokens, start, i, &mut errors) {
+                        Ok(Some(x)) => expressions.push(x),
+                        Ok(None) => {}
+                        Err(e) => errors.push(into_parse_error(e, tokens[start].span())),
+                    }
                 }
             }
-            _ => {
-                if nesting == 0 {
-                    if let Some(atom) = process_atom(t) {
-                        expressions.push(atom);
-                    };
+            Token::Quote {..} => {
+                if open.is_empty() && quote_start.is_none() {
+                    quote_start = Some(i);
+                }
+            }
+            Token::Comment {..} => {}
+            Token::Symbol {..} | Token::StrLit {..} => {
+                if open.is_empty() {
+                    let start = quote_start.take().unwrap_or(i);
+                    match read_expression(tokens, start, i, &mut errors) {
+                        Ok(Some(atom)) => expressions.push(atom),
+                        Ok(None) => {}
+                        Err(e) => errors.push(into_parse_error(e, t.span())),
+                    }
                 }
             }
         }
-        i += 1;
     }
 
-    Ok(expressions)
+    if let Some(q) = quote_start {
+        errors.push(dangling_quote_error(&tokens[q]));
+    }
+    if let Some(first) = open.first() {
+        // The last form was never closed
+        if !broken {
+            errors.push(unclosed_error(first));
+        }
+    }
+    (expressions, errors, !open.is_empty())
 }
 
-pub fn process_atom(token: &Token) -> Option<Exp> {
-    match token {
-        Token::Symbol { content, .. } => {
-            if content == "nil" {
-                Some(Exp::Nil)
-            } else {
-                Some(Exp::Atom(content.clone()))
+// Parsing only fails with `ParseError`s, but make sure of it
+fn into_parse_error(e: Box<dyn Error>, span: Span) -> ParseError {
+    match e.downcast::<ParseError>() {
+        Ok(e) => *e,
+        Err(e) => ParseError::new(ErrorCode::InvalidForm, e.to_string(), span),
+    }
+}
+
+/// The problems with the keyword arguments among the items of a form, which
+/// `Exp::new_sexp` would otherwise paper over: keywords given more than once,
+/// and a keyword at the end without a value.
+pub fn keyword_errors(contents: &[Exp]) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    let mut seen: Vec<(&str, Span)> = Vec::new();
+    let mut items = contents.iter().skip(1);
+    while let Some(x) = items.next() {
+        let Exp::Keyword(name, span) = x else {
+            continue;
+        };
+        if items.next().is_none() {
+            errors.push(ParseError::new(
+                ErrorCode::MissingKeywordValue,
+                format!("Keyword `:{name}` has no value"),
+                *span,
+            ));
+        }
+        match seen.iter().find(|(k, _)| k == name) {
+            Some((_, first)) => {
+                errors.push(ParseError::new(
+                    ErrorCode::DuplicateKeyword,
+                    format!("Keyword argument `:{name}` is given more than once"),
+                    *span,
+                ).with_note(format!("`:{name}` is first given at {first}")));
             }
+            None => seen.push((name, *span)),
         }
-        Token::StrLit { content, .. } => Some(Exp::Atom(content.clone())),
-        _ => None
+    }
+    errors
+}
+
+// A closing paren or bracket with nothing to close
+pub(crate) fn unexpected_error(closing: &Token) -> ParseError {
+    if let Token::RBracket {..} = closing {
+        ParseError::new(
+            ErrorCode::UnexpectedBracket,
+            "Unexpected closing bracket".to_string(),
+            closing.span(),
+        )
+    } else {
+        ParseError::new(
+            ErrorCode::UnexpectedParen,
+            "Unexpected closing paren".to_string(),
+            closing.span(),
+        )
     }
 }
 
+// A list closed with a paren, or an expression closed with a bracket
+pub(crate) fn mismatched_error(opening: &Token, closing: &Token) -> ParseError {
+    if let Token::LBracket {..} = opening {
+        ParseError::new(
+            ErrorCode::UnclosedBracket,
+            "List is missing closing bracket".to_string(),
+            opening.span(),
+        ).with_note(format!("the list is closed with `)` at {}", closing.span()))
+         .with_help("close the list with `]`".to_string())
+    } else {
+        ParseError::new(
+            ErrorCode::UnexpectedBracket,
+            "Unexpected closing bracket".to_string(),
+            closing.span(),
+        ).with_note(format!("the expression is opened with `(` at {}", opening.span()))
+         .with_help("replace this `]` with `)`".to_string())
+    }
+}
+
+pub(crate) fn dangling_quote_error(quote: &Token) -> ParseError {
+    ParseError::new(
+        ErrorCode::DanglingQuote,
+        format!("Expected an expression after `{quote}`"),
+        quote.span(),
+    )
+}
+
+pub(crate) fn unclosed_error(opening: &Token) -> ParseError {
+    if let Token::LBracket {..} = opening {
+        ParseError::new(
+            ErrorCode::UnclosedBracket,
+            "List is never closed".to_string(),
+            opening.span(),
+        )
+    } else {
+        ParseError::new(
+            ErrorCode::UnclosedParen,
+            "Expression is never closed".to_string(),
+            opening.span(),
+        )
+    }
+}
+
+/// Whether the source ends in the middle of an expression, i.e. more input
+/// could complete it. Sources with other syntax errors aren't incomplete.
+pub fn is_incomplete(source: &str) -> bool {
+    let (tokens, errors) = tokenize_checked(source, FileId::default());
+    if errors.iter().any(|e| e.code == ErrorCode::UnterminatedString) {
+        return true;
+    }
+    let mut start: usize = 0;
+    while start < tokens.len() {
+        let in_list = match tokens[start] {
+            Token::LParen {..} => false,
+            Token::LBracket {..} => true,
+            _ => {
+                start += 1;
+                continue;
+            }
+        };
+        match find_exp_end(&tokens, start, in_list) {
+            Ok(Some(end)) => start = end + 1,
+            Ok(None) => return true,
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+pub fn process_atom(token: &Token) -> Result<Option<Exp>, Box<dyn Error>> {
+    match token {
+        Token::Symbol { content, span } => {
+            let span = *span;
+            let x = match content.as_str() {
+                "nil" => Exp::Nil(span),
+                "true" => Exp::Bool(true, span),
+                "false" => Exp::Bool(false, span),
+                _ if is_number(content) => {
+                    let digits = content.strip_prefix(['-', '+']).unwrap_or(content);
+                    if digits.bytes().all(|b| b.is_ascii_digit()) {
+                        let Ok(n) = content.parse::<i64>() else {
+                            return Err(ParseError::new(
+                                ErrorCode::InvalidLiteral,
+                                format!("Integer literal '{content}' is out of range"),
+                                span,
+                            ).with_note(format!("integers must be between {} and {}", i64::MIN, i64::MAX))
+                             .into());
+                        };
+                        Exp::Int(n, span)
+                    } else if let Ok(n) = content.parse::<f64>() {
+                        Exp::Float(n, span)
+                    } else {
+                        return Err(ParseError::boxed(
+                            ErrorCode::InvalidLiteral,
+                            format!("Invalid number literal '{content}'"),
+                            span,
+                        ));
+                    }
+                }
+                _ => match content.strip_prefix(':') {
+                    Some(name) if !name.is_empty() => Exp::Keyword(name.to_string(), span),
+                    _ => Exp::Symbol(content.clone(), span),
+                }
+            };
+            Ok(Some(x))
+        }
+        Token::StrLit { content, span } => Ok(Some(Exp::Str(content.clone(), *span))),
+        _ => Ok(None)
+    }
+}
+
+// Whether a symbol should be read as a number, i.e. it starts with a digit,
+// possibly after a sign or a decimal point
+fn is_number(symbol: &str) -> bool {
+    let digits = symbol.strip_prefix(['-', '+']).unwrap_or(symbol);
+    let digits = digits.strip_prefix('.').unwrap_or(digits);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+}
+
 // start: the start of the range of tokens to parse
 // end: the end of the range of tokens to parse
 pub fn parse_expression(
-    tokens: &Vec<Token>,
+    tokens: &[Token],
     start: usize,
     end: usize
 ) -> Result<Option<Exp>, Box<dyn Error>> {
+    let mut keyword_errors = Vec::new();
+    let x = read_expression(tokens, start, end, &mut keyword_errors)?;
+    match keyword_errors.into_iter().next() {
+        Some(e) => Err(Box::new(e)),
+        None => Ok(x),
+    }
+}
+
+// Like `parse_expression`, but forms with problems in their keyword arguments
+// are kept, and the problems are added to `errors`
+fn read_expression(
+    tokens: &[Token],
+    start: usize,
+    end: usize,
+    errors: &mut Vec<ParseError>,
+) -> Result<Option<Exp>, Box<dyn Error>> {
+    if let Token::Quote { form, span } = &tokens[start] {
+        // Wrap the quoted expression, e.g. `'x` becomes `(quote x)`
+        let inner = skip_comments(tokens, start + 1);
+        let quoted = if inner <= end { read_expression(tokens, inner, end, errors)? } else { None };
+        let Some(quoted) = quoted else {
+            return Err(Box::new(dangling_quote_error(&tokens[start])));
+        };
+        let span = span.to(quoted.span());
+        return Ok(Some(Exp::SExp {
+            kind: Box::new(Exp::Symbol(form.to_string(), span)),
+            args: vec![quoted],
+            kwargs: Vec::new(),
+            span,
+        }));
+    }
     let mut contents: Vec<Exp> = Vec::new();
     let mut nested = false;
     let mut in_list = false;
     let mut i: usize = start;
-    let mut location = Location::new(0, 0);
+    let mut span = Span::default();
     loop {
         if i > end || i >= tokens.len() { break; }
         let t = tokens.get(i).unwrap();
-        location = t.get_location();
+        span = t.span();
         match t {
             Token::LParen {..} => {
                 if nested {
                     // Find the matching RParen
-                    let inner_end = find_exp_end(tokens, i, false)?;
-                    if let Some(x) = parse_expression(tokens, i, inner_end)? {
+                    let inner_end = expect_exp_end(tokens, i, false)?;
+                    if let Some(x) = read_expression(tokens, i, inner_end, errors)? {
                         contents.push(x);
                     };
                     i = inner_end;
@@ -472,17 +772,18 @@ pub fn parse_expression(
                     nested = false;
                 } else {
                     // Syntax error: Unexpected RParen
-                    return Err(ParseError::new(
-                        format!("Unexpected closing paren"),
-                        location,
+                    return Err(ParseError::boxed(
+                        ErrorCode::UnexpectedParen,
+                        "Unexpected closing paren".to_string(),
+                        span,
                     ));
                 }
             }
             Token::LBracket {..} => {
                 if nested {
                     // Find the matching RParen
-                    let inner_end = find_exp_end(tokens, i, true)?;
-                    if let Some(x) = parse_expression(tokens, i, inner_end)? {
+                    let inner_end = expect_exp_end(tokens, i, true)?;
+                    if let Some(x) = read_expression(tokens, i, inner_end, errors)? {
                         contents.push(x);
                     };
                     i = inner_end;
@@ -491,37 +792,47 @@ pub fn parse_expression(
                     in_list = true;
                 }
             }
-            Token::RBracket { location } => {
+            Token::RBracket {..} => {
                 if !in_list {
-                    return Err(ParseError::new(
-                        format!("Unexpected closing bracket"),
-                        *location,
+                    return Err(ParseError::boxed(
+                        ErrorCode::UnexpectedBracket,
+                        "Unexpected closing bracket".to_string(),
+                        span,
                     ));
                 }
                 if nested {
                     nested = false;
                 } else {
-                    return Err(ParseError::new(
-                        format!("Unexpected closing bracket"),
-                        *location,
+                    return Err(ParseError::boxed(
+                        ErrorCode::UnexpectedBracket,
+                        "Unexpected closing bracket".to_string(),
+                        span,
                     ));
                 }
             }
+            Token::Quote {..} if nested => {
+                let inner_end = quoted_end(tokens, i)?;
+                if let Some(x) = read_expression(tokens, i, inner_end, errors)? {
+                    contents.push(x);
+                };
+                i = inner_end;
+            }
             Token::Symbol {..} | Token::StrLit {..} => {
                 if nested {
-                    if let Some(atom) = process_atom(t) {
+                    if let Some(atom) = process_atom(t)? {
                         contents.push(atom);
                     };
                 } else {
                     if end - start > 1 {
                         // Multiple atoms outside of a list (syntax error)
-                        return Err(ParseError::new(
-                            format!("Expression missing opening paren/bracket"),
-                            location,
+                        return Err(ParseError::boxed(
+                            ErrorCode::MissingOpening,
+                            "Expression missing opening paren/bracket".to_string(),
+                            span,
                         ));
                     } else {
                         // Single atom
-                        return Ok(process_atom(t));
+                        return process_atom(t);
                     }
                 }
             }
@@ -531,35 +842,77 @@ pub fn parse_expression(
     }
     if nested {
         // Syntax error: missing RParen
-        Err(ParseError::new(
-            format!("Missing closing parentheses"),
-            location,
+        Err(ParseError::boxed(
+            ErrorCode::UnclosedParen,
+            "Missing closing parentheses".to_string(),
+            span,
         ))
     } else {
+        // The expression covers everything from its opening token to its
+        // closing one
+        let span = tokens[start].span().to(span);
         if in_list {
-            return Ok(Some(Exp::List(contents)));
+            Ok(Some(Exp::List(contents, span)))
+        } else if contents.is_empty() {
+            Ok(Some(Exp::Nil(span)))
         } else {
-            if contents.is_empty() {
-                return Ok(Some(Exp::Nil));
-            } else {
-                return Ok(Some(Exp::new_sexp(contents)));
-            }
+            errors.extend(keyword_errors(&contents));
+            Ok(Some(Exp::new_sexp(contents, span)))
         }
     }
 }
 
-// Given a starting LParen index, return the index of the closing RParen
-fn find_exp_end(
-    tokens: &Vec<Token>,
+// The index of the first token from `start` which isn't a comment
+fn skip_comments(tokens: &[Token], start: usize) -> usize {
+    let mut i = start;
+    while let Some(Token::Comment {..}) = tokens.get(i) {
+        i += 1;
+    }
+    i
+}
+
+// Given the index of a quote, return the index of the last token of the
+// expression it quotes
+fn quoted_end(tokens: &[Token], quote: usize) -> Result<usize, Box<dyn Error>> {
+    let i = skip_comments(tokens, quote + 1);
+    match tokens.get(i) {
+        Some(Token::Quote {..}) => quoted_end(tokens, i),
+        Some(Token::LParen {..}) => expect_exp_end(tokens, i, false),
+        Some(Token::LBracket {..}) => expect_exp_end(tokens, i, true),
+        Some(Token::Symbol {..} | Token::StrLit {..}) => Ok(i),
+        _ => Err(Box::new(dangling_quote_error(&tokens[quote]))),
+    }
+}
+
+// Like `find_exp_end`, but an expression which is never closed is an error
+fn expect_exp_end(
+    tokens: &[Token],
     start: usize,
     in_list: bool
 ) -> Result<usize, Box<dyn Error>> {
+    match find_exp_end(tokens, start, in_list)? {
+        Some(end) => Ok(end),
+        None => Err(ParseError::boxed(
+            if in_list { ErrorCode::UnclosedBracket } else { ErrorCode::UnclosedParen },
+            "Inner expression is never closed".to_string(),
+            tokens[start].span(),
+        ))
+    }
+}
+
+// Given a starting LParen index, return the index of the closing RParen, or
+// None if the tokens run out before the expression is closed
+pub fn find_exp_end(
+    tokens: &[Token],
+    start: usize,
+    in_list: bool
+) -> Result<Option<usize>, Box<dyn Error>> {
     let mut nesting: usize = 0;
-    let mut start_location = Location::new(0, 0);
+    let mut start_span = Span::default();
     for i in start..tokens.len() {
         let t = tokens.get(i).unwrap();
         if i == start {
-            start_location = t.get_location();
+            start_span = t.span();
         }
         match t {
             Token::LParen {..} => {
@@ -569,18 +922,22 @@ fn find_exp_end(
                 match nesting {
                     0 => {
                         return Err(ParseError::new(
-                            format!("List is missing closing bracket"),
-                            start_location,
-                        ));
+                            ErrorCode::UnexpectedParen,
+                            "Unexpected closing paren".to_string(),
+                            t.span(),
+                        ).into());
                     }
                     1 => {
                         if in_list {
                             return Err(ParseError::new(
-                                format!("List is missing closing bracket"),
-                                start_location,
-                            ));
+                                ErrorCode::UnclosedBracket,
+                                "List is missing closing bracket".to_string(),
+                                start_span,
+                            ).with_note(format!("the list is closed with `)` at {}", t.span()))
+                             .with_help("close the list with `]`".to_string())
+                             .into());
                         } else {
-                            return Ok(i);
+                            return Ok(Some(i));
                         }
                     }
                     _ => {}
@@ -590,22 +947,26 @@ fn find_exp_end(
             Token::LBracket {..} => {
                 nesting += 1;
             }
-            Token::RBracket { location } => {
+            Token::RBracket { span } => {
                 match nesting {
                     0 => {
-                        return Err(ParseError::new(
-                            format!("Unexpected closing bracket"),
-                            *location,
+                        return Err(ParseError::boxed(
+                            ErrorCode::UnexpectedBracket,
+                            "Unexpected closing bracket".to_string(),
+                            *span,
                         ));
                     }
                     1 => {
                         if !in_list {
                             return Err(ParseError::new(
-                                format!("Unexpected closing bracket"),
-                                *location,
-                            ));
+                                ErrorCode::UnexpectedBracket,
+                                "Unexpected closing bracket".to_string(),
+                                *span,
+                            ).with_note(format!("the expression is opened with `(` at {start_span}"))
+                             .with_help("replace this `]` with `)`".to_string())
+                             .into());
                         } else {
-                            return Ok(i);
+                            return Ok(Some(i));
                         }
                     }
                     _ => {}
@@ -615,8 +976,5 @@ fn find_exp_end(
             _ => {}
         }
     }
-    Err(ParseError::new(
-        format!("Inner expression is never closed"),
-        start_location,
-    ))
+    Ok(None)
 }
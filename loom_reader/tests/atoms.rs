@@ -0,0 +1,60 @@
+use loom_reader::diagnostics::ErrorCode;
+use loom_reader::parse::{Exp, FileId, ParseError, read_all_expressions, read_expressions};
+
+fn read(source: &str) -> Exp {
+    let mut expressions = read_expressions(source.to_string()).unwrap_or_else(|e| panic!("{e}"));
+    assert_eq!(expressions.len(), 1, "{source}");
+    expressions.remove(0)
+}
+
+fn error(source: &str) -> ParseError {
+    let (_, mut errors) = read_all_expressions(source.to_string(), FileId::default());
+    assert_eq!(errors.len(), 1, "{source}");
+    errors.remove(0)
+}
+
+#[test]
+fn atoms_have_types() {
+    assert!(matches!(read("42"), Exp::Int(42, _)));
+    assert!(matches!(read("-7"), Exp::Int(-7, _)));
+    assert!(matches!(read("+7"), Exp::Int(7, _)));
+    assert!(matches!(read("1.5"), Exp::Float(n, _) if n == 1.5));
+    assert!(matches!(read(".5"), Exp::Float(n, _) if n == 0.5));
+    assert!(matches!(read("-2e3"), Exp::Float(n, _) if n == -2000.0));
+    assert!(matches!(read("true"), Exp::Bool(true, _)));
+    assert!(matches!(read("false"), Exp::Bool(false, _)));
+    assert!(matches!(read("nil"), Exp::Nil(_)));
+    assert!(matches!(read(":depth"), Exp::Keyword(name, _) if name == "depth"));
+    assert!(matches!(read("foo-bar?"), Exp::Symbol(name, _) if name == "foo-bar?"));
+    // A sign or a colon on its own is a symbol
+    assert!(matches!(read("-"), Exp::Symbol(name, _) if name == "-"));
+    assert!(matches!(read(":"), Exp::Symbol(name, _) if name == ":"));
+}
+
+#[test]
+fn strings_are_not_symbols() {
+    assert!(matches!(read("\"nil\""), Exp::Str(s, _) if s == "nil"));
+    assert!(matches!(read("\"42\""), Exp::Str(s, _) if s == "42"));
+    assert!(matches!(read("\":key\""), Exp::Str(s, _) if s == ":key"));
+}
+
+#[test]
+fn atoms_display_as_they_read() {
+    for source in ["42", "-7", "1.5", "2.0", "1e100", "true", "nil", ":depth", "foo", "\"nil\"", "\"42\""] {
+        assert_eq!(read(source).to_string(), source);
+        assert_eq!(read(&read(source).to_string()).to_string(), source);
+    }
+    // Floats keep a decimal point, so they don't read back as integers
+    assert_eq!(read("3.").to_string(), "3.0");
+}
+
+#[test]
+fn invalid_numbers() {
+    let e = error("99999999999999999999");
+    assert_eq!(e.code, ErrorCode::InvalidLiteral);
+    assert_eq!(e.message, "Integer literal '99999999999999999999' is out of range");
+    let e = error("(foo 1.2.3)");
+    assert_eq!(e.code, ErrorCode::InvalidLiteral);
+    assert_eq!(e.message, "Invalid number literal '1.2.3'");
+    assert_eq!((e.span.start.line, e.span.start.column), (1, 6));
+}
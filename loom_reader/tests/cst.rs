@@ -0,0 +1,115 @@
+use loom_reader::cst::{self, SyntaxElement, SyntaxKind, SyntaxNode};
+use loom_reader::parse::FileId;
+
+/// Sources without any expressions, which are still valid
+const EMPTY: &[&str] = &["", "   \n\t", "; only a comment", "\n; two\n;comments"];
+
+const MALFORMED: &[&str] = &[
+    "(",
+    ")",
+    "]]",
+    "(a [b)",
+    "(a (b (c",
+    "[1 2",
+    "'",
+    "(a ')",
+    "`(a ,@",
+    "\"never closed",
+    "(print \"never closed)",
+    "(a \"bad \\q escape\" b)",
+    "(a \"\\u{110000}\")",
+    "r#\"raw never closed\"",
+    "(a ; comment in a broken form\n",
+    "(def x\n  (foo 1)\n(def y 2)\n(def z (bar)",
+    ")\n(a [b)\n(c)\n'",
+    "(é ñ \"ü\")) ] (",
+    "\r\n(a\r\n",
+];
+
+// Every token of a tree, in order
+fn tokens(node: &SyntaxNode) -> Vec<(SyntaxKind, String)> {
+    node.children_with_tokens().into_iter().flat_map(|child| match child {
+        SyntaxElement::Token(token) => vec![(token.kind(), token.text().to_string())],
+        SyntaxElement::Node(node) => tokens(&node),
+    }).collect()
+}
+
+#[test]
+fn trees_cover_the_whole_source() {
+    for source in EMPTY.iter().chain(MALFORMED) {
+        let (tree, _) = cst::parse(source, FileId::default());
+        assert_eq!(tree.text(), *source);
+        assert_eq!(tree.range(), 0..source.len(), "{source:?}");
+        let text: String = tokens(&tree).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(text, *source);
+    }
+}
+
+#[test]
+fn only_malformed_sources_have_errors() {
+    for source in EMPTY {
+        let (tree, errors) = cst::parse(source, FileId::default());
+        assert!(errors.is_empty() && tree.children().is_empty(), "{source:?}");
+    }
+    for source in MALFORMED {
+        let (_, errors) = cst::parse(source, FileId::default());
+        assert!(!errors.is_empty(), "{source:?}");
+    }
+}
+
+#[test]
+fn ranges_nest() {
+    fn check(node: &SyntaxNode, source: &str) {
+        let mut start = node.range().start;
+        for child in node.children_with_tokens() {
+            let range = match &child {
+                SyntaxElement::Token(token) => token.range(),
+                SyntaxElement::Node(node) => node.range(),
+            };
+            assert_eq!(range.start, start, "{source:?}");
+            start = range.end;
+            if let SyntaxElement::Node(child) = child {
+                assert_eq!(&source[child.range()], child.text());
+                check(&child, source);
+            }
+        }
+        assert_eq!(start, node.range().end, "{source:?}");
+    }
+    for source in EMPTY.iter().chain(MALFORMED) {
+        let (tree, _) = cst::parse(source, FileId::default());
+        check(&tree, source);
+    }
+}
+
+#[test]
+fn broken_strings_are_kept_as_errors() {
+    let (tree, _) = cst::parse("(a \"oops", FileId::default());
+    assert_eq!(tokens(&tree), vec![
+        (SyntaxKind::LParen, "(".to_string()),
+        (SyntaxKind::Symbol, "a".to_string()),
+        (SyntaxKind::Whitespace, " ".to_string()),
+        (SyntaxKind::Error, "\"oops".to_string()),
+    ]);
+}
+
+#[test]
+fn unclosed_forms_end_before_the_next_top_level_form() {
+    let (tree, _) = cst::parse("(def x\n  (foo 1)\n(def y 2)\n(def z (bar)", FileId::default());
+    let forms: Vec<String> = tree.children().iter().map(|node| node.text().to_string()).collect();
+    assert_eq!(forms, vec!["(def x\n  (foo 1)\n", "(def y 2)", "(def z (bar)"]);
+}
+
+#[test]
+fn stray_closings_stay_at_the_top_level() {
+    let (tree, _) = cst::parse("(a)) ]", FileId::default());
+    let kinds: Vec<SyntaxKind> = tree.children_with_tokens()
+                                     .into_iter()
+                                     .map(|child| match child {
+                                         SyntaxElement::Token(token) => token.kind(),
+                                         SyntaxElement::Node(node) => node.kind(),
+                                     })
+                                     .collect();
+    assert_eq!(kinds, vec![
+        SyntaxKind::Form, SyntaxKind::RParen, SyntaxKind::Whitespace, SyntaxKind::RBracket,
+    ]);
+}
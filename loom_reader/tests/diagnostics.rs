@@ -0,0 +1,91 @@
+use loom_reader::diagnostics::{Diagnostic, ErrorCode, SourceMap};
+use loom_reader::parse::{Location, Span, read_all_expressions};
+
+/// Read a source file, and return the codes of its errors and where they
+/// start, along with the rendered diagnostics.
+fn errors(source: &str) -> (Vec<(ErrorCode, Location)>, Vec<String>) {
+    let mut sources = SourceMap::new();
+    let file = sources.add("test.loom", source);
+    let (_, errors) = read_all_expressions(source.to_string(), file);
+    let codes = errors.iter().map(|e| (e.code, e.span.start)).collect();
+    let rendered = errors.iter().map(|e| e.diagnostic().render(&sources, false)).collect();
+    (codes, rendered)
+}
+
+#[test]
+fn codes_and_locations() {
+    let cases = [
+        ("(foo (bar)", ErrorCode::UnclosedParen, Location::new(1, 1)),
+        ("(foo))", ErrorCode::UnexpectedParen, Location::new(1, 6)),
+        ("(foo [1 2)", ErrorCode::UnclosedBracket, Location::new(1, 6)),
+        ("]", ErrorCode::UnexpectedBracket, Location::new(1, 1)),
+        ("\"abc", ErrorCode::UnterminatedString, Location::new(1, 1)),
+        ("(foo \"a\\qb\")", ErrorCode::InvalidEscape, Location::new(1, 8)),
+        ("(a)\n  (b (c)", ErrorCode::UnclosedParen, Location::new(2, 3)),
+    ];
+    for (source, code, location) in cases {
+        assert_eq!(errors(source).0, vec![(code, location)], "{source}");
+    }
+}
+
+#[test]
+fn codes_are_numbered() {
+    assert_eq!(ErrorCode::UnclosedParen.to_string(), "E0001");
+    assert_eq!(ErrorCode::InvalidEscape.to_string(), "E0008");
+    assert_eq!(ErrorCode::ShadowedName.to_string(), "E0016");
+    assert_eq!(ErrorCode::InvalidType.to_string(), "E0018");
+}
+
+#[test]
+fn caret_under_the_offending_character() {
+    let (_, rendered) = errors("(foo))");
+    assert_eq!(rendered[0], "\
+error[E0003]: Unexpected closing paren
+ --> test.loom:1:6
+  |
+1 | (foo))
+  |      ^
+  = help: remove this `)`, or add a matching `(` before it
+");
+}
+
+#[test]
+fn underline_covers_the_span() {
+    let (_, rendered) = errors("(foo \"a\\qb\")");
+    assert!(rendered[0].contains("1 | (foo \"a\\qb\")\n  |        ^^\n"), "{}", rendered[0]);
+    assert!(rendered[0].contains("= note: the supported escapes are"), "{}", rendered[0]);
+}
+
+#[test]
+fn notes_come_before_help() {
+    let (_, rendered) = errors("(foo [1 2)");
+    let note = rendered[0].find("= note: the list is closed with `)` at 1:10").unwrap();
+    let help = rendered[0].find("= help: close the list with `]`").unwrap();
+    assert!(note < help);
+}
+
+#[test]
+fn spans_over_several_lines_are_underlined_to_the_end_of_the_first() {
+    let mut sources = SourceMap::new();
+    let file = sources.add("test.loom", "(def x\n  (foo 1))");
+    let span = Span::new(file, Location::new(1, 6), Location::new(2, 10));
+    let rendered = Diagnostic::error("Oops".to_string(), span).render(&sources, false);
+    assert!(rendered.contains("1 | (def x\n  |      ^\n"), "{rendered}");
+}
+
+#[test]
+fn color_is_optional() {
+    let mut sources = SourceMap::new();
+    let file = sources.add("test.loom", "(foo");
+    let span = Span::point(file, Location::new(1, 1));
+    let diagnostic = Diagnostic::error("Oops".to_string(), span).with_code(ErrorCode::UnclosedParen);
+    assert!(!diagnostic.render(&sources, false).contains('\x1b'));
+    assert!(diagnostic.render(&sources, true).contains("\x1b[0m"));
+}
+
+#[test]
+fn unknown_files_render_without_a_snippet() {
+    let span = Span::point(Default::default(), Location::new(3, 2));
+    let rendered = Diagnostic::error("Oops".to_string(), span).render(&SourceMap::new(), false);
+    assert_eq!(rendered, "error: Oops\n --> <input>:3:2\n");
+}
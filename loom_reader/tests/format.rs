@@ -0,0 +1,127 @@
+use loom_reader::format::{WIDTH, format};
+use loom_reader::parse::{FileId, read_expressions};
+
+fn fmt(source: &str) -> String {
+    format(source, FileId::default()).unwrap_or_else(|e| panic!("{e:?}"))
+}
+
+const SOURCES: &[&str] = &[
+    "(def x 1)",
+    "(def total (+ first-long-argument-name second-long-argument-name third-long-argument-name))",
+    "(def main (fn [] (print (+ first-long-argument-name second-long-argument-name 3))))",
+    "(fn add [a b] [] (+ a b))",
+    "(if (and first-long-argument-name second-long-argument-name) (print first-long-argument-name 2))",
+    "(let [a 1 b 2] (print a) (print b))",
+    "(draw :width 100 :height 200 :title \"a window with a long title\" :resizable true :vsync false)",
+    "(fn f [] [] ; why\n  ; how\n  (g) ; what\n\n\n  (h))",
+    "; top\n\n\n(a) ; after a\n; before b\n(b '(c ; in a quote\n d))",
+    "'(quoted ; comment\n list)",
+    "(define-syntax swap (syntax-rules () ((_ a b) (let [t a] (set a b) (set b t)))))",
+    "(cond ((= x 1) \"one\") ((= x 2) \"two\") ((= x 3) \"three\") (else \"some other number entirely\"))",
+];
+
+/// The example files at the root of the repository
+const FILES: &[(&str, &str)] = &[
+    ("basic.loom", include_str!("../../basic.loom")),
+    ("lazer.loom", include_str!("../../lazer.loom")),
+    ("shader.loom", include_str!("../../shader.loom")),
+    ("test.loom", include_str!("../../test.loom")),
+];
+
+#[test]
+fn formatting_is_idempotent() {
+    for source in SOURCES {
+        let once = fmt(source);
+        assert_eq!(fmt(&once), once, "{source}");
+    }
+}
+
+#[test]
+fn formatting_keeps_the_meaning() {
+    for source in SOURCES {
+        let before: Vec<String> = read_expressions(source.to_string()).unwrap().iter().map(|x| x.to_string()).collect();
+        let after: Vec<String> = read_expressions(fmt(source)).unwrap().iter().map(|x| x.to_string()).collect();
+        assert_eq!(after, before, "{source}");
+    }
+}
+
+#[test]
+fn comments_survive() {
+    for source in SOURCES {
+        let formatted = fmt(source);
+        for comment in source.lines().filter_map(|line| line.find(';').map(|i| &line[i..])) {
+            assert!(formatted.contains(comment), "{comment} is lost from {formatted}");
+        }
+    }
+    assert_eq!(fmt(SOURCES[7]), "\
+(fn f [] [] ; why
+    ; how
+    (g) ; what
+
+    (h)
+)
+");
+    assert_eq!(fmt(SOURCES[8]), "; top\n\n(a) ; after a\n; before b\n(b\n    '(c ; in a quote\n        d\n    )\n)\n");
+}
+
+#[test]
+fn lines_fit() {
+    for source in SOURCES {
+        for line in fmt(source).lines() {
+            assert!(line.chars().count() <= WIDTH, "{line}");
+        }
+    }
+}
+
+#[test]
+fn only_functions_hang() {
+    assert_eq!(fmt(SOURCES[1]), "\
+(def total
+    (+
+        first-long-argument-name
+        second-long-argument-name
+        third-long-argument-name
+    )
+)
+");
+    assert_eq!(fmt(SOURCES[2]), "\
+(def main (fn []
+    (print (+ first-long-argument-name second-long-argument-name 3))
+))
+");
+    assert_eq!(fmt(SOURCES[4]), "\
+(if (and first-long-argument-name second-long-argument-name)
+    (print first-long-argument-name 2)
+)
+");
+}
+
+#[test]
+fn keyword_arguments_stay_with_their_values() {
+    assert_eq!(fmt(SOURCES[6]), "\
+(draw
+    :width 100
+    :height 200
+    :title \"a window with a long title\"
+    :resizable true
+    :vsync false
+)
+");
+}
+
+#[test]
+fn syntax_errors_are_not_formatted() {
+    assert!(format("(def x", FileId::default()).is_err());
+    assert!(format("(a))", FileId::default()).is_err());
+}
+
+#[test]
+fn the_example_files_format() {
+    for (name, source) in FILES {
+        let once = fmt(source);
+        assert_eq!(fmt(&once), once, "{name}");
+        let before: Vec<String> = read_expressions(source.to_string()).unwrap().iter().map(|x| x.to_string()).collect();
+        let after: Vec<String> = read_expressions(once).unwrap().iter().map(|x| x.to_string()).collect();
+        assert_eq!(after, before, "{name}");
+    }
+}
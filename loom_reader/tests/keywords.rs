@@ -0,0 +1,82 @@
+use loom_reader::cst;
+use loom_reader::diagnostics::ErrorCode;
+use loom_reader::format::format;
+use loom_reader::parse::{Exp, FileId, Location, read_all_expressions, read_expressions};
+
+/// Read a source file with both parsers, checking they find the same errors,
+/// and return its expressions along with the codes of its errors and where
+/// they start.
+fn read(source: &str) -> (Vec<String>, Vec<(ErrorCode, Location)>) {
+    let (expressions, errors) = read_all_expressions(source.to_string(), FileId::default());
+    let errors: Vec<_> = errors.iter().map(|e| (e.code, e.span.start)).collect();
+    let (_, cst_errors) = cst::parse(source, FileId::default());
+    let cst_errors: Vec<_> = cst_errors.iter().map(|e| (e.code, e.span.start)).collect();
+    assert_eq!(cst_errors, errors, "{source}");
+    (expressions.iter().map(|x| x.to_string()).collect(), errors)
+}
+
+fn kwargs(source: &str) -> Vec<(String, String)> {
+    let x = read_expressions(source.to_string()).unwrap().remove(0);
+    let Exp::SExp { kwargs, .. } = x else { panic!("Expected a form, found {x}") };
+    kwargs.into_iter().map(|(k, v)| (k, v.to_string())).collect()
+}
+
+#[test]
+fn keyword_arguments_keep_their_order() {
+    let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+    assert_eq!(kwargs("(f :b 1 :a 2 :c 3)"), pairs(&[("b", "1"), ("a", "2"), ("c", "3")]));
+    // Positional arguments can come between them
+    assert_eq!(kwargs("(f x :b 1 y :a (g :d 4))"), pairs(&[("b", "1"), ("a", "(g :d 4)")]));
+    let x = read_expressions("(f x :b 1 y :a 2)".to_string()).unwrap().remove(0);
+    assert_eq!(x.to_string(), "(f x y :b 1 :a 2)");
+    // A keyword's value can be another keyword
+    assert_eq!(kwargs("(f :mode :fast)"), pairs(&[("mode", ":fast")]));
+}
+
+#[test]
+fn duplicate_keywords() {
+    let (expressions, errors) = read("(f :a 1 :b 2 :a 3)");
+    assert_eq!(expressions, vec!["(f :a 3 :b 2)"]);
+    assert_eq!(errors, vec![(ErrorCode::DuplicateKeyword, Location::new(1, 14))]);
+
+    let (_, errors) = read_all_expressions("(f :a 1\n   :a 2)".to_string(), FileId::default());
+    assert_eq!(errors[0].message, "Keyword argument `:a` is given more than once");
+    assert_eq!(errors[0].notes, vec!["`:a` is first given at 1:4".to_string()]);
+}
+
+#[test]
+fn dangling_keywords() {
+    let (expressions, errors) = read("(f 1 :a)");
+    assert_eq!(expressions, vec!["(f 1)"]);
+    assert_eq!(errors, vec![(ErrorCode::MissingKeywordValue, Location::new(1, 6))]);
+    let (expressions, errors) = read("(f :a :b)");
+    assert_eq!(expressions, vec!["(f :a :b)"]);
+    assert_eq!(errors, vec![]);
+}
+
+#[test]
+fn every_keyword_error_is_reported() {
+    let source = "(f :a 1 :a 2 :b)\n(g [(h :c 1 :c 2)] '(:d 1 :d))\n(i :e 1)";
+    let (expressions, errors) = read(source);
+    assert_eq!(expressions, vec!["(f :a 2)", "(g [(h :c 2)] (quote (:d 1)))", "(i :e 1)"]);
+    assert_eq!(errors, vec![
+        (ErrorCode::DuplicateKeyword, Location::new(1, 9)),
+        (ErrorCode::MissingKeywordValue, Location::new(1, 14)),
+        (ErrorCode::DuplicateKeyword, Location::new(2, 13)),
+        (ErrorCode::MissingKeywordValue, Location::new(2, 27)),
+    ]);
+    // A form's head isn't one of its arguments
+    assert_eq!(read("(:a :a 1)"), (vec!["(:a :a 1)".to_string()], vec![]));
+    // Reading a single expression stops at its first error
+    assert!(read_expressions("(f :a 1 :a 2)".to_string()).is_err());
+}
+
+#[test]
+fn keyword_errors_stop_formatting() {
+    let errors = format("(f :a 1 :a 2)", FileId::default()).unwrap_err();
+    assert_eq!(errors[0].code, ErrorCode::DuplicateKeyword);
+    let errors = format("(f (g :a))", FileId::default()).unwrap_err();
+    assert_eq!(errors[0].code, ErrorCode::MissingKeywordValue);
+}
@@ -0,0 +1,83 @@
+use loom_reader::lexer::{Lexeme, Lexer};
+use loom_reader::parse::{FileId, Location};
+
+/// The text, byte range and starting location of each lexeme.
+fn lexemes(lexer: Lexer) -> Vec<(String, std::ops::Range<usize>, Location)> {
+    lexer.map(|Lexeme { token, range }| (token.to_string(), range, token.span().start)).collect()
+}
+
+const SOURCE: &str = "(def greeting\n  \"héllo\") ; a comment\n[1 'two \"thrée\"]\n\n  (print greeting)";
+
+#[test]
+fn ranges_cover_the_source() {
+    for (_, range, _) in lexemes(Lexer::new(SOURCE, FileId::default())) {
+        assert!(SOURCE.is_char_boundary(range.start) && SOURCE.is_char_boundary(range.end));
+        assert!(!SOURCE[range].trim().is_empty());
+    }
+    let ranges: Vec<_> = Lexer::new("(a \"é\" bc)", FileId::default()).map(|l| l.range).collect();
+    assert_eq!(ranges, vec![0..1, 1..2, 3..7, 8..10, 10..11]);
+}
+
+#[test]
+fn restarting_at_any_token_gives_the_same_tokens() {
+    let all = lexemes(Lexer::new(SOURCE, FileId::default()));
+    for (i, (_, range, _)) in all.iter().enumerate() {
+        assert_eq!(lexemes(Lexer::at(SOURCE, FileId::default(), range.start)), all[i..], "at {}", range.start);
+    }
+}
+
+#[test]
+fn restarting_in_whitespace_gives_the_next_tokens() {
+    let all = lexemes(Lexer::new(SOURCE, FileId::default()));
+    for (offset, _) in SOURCE.char_indices().filter(|(_, c)| c.is_whitespace()) {
+        let restarted = lexemes(Lexer::at(SOURCE, FileId::default(), offset));
+        let next = all.iter().position(|(_, range, _)| range.start >= offset).unwrap_or(all.len());
+        // Whitespace in strings and comments isn't between tokens
+        if all.iter().all(|(_, range, _)| !range.contains(&offset)) {
+            assert_eq!(restarted, all[next..], "at {offset}");
+        }
+    }
+}
+
+#[test]
+fn restarting_counts_lines_and_characters() {
+    let offset = SOURCE.find("[1").unwrap();
+    let mut lexer = Lexer::at(SOURCE, FileId::default(), offset);
+    assert_eq!(lexer.offset(), offset);
+    let first = lexer.next().unwrap();
+    assert_eq!(first.token.span().start, Location::new(3, 1));
+    assert_eq!(lexer.offset(), offset + 1);
+
+    // Columns count characters, not bytes
+    let source = "\"é\" x";
+    let x: Vec<_> = Lexer::at(source, FileId::default(), source.len() - 1).collect();
+    assert_eq!(x[0].token.span().start, Location::new(1, 5));
+    assert_eq!(x[0].range, 5..6);
+
+    let end: Vec<_> = Lexer::at(SOURCE, FileId::default(), SOURCE.len()).collect();
+    assert!(end.is_empty());
+}
+
+#[test]
+fn restarting_after_an_edit() {
+    let edited = SOURCE.replace("(print greeting)", "(print (len greeting))");
+    let all = lexemes(Lexer::new(&edited, FileId::default()));
+    let start = SOURCE.find("(print").unwrap();
+    let restarted = lexemes(Lexer::at(&edited, FileId::default(), start));
+    assert_eq!(restarted, all[all.len() - restarted.len()..]);
+    assert_eq!(restarted[2].0, "(");
+    assert_eq!(restarted[2].2, Location::new(5, 10));
+}
+
+#[test]
+fn errors_are_only_for_what_is_read() {
+    let source = "\"a\\q\" (b \"c\\z\")";
+    let mut lexer = Lexer::new(source, FileId::default());
+    lexer.next();
+    assert_eq!(lexer.errors().len(), 1);
+    let mut lexer = Lexer::at(source, FileId::default(), source.find('(').unwrap());
+    lexer.by_ref().for_each(drop);
+    let errors = lexer.into_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span.start, Location::new(1, 12));
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::rc::Rc;
+use loom_reader::macros::Expander;
+use loom_reader::parse::{Exp, read_expressions};
+
+/// Expand the forms of a source file, and return the expansions of those which
+/// aren't macro definitions.
+fn expand(source: &str) -> Vec<String> {
+    let forms = read_expressions(source.to_string()).unwrap();
+    Expander::default().expand_all(forms)
+                       .unwrap_or_else(|e| panic!("{e}"))
+                       .iter()
+                       .map(|x| x.to_string())
+                       .collect()
+}
+
+const OR: &str = "(define-syntax my-or (syntax-rules () ((_ a b) (let [t a] (if t t b)))))";
+const TWICE: &str = "(define-syntax twice (syntax-rules () ((_ a) (helper (helper a)))))";
+
+#[test]
+fn variables_a_macro_binds_dont_capture_the_arguments() {
+    let expanded = expand(&format!("{OR} (let [t 5] (my-or false t))"));
+    assert_eq!(expanded, vec!["(let [t 5] (let [t%1 false] (if t%1 t%1 t)))"]);
+    let expanded = expand(&format!("{OR} (fn [t] (my-or t t))"));
+    assert_eq!(expanded, vec!["(fn [t] (let [t%1 t] (if t%1 t%1 t)))"]);
+}
+
+#[test]
+fn variables_around_a_use_dont_capture_what_the_macro_refers_to() {
+    let expanded = expand(&format!("{TWICE} (let [helper (fn [x] 0)] (twice 21))"));
+    assert_eq!(expanded, vec!["(let [helper%2 (fn [x] 0)] (helper (helper 21)))"]);
+    // The values of a `let` aren't in its scope
+    let expanded = expand(&format!("{TWICE} (let [helper helper] (twice helper))"));
+    assert_eq!(expanded, vec!["(let [helper%2 helper] (helper (helper helper%2)))"]);
+    let expanded = expand(&format!("{TWICE} (fn f [helper] [] (twice helper))"));
+    assert_eq!(expanded, vec!["(fn f [helper%2] [] (helper (helper helper%2)))"]);
+    let expanded = expand(&format!("{TWICE} (let-values [[a helper] (values 1 2)] (twice a))"));
+    assert_eq!(expanded, vec!["(let-values [[a helper%2] (values 1 2)] (helper (helper a)))"]);
+    // Special forms are symbols the macro refers to too
+    let expanded = expand(&format!("{OR} (let [if 1] (my-or if 2))"));
+    assert_eq!(expanded, vec!["(let [if%2 1] (let [t%3 if%2] (if t%3 t%3 2)))"]);
+}
+
+#[test]
+fn variables_which_capture_nothing_keep_their_names() {
+    let expanded = expand(&format!("{TWICE} (let [x 1] (twice x) 'helper) (let [helper 1] helper)"));
+    assert_eq!(expanded, vec!["(let [x 1] (helper (helper x)) (quote helper))", "(let [helper 1] helper)"]);
+    // A variable bound inside the macro's argument is only around the argument
+    let expanded = expand(&format!("{TWICE} (twice (let [helper 1] helper))"));
+    assert_eq!(expanded, vec!["(helper (helper (let [helper 1] helper)))"]);
+}
+
+#[test]
+fn renamed_variables_capture_inner_uses() {
+    let expanded = expand(&format!("{TWICE} (let [helper 1] (let [y helper] (twice y)) (fn [z] (+ z helper)))"));
+    assert_eq!(expanded, vec!["(let [helper%2 1] (let [y helper%2] (helper (helper y))) (fn [z] (+ z helper%2)))"]);
+}
+
+/// A procedural macro which reverses the arguments of the form it's used in,
+/// the way the interpreter defines those written with `defmacro`
+fn reverse(form: &Exp) -> Result<Exp, Box<dyn Error>> {
+    let mut args = form.args().unwrap_or_default();
+    if args.is_empty() {
+        return Err("'reverse' expects at least one argument".into());
+    }
+    args.reverse();
+    Ok(Exp::new_sexp(args, form.span()))
+}
+
+fn with_procedures(source: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut expander = Expander::default();
+    expander.define_procedure("reverse", Rc::new(reverse));
+    // Expands into a use of itself, with one argument fewer
+    expander.define_procedure("countdown", Rc::new(|form: &Exp| {
+        let args = form.args().unwrap_or_default();
+        Ok(match args.split_first() {
+            Some((_, rest)) if !rest.is_empty() => {
+                let mut items = vec![Exp::Symbol("countdown".to_string(), form.span())];
+                items.extend(rest.iter().cloned());
+                Exp::new_sexp(items, form.span())
+            }
+            Some((last, _)) => last.clone(),
+            None => return Err("'countdown' expects an argument".into()),
+        })
+    }));
+    expander.define_procedure("forever", Rc::new(|form: &Exp| Ok(form.clone())));
+    let forms = read_expressions(source.to_string())?;
+    Ok(expander.expand_all(forms)?.iter().map(|x| x.to_string()).collect())
+}
+
+#[test]
+fn procedures_expand_uses() {
+    assert_eq!(with_procedures("(reverse 1 2 +)").unwrap(), vec!["(+ 2 1)"]);
+    // Inside other forms, and inside their own arguments
+    assert_eq!(with_procedures("(f (reverse 1 (reverse x g)))").unwrap(), vec!["(f ((g x) 1))"]);
+}
+
+#[test]
+fn procedure_errors_stop_expansion() {
+    let e = with_procedures("(f 1)\n(g (reverse))").unwrap_err();
+    assert_eq!(e.to_string(), "'reverse' expects at least one argument");
+}
+
+#[test]
+fn expansions_are_expanded_again() {
+    assert_eq!(with_procedures("(countdown 3 2 1 done)").unwrap(), vec!["done"]);
+    assert_eq!(with_procedures("(countdown (reverse 1 inc) x)").unwrap(), vec!["x"]);
+    assert_eq!(with_procedures("(countdown (reverse 1 inc))").unwrap(), vec!["(inc 1)"]);
+    // Until a macro keeps expanding into itself
+    let e = with_procedures("(forever 1)").unwrap_err();
+    assert!(e.to_string().contains("Macro 'forever' keeps expanding into itself"), "{e}");
+}
@@ -0,0 +1,88 @@
+use loom_reader::cst;
+use loom_reader::diagnostics::ErrorCode;
+use loom_reader::parse::{Exp, FileId, Location, read_all_expressions};
+
+/// Read a source file with both parsers, checking they agree, and return its
+/// expressions along with the codes of its errors and where they start.
+fn read(source: &str) -> (Vec<String>, Vec<(ErrorCode, Location)>) {
+    let (expressions, errors) = read_all_expressions(source.to_string(), FileId::default());
+    let expressions: Vec<String> = expressions.iter().map(|x| x.to_string()).collect();
+    let errors: Vec<_> = errors.iter().map(|e| (e.code, e.span.start)).collect();
+
+    let (tree, cst_errors) = cst::parse(source, FileId::default());
+    let cst_expressions: Vec<String> = tree.to_exps()
+                                           .map(|xs| xs.iter().map(|x| x.to_string()).collect())
+                                           .unwrap_or_default();
+    let cst_errors: Vec<_> = cst_errors.iter().map(|e| (e.code, e.span.start)).collect();
+    if errors.is_empty() {
+        assert_eq!(cst_expressions, expressions, "{source}");
+    }
+    assert_eq!(cst_errors, errors, "{source}");
+    (expressions, errors)
+}
+
+fn read_one(source: &str) -> String {
+    let (mut expressions, errors) = read(source);
+    assert_eq!(errors, vec![], "{source}");
+    assert_eq!(expressions.len(), 1, "{source}");
+    expressions.remove(0)
+}
+
+#[test]
+fn quotes_wrap_the_next_expression() {
+    assert_eq!(read_one("'x"), "(quote x)");
+    assert_eq!(read_one("'(a b)"), "(quote (a b))");
+    assert_eq!(read_one("'[1 2]"), "(quote [1 2])");
+    assert_eq!(read_one("`(a ,b ,@c)"), "(quasiquote (a (unquote b) (unquote-splicing c)))");
+    assert_eq!(read_one("`[,x]"), "(quasiquote [(unquote x)])");
+    assert_eq!(read_one("'\"text\""), "(quote \"text\")");
+    assert_eq!(read_one("'()"), "(quote nil)");
+}
+
+#[test]
+fn quotes_nest() {
+    assert_eq!(read_one("''x"), "(quote (quote x))");
+    assert_eq!(read_one("`(a `(b ,,c))"), "(quasiquote (a (quasiquote (b (unquote (unquote c))))))");
+    assert_eq!(read_one(",@'x"), "(unquote-splicing (quote x))");
+}
+
+#[test]
+fn quotes_skip_whitespace_and_comments() {
+    assert_eq!(read_one("' x"), "(quote x)");
+    assert_eq!(read_one("'\n  (a b)"), "(quote (a b))");
+    assert_eq!(read_one("' ; the list\n(a b)"), "(quote (a b))");
+}
+
+#[test]
+fn quotes_are_spanned_from_the_quote() {
+    let (expressions, _) = read_all_expressions("(f\n  `(a ,b))".to_string(), FileId::default());
+    let Exp::SExp { args, .. } = &expressions[0] else { panic!() };
+    let span = args[0].span();
+    assert_eq!((span.start, span.end), (Location::new(2, 3), Location::new(2, 9)));
+    let Exp::SExp { args, .. } = &args[0] else { panic!() };
+    let Exp::SExp { args, .. } = &args[0] else { panic!() };
+    let span = args[0].span();
+    assert_eq!((span.start, span.end), (Location::new(2, 7), Location::new(2, 8)));
+}
+
+#[test]
+fn an_unquote_splice_is_one_token() {
+    // `, @x` unquotes the symbol `@x`
+    assert_eq!(read_one(", @x"), "(unquote @x)");
+    assert_eq!(read_one("'a,b"), "(quote a,b)");
+}
+
+#[test]
+fn dangling_quotes() {
+    let cases = [
+        ("'", vec![], vec![(ErrorCode::DanglingQuote, Location::new(1, 1))]),
+        ("(a ')", vec![], vec![(ErrorCode::DanglingQuote, Location::new(1, 4))]),
+        ("[`]", vec![], vec![(ErrorCode::DanglingQuote, Location::new(1, 2))]),
+        ("(a ,@)\n(b)", vec!["(b)"], vec![(ErrorCode::DanglingQuote, Location::new(1, 4))]),
+        ("(a)\n' ; nothing", vec!["(a)"], vec![(ErrorCode::DanglingQuote, Location::new(2, 1))]),
+    ];
+    for (source, expressions, errors) in cases {
+        let expressions: Vec<String> = expressions.into_iter().map(String::from).collect();
+        assert_eq!(read(source), (expressions, errors), "{source}");
+    }
+}
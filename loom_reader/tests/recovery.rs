@@ -0,0 +1,45 @@
+use loom_reader::cst;
+use loom_reader::diagnostics::ErrorCode;
+use loom_reader::parse::{FileId, Location, read_all_expressions};
+
+fn read(source: &str) -> (Vec<String>, Vec<(ErrorCode, Location)>) {
+    let (expressions, errors) = read_all_expressions(source.to_string(), FileId::default());
+    let expressions = expressions.iter().map(|x| x.to_string()).collect();
+    (expressions, errors.iter().map(|e| (e.code, e.span.start)).collect())
+}
+
+fn parse_errors(source: &str) -> Vec<(ErrorCode, Location)> {
+    let (_, errors) = cst::parse(source, FileId::default());
+    errors.iter().map(|e| (e.code, e.span.start)).collect()
+}
+
+#[test]
+fn nested_forms_can_start_a_line() {
+    let source = "(def x\n(foo 1))\n(def y\n[1\n2])";
+    assert_eq!(read(source), (vec!["(def x (foo 1))".to_string(), "(def y [1 2])".to_string()], vec![]));
+    assert_eq!(parse_errors(source), vec![]);
+}
+
+#[test]
+fn unclosed_forms_end_at_the_next_line_which_starts_a_form() {
+    let source = "(def x\n  (foo 1)\n(def y 2)\n(def z (bar)";
+    let errors = vec![
+        (ErrorCode::UnclosedParen, Location::new(1, 1)),
+        (ErrorCode::UnclosedParen, Location::new(4, 1)),
+    ];
+    assert_eq!(read(source), (vec!["(def y 2)".to_string()], errors.clone()));
+    assert_eq!(parse_errors(source), errors);
+}
+
+#[test]
+fn every_error_is_reported() {
+    let source = ")\n(a [b)\n(c)\n'";
+    let (expressions, errors) = read(source);
+    assert_eq!(expressions, vec!["(c)".to_string()]);
+    assert_eq!(errors, vec![
+        (ErrorCode::UnexpectedParen, Location::new(1, 1)),
+        (ErrorCode::UnclosedBracket, Location::new(2, 4)),
+        (ErrorCode::DanglingQuote, Location::new(4, 1)),
+    ]);
+    assert_eq!(parse_errors(source), errors);
+}
@@ -0,0 +1,110 @@
+use loom_reader::cst;
+use loom_reader::diagnostics::ErrorCode;
+use loom_reader::parse::{Exp, FileId, Location, read_all_expressions};
+
+/// Read a source file, and return its strings, along with the codes of its
+/// errors and where they start.
+fn read(source: &str) -> (Vec<String>, Vec<(ErrorCode, Location)>) {
+    let (expressions, errors) = read_all_expressions(source.to_string(), FileId::default());
+    let strings = expressions.into_iter()
+                             .map(|x| match x {
+                                 Exp::Str(s, _) => s,
+                                 x => panic!("Expected a string, found {x}"),
+                             })
+                             .collect();
+    (strings, errors.iter().map(|e| (e.code, e.span.start)).collect())
+}
+
+fn string(source: &str) -> String {
+    let (mut strings, errors) = read(source);
+    assert_eq!(errors, vec![], "{source}");
+    assert_eq!(strings.len(), 1, "{source}");
+    strings.remove(0)
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(string(r#""a\"b\\c\nd\te\rf""#), "a\"b\\c\nd\te\rf");
+    assert_eq!(string(r#""\u{41}\u{e9}\u{1F600}""#), "A\u{e9}\u{1F600}");
+    assert_eq!(string(r#""\u{10FFFF}""#), "\u{10FFFF}");
+    assert_eq!(string(r#""""#), "");
+    // A newline can be written as it is
+    assert_eq!(string("\"a\nb\""), "a\nb");
+}
+
+#[test]
+fn invalid_escapes_keep_the_rest_of_the_string() {
+    let cases = [
+        (r#""a\qb""#, "ab", 3),
+        (r#""a\u41b""#, "a41b", 3),
+        (r#""\u{41""#, "", 2),
+        (r#""\u{41 b""#, " b", 2),
+        (r#""\u{110000}""#, "", 2),
+        (r#""\u{D800}""#, "", 2),
+        (r#""\u{0000041}""#, "", 2),
+        (r#""\u{}""#, "", 2),
+    ];
+    for (source, content, column) in cases {
+        let (strings, errors) = read(source);
+        assert_eq!(strings, vec![content.to_string()], "{source}");
+        assert_eq!(errors, vec![(ErrorCode::InvalidEscape, Location::new(1, column))], "{source}");
+    }
+}
+
+#[test]
+fn every_invalid_escape_is_reported() {
+    let (strings, errors) = read(r#""\q\u{110000}\x""#);
+    assert_eq!(strings, vec![String::new()]);
+    assert_eq!(errors, vec![
+        (ErrorCode::InvalidEscape, Location::new(1, 2)),
+        (ErrorCode::InvalidEscape, Location::new(1, 4)),
+        (ErrorCode::InvalidEscape, Location::new(1, 14)),
+    ]);
+}
+
+#[test]
+fn unterminated_strings() {
+    let cases = [
+        ("\"abc", Location::new(1, 1)),
+        ("\"abc\\\"", Location::new(1, 1)),
+        ("\"abc\\", Location::new(1, 1)),
+        ("\"\\u{41", Location::new(1, 1)),
+        ("\"a\" \"b", Location::new(1, 5)),
+        ("r\"abc", Location::new(1, 1)),
+        ("r#\"abc\"", Location::new(1, 1)),
+        ("r##\"abc\"#", Location::new(1, 1)),
+    ];
+    for (source, location) in cases {
+        let (_, errors) = read(source);
+        assert_eq!(errors, vec![(ErrorCode::UnterminatedString, location)], "{source}");
+    }
+    // The formatter's parser skips broken strings too
+    let (_, errors) = cst::parse("(print \"abc)", FileId::default());
+    let codes: Vec<_> = errors.iter().map(|e| e.code).collect();
+    assert_eq!(codes, vec![ErrorCode::UnclosedParen, ErrorCode::UnterminatedString]);
+}
+
+#[test]
+fn raw_strings() {
+    assert_eq!(string(r#"r"a\nb""#), "a\\nb");
+    assert_eq!(string(r#"r"C:\path\""#), "C:\\path\\");
+    assert_eq!(string(r##"r#"say "hi""#"##), "say \"hi\"");
+    assert_eq!(string(r###"r##"a"#b"##"###), "a\"#b");
+    assert_eq!(string(r#"r"""#), "");
+    assert_eq!(string("r\"a\nb\""), "a\nb");
+}
+
+#[test]
+fn raw_strings_need_an_r_and_hashes() {
+    let (expressions, errors) = read_all_expressions(r#"(r "a" rx"b" ra)"#.to_string(), FileId::default());
+    assert_eq!(errors.len(), 0);
+    assert_eq!(expressions[0].to_string(), r#"(r "a" rx "b" ra)"#);
+}
+
+#[test]
+fn strings_display_escaped() {
+    for source in [r#""a\"b\\c\nd\te\rf""#, r#""\u{1b}[0m""#, "\"caf\u{e9} \u{1F600}\""] {
+        assert_eq!(read_all_expressions(source.to_string(), FileId::default()).0[0].to_string(), source);
+    }
+    assert_eq!(read_all_expressions(r##"r#"a\"b"#"##.to_string(), FileId::default()).0[0].to_string(), r#""a\\\"b""#);
+}
@@ -1,18 +1,14 @@
 use std::fs;
 use std::error::Error;
-use loom_reader::parse::{
-    Exp, read_expressions
-};
-use loom_runtime::eval::eval_expression;
+use loom_runtime::eval::Interpreter;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let source = fs::read_to_string("test.loom")?;
-    let expressions = read_expressions(source)?;
+    let mut interpreter = Interpreter::default();
+    interpreter.eval_source(&source)?;
 
-    for x in expressions {
-        //println!("{x}");
-        println!("--> {}", eval_expression(x));
-        //println!("{x:#?}\n");
+    for call in ["(foo 1 0)", "(recursive_fib 10)", "(iterative_fib 10)"] {
+        println!("{call} --> {}", interpreter.eval_source(call)?);
     }
 
     Ok(())
@@ -0,0 +1,41 @@
+use std::error::Error;
+use loom_reader::parse::read_expressions;
+use loom_runtime::eval::Interpreter;
+
+/// A sprite DSL on top of the one in lazer.loom: `layers` stacks sprites on
+/// top of each other, and `talking-mouth` builds a frame sequence from a
+/// prefix and a frame count.
+const SOURCE: &str = r#"
+(defmacro talking-mouth [prefix frames signal]
+    (set paths (array frames))
+    (set i 0)
+    (while (< i frames)
+        (array_set paths i (str prefix i ".png"))
+        (set i (+ i 1))
+    )
+    `(tex-sequence :paths ,paths :time ,signal))
+
+(defmacro layers [images]
+    (set images (items images))
+    (set sprites (array (len images)))
+    (set depth 0)
+    (while (< depth (len images))
+        (array_set sprites depth `(sprite :image ,(array_get images depth) :depth ,depth))
+        (set depth (+ depth 1))
+    )
+    `(do ,@sprites))
+
+(layers [
+    (tex :path "Base.png")
+    (talking-mouth "Mouth" 3 talking)
+])
+"#;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::default();
+    let forms = interpreter.expand_all(read_expressions(SOURCE.to_string())?)?;
+    for x in forms {
+        println!("{x}");
+    }
+    Ok(())
+}
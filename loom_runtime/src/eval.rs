@@ -1,23 +1,889 @@
-use loom_reader::parse::Exp;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use loom_reader::macros::Expander;
+use loom_reader::parse::{Exp, Span, read_expressions};
 
-pub fn eval_expression(x: Exp) -> Exp {
-    match x {
-        Exp::SExp { car, cdr } => {
-            match *car {
-                Exp::Symbol { contents } => {
-                    match contents.as_str() {
-                        "print" => {
-                            for i in cdr {
-                                println!("{i}");
+/// A runtime value
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Keyword(String),
+    /// Quoted code, such as a symbol or a list
+    Quoted(Rc<Exp>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Closure(Rc<Closure>),
+    Builtin(&'static str),
+    /// Several values at once, as `values` returns them
+    Values(Rc<Vec<Value>>),
+}
+
+impl Value {
+    /// Whether this value counts as true in a condition. Like the JIT, `0` is
+    /// false, as are `nil` and `false`.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false) | Value::Int(0))
+    }
+
+    /// The integer representation of this value, as the JIT would see it.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Nil => Some(0),
+            Value::Int(n) => Some(*n),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None
+        }
+    }
+}
+
+impl Value {
+    /// The value of quoted code: atoms evaluate to themselves, anything else
+    /// stays code.
+    pub fn from_exp(x: &Exp) -> Self {
+        match x {
+            Exp::Nil(_) => Value::Nil,
+            Exp::Int(n, _) => Value::Int(*n),
+            Exp::Float(n, _) => Value::Float(*n),
+            Exp::Bool(b, _) => Value::Bool(*b),
+            Exp::Str(s, _) => Value::Str(s.clone()),
+            Exp::Keyword(name, _) => Value::Keyword(name.clone()),
+            _ => Value::Quoted(Rc::new(x.clone())),
+        }
+    }
+
+    /// The code for this value, which is at `span` in the source
+    pub fn to_exp(&self, span: Span) -> Result<Exp, Box<dyn Error>> {
+        Ok(match self {
+            Value::Nil => Exp::Nil(span),
+            Value::Int(n) => Exp::Int(*n, span),
+            Value::Float(n) => Exp::Float(*n, span),
+            Value::Bool(b) => Exp::Bool(*b, span),
+            Value::Str(s) => Exp::Str(s.clone(), span),
+            Value::Keyword(name) => Exp::Keyword(name.clone(), span),
+            Value::Quoted(x) => (**x).clone(),
+            Value::Array(contents) => {
+                let items = contents.borrow()
+                                    .iter()
+                                    .map(|v| v.to_exp(span))
+                                    .collect::<Result<Vec<Exp>, _>>()?;
+                Exp::List(items, span)
+            }
+            Value::Closure(_) | Value::Builtin(_) | Value::Values(_) => {
+                return Err(EvalError::boxed(format!("{span}: {self} can't be turned into code")));
+            }
+        })
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Float(n) => write!(f, "{n:?}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::Keyword(name) => write!(f, ":{name}"),
+            Value::Quoted(x) => write!(f, "{x}"),
+            Value::Array(contents) => {
+                let inner = contents.borrow()
+                                    .iter()
+                                    .map(|x| format!("{x}"))
+                                    .collect::<Vec<String>>()
+                                    .join(" ");
+                write!(f, "[{inner}]")
+            }
+            Value::Closure(c) => match &c.name {
+                Some(name) => write!(f, "<fn {name}>"),
+                None => write!(f, "<fn>"),
+            }
+            Value::Builtin(name) => write!(f, "<builtin {name}>"),
+            Value::Values(values) => {
+                let inner = values.iter()
+                                  .map(|x| format!("{x}"))
+                                  .collect::<Vec<String>>()
+                                  .join(" ");
+                write!(f, "(values {inner})")
+            }
+        }
+    }
+}
+
+/// A function parameter, with an optional default value expression
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<Exp>,
+}
+
+/// A function together with the environment it was defined in
+#[derive(Debug)]
+pub struct Closure {
+    pub name: Option<String>,
+    pub params: Vec<Param>,
+    pub body: Vec<Exp>,
+    pub env: Env,
+}
+
+/// A lexical environment, holding the variables of one scope
+#[derive(Debug, Default)]
+pub struct Scope {
+    vars: HashMap<String, Value>,
+    parent: Option<Env>,
+}
+
+pub type Env = Rc<RefCell<Scope>>;
+
+impl Scope {
+    pub fn new(parent: Option<Env>) -> Env {
+        Rc::new(RefCell::new(Self { vars: HashMap::new(), parent }))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.vars.get(name) {
+            Some(v) => Some(v.clone()),
+            None => self.parent.as_ref().and_then(|p| p.borrow().get(name))
+        }
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Assign to the innermost existing binding of `name`, returning false if
+    /// there is none.
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        if let Some(v) = self.vars.get_mut(name) {
+            *v = value;
+            true
+        } else {
+            match &self.parent {
+                Some(p) => p.borrow_mut().assign(name, value),
+                None => false
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EvalError {
+    pub message: String,
+}
+
+impl EvalError {
+    fn boxed(message: String) -> Box<dyn Error> {
+        Box::new(Self { message })
+    }
+}
+
+impl Error for EvalError {}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The names of the builtin functions in the global environment
+const BUILTINS: &[&str] = &[
+    "+", "-", "*", "/", "%", "=", "!=", "<", "<=", ">", ">=", "not",
+    "print", "time.now", "array", "array_get", "array_set",
+    "list", "len", "str", "symbol", "items", "values",
+    "int", "f32", "float", "f64",
+];
+
+/// A tree-walking interpreter for Loom.
+///
+/// This is the reference implementation of the language: the JIT is expected
+/// to agree with it on every program the JIT can compile.
+pub struct Interpreter {
+    pub globals: Env,
+    /// Expands macros before evaluation, and remembers their definitions
+    pub macros: Expander,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let globals = Scope::new(None);
+        for name in BUILTINS {
+            globals.borrow_mut().define(name, Value::Builtin(name));
+        }
+        Self { globals, macros: Expander::default() }
+    }
+}
+
+impl Interpreter {
+    /// Read and evaluate every expression in `source`, returning the value of
+    /// the last one.
+    pub fn eval_source(&mut self, source: &str) -> Result<Value, Box<dyn Error>> {
+        let mut result = Value::Nil;
+        for x in read_expressions(source.to_string())? {
+            result = self.eval(&x)?;
+        }
+        Ok(result)
+    }
+
+    /// Expand and evaluate an expression in the global environment. Macro
+    /// definitions evaluate to nil.
+    pub fn eval(&mut self, x: &Exp) -> Result<Value, Box<dyn Error>> {
+        match self.expand_top(x)? {
+            Some(x) => eval(&x, &self.globals),
+            None => Ok(Value::Nil),
+        }
+    }
+
+    /// Expand a top-level form without evaluating it, or define a macro if
+    /// it's a `define-syntax` or `defmacro` form. This is how procedural
+    /// macros are run for the JIT.
+    pub fn expand_top(&mut self, x: &Exp) -> Result<Option<Exp>, Box<dyn Error>> {
+        if x.car_symbol().as_deref() == Some("defmacro") {
+            self.define_macro(x)?;
+            return Ok(None);
+        }
+        self.macros.expand_top(x)
+    }
+
+    /// Expand every top-level form, leaving out macro definitions.
+    pub fn expand_all(&mut self, forms: Vec<Exp>) -> Result<Vec<Exp>, Box<dyn Error>> {
+        let mut expanded = Vec::new();
+        for x in forms {
+            if let Some(x) = self.expand_top(&x)? {
+                expanded.push(x);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// `(defmacro name [params] body...)`
+    ///
+    /// The body is run on the unevaluated arguments of each use, as quoted
+    /// data, and returns the code to replace the use with.
+    fn define_macro(&mut self, x: &Exp) -> Result<(), Box<dyn Error>> {
+        let x = self.macros.expand(x)?;
+        let Some(name) = x.arg_symbol(0) else {
+            return Err(EvalError::boxed(format!("{}: Expected a name for the macro", x.span())));
+        };
+        let Some(Exp::List(params, _)) = x.arg(1) else {
+            return Err(EvalError::boxed(format!("{}: Expected a parameter list for '{name}'", x.span())));
+        };
+        let closure = Rc::new(Closure {
+            name: Some(name.clone()),
+            params: parse_params(&params)?,
+            body: x.args().unwrap_or_default().split_off(2),
+            env: self.globals.clone(),
+        });
+        self.macros.define_procedure(&name, Rc::new(move |form: &Exp| {
+            let Exp::SExp { args, kwargs, span, .. } = form else {
+                return Err(EvalError::boxed(format!("Invalid macro use: {form}")));
+            };
+            let values = args.iter().map(Value::from_exp).collect();
+            let keywords = kwargs.iter()
+                                 .map(|(k, v)| (k.clone(), Value::from_exp(v)))
+                                 .collect();
+            let expansion = call_closure(&closure, values, keywords)
+                .map_err(|e| EvalError::boxed(format!("{span}: In macro '{}': {e}", closure.name.as_deref().unwrap_or("?"))))?;
+            expansion.to_exp(*span)
+        }));
+        Ok(())
+    }
+
+    /// Call a function value with positional arguments
+    pub fn call(&mut self, f: &Value, args: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+        match f {
+            Value::Builtin(name) => call_builtin(name, args),
+            Value::Closure(c) => call_closure(c, args, Vec::new()),
+            _ => Err(EvalError::boxed(format!("{f} is not a function")))
+        }
+    }
+}
+
+fn call_closure(
+    c: &Closure,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+) -> Result<Value, Box<dyn Error>> {
+    let env = bind_params(c, args, kwargs)?;
+    let mut result = Value::Nil;
+    for x in &c.body {
+        result = eval(x, &env)?;
+    }
+    Ok(result)
+}
+
+/// Evaluate an expression in the given environment.
+///
+/// Expressions in tail position (the branches of `if`, the last expression of
+/// `do` and of function bodies) are evaluated by looping instead of recursing,
+/// so tail calls run in constant stack space.
+pub fn eval(x: &Exp, env: &Env) -> Result<Value, Box<dyn Error>> {
+    let mut x = x.clone();
+    let mut env = env.clone();
+    loop {
+        match &x {
+            Exp::Nil(_) => return Ok(Value::Nil),
+            Exp::Int(n, _) => return Ok(Value::Int(*n)),
+            Exp::Float(n, _) => return Ok(Value::Float(*n)),
+            Exp::Bool(b, _) => return Ok(Value::Bool(*b)),
+            Exp::Str(s, _) => return Ok(Value::Str(s.clone())),
+            Exp::Keyword(name, _) => return Ok(Value::Keyword(name.clone())),
+            Exp::Symbol(name, span) => {
+                return env.borrow().get(name).ok_or_else(|| {
+                    EvalError::boxed(format!("{span}: Variable '{name}' is not defined"))
+                });
+            }
+            Exp::List(contents, _) => {
+                let items = contents.iter()
+                                    .map(|i| eval(i, &env))
+                                    .collect::<Result<Vec<Value>, _>>()?;
+                return Ok(Value::Array(Rc::new(RefCell::new(items))));
+            }
+            Exp::SExp { kind, args, kwargs, .. } => {
+                match kind.as_symbol().as_deref() {
+                    Some("def") => return eval_def(&x, &env),
+                    Some("quote") => {
+                        let [quoted] = args.as_slice() else {
+                            return Err(EvalError::boxed(format!("'quote' expects 1 argument: {x}")));
+                        };
+                        return Ok(Value::from_exp(quoted));
+                    }
+                    Some("quasiquote") => {
+                        let [quoted] = args.as_slice() else {
+                            return Err(EvalError::boxed(format!("'quasiquote' expects 1 argument: {x}")));
+                        };
+                        return Ok(Value::from_exp(&quasiquote(quoted, 1, &env)?));
+                    }
+                    Some("fn") => return eval_fn(&x, &env),
+                    Some("set") => {
+                        let Some(name) = x.arg_symbol(0) else {
+                            return Err(EvalError::boxed(format!("'set' expects a variable name: {x}")));
+                        };
+                        let value = eval_arg(&x, 1, &env)?;
+                        // Like in the JIT, assigning to an unknown variable
+                        // declares it in the current scope.
+                        if !env.borrow_mut().assign(&name, value.clone()) {
+                            env.borrow_mut().define(&name, value.clone());
+                        }
+                        return Ok(value);
+                    }
+                    Some("if") => {
+                        let condition = eval_arg(&x, 0, &env)?;
+                        let branch = if condition.is_truthy() { 1 } else { 2 };
+                        match args.get(branch) {
+                            Some(next) => x = next.clone(),
+                            None => return Ok(Value::Nil),
+                        }
+                    }
+                    Some("do") => {
+                        let Some((last, init)) = args.split_last() else {
+                            return Ok(Value::Nil);
+                        };
+                        for i in init {
+                            eval(i, &env)?;
+                        }
+                        x = last.clone();
+                    }
+                    Some("let") => {
+                        let Some((Exp::List(bindings, _), body)) = args.split_first() else {
+                            return Err(EvalError::boxed(format!("'let' expects a list of bindings: {x}")));
+                        };
+                        if bindings.len() % 2 != 0 {
+                            return Err(EvalError::boxed(format!("'let' expects a value for every name: {x}")));
+                        }
+                        // Every value is evaluated before any name is bound
+                        let scope = Scope::new(Some(env.clone()));
+                        for pair in bindings.chunks(2) {
+                            let Some(name) = pair[0].as_symbol() else {
+                                return Err(EvalError::boxed(format!("Expected a variable name, found {}", pair[0])));
+                            };
+                            let value = eval(&pair[1], &env)?;
+                            scope.borrow_mut().define(&name, value);
+                        }
+                        let Some((last, init)) = body.split_last() else {
+                            return Ok(Value::Nil);
+                        };
+                        env = scope;
+                        for i in init {
+                            eval(i, &env)?;
+                        }
+                        x = last.clone();
+                    }
+                    Some("let-values") => {
+                        let Some((Exp::List(bindings, _), body)) = args.split_first() else {
+                            return Err(EvalError::boxed(format!("'let-values' expects a list of bindings: {x}")));
+                        };
+                        if bindings.len() % 2 != 0 {
+                            return Err(EvalError::boxed(format!("'let-values' expects a value for every list of names: {x}")));
+                        }
+                        let scope = Scope::new(Some(env.clone()));
+                        for pair in bindings.chunks(2) {
+                            let Exp::List(names, _) = &pair[0] else {
+                                return Err(EvalError::boxed(format!("Expected a list of variable names, found {}", pair[0])));
+                            };
+                            let values = match eval(&pair[1], &env)? {
+                                Value::Values(values) => values.as_ref().clone(),
+                                value => vec![value],
+                            };
+                            if names.len() != values.len() {
+                                return Err(EvalError::boxed(format!(
+                                    "{}: Expected {} values, got {}", pair[1].span(), names.len(), values.len()
+                                )));
+                            }
+                            for (name, value) in names.iter().zip(values) {
+                                let Some(name) = name.as_symbol() else {
+                                    return Err(EvalError::boxed(format!("Expected a variable name, found {name}")));
+                                };
+                                scope.borrow_mut().define(&name, value);
                             }
-                            Exp::Nil
                         }
-                        _ => Exp::Nil
+                        let Some((last, init)) = body.split_last() else {
+                            return Ok(Value::Nil);
+                        };
+                        env = scope;
+                        for i in init {
+                            eval(i, &env)?;
+                        }
+                        x = last.clone();
+                    }
+                    Some("while") => {
+                        while eval_arg(&x, 0, &env)?.is_truthy() {
+                            for i in args.iter().skip(1) {
+                                eval(i, &env)?;
+                            }
+                        }
+                        return Ok(Value::Int(0));
                     }
+                    _ => {
+                        let f = eval(kind, &env)?;
+                        let values = args.iter()
+                                         .map(|i| eval(i, &env))
+                                         .collect::<Result<Vec<Value>, _>>()?;
+                        let mut keywords: Vec<(String, Value)> = Vec::new();
+                        for (k, v) in kwargs {
+                            keywords.push((k.clone(), eval(v, &env)?));
+                        }
+                        match f {
+                            Value::Builtin(name) => {
+                                if let Some((k, _)) = keywords.first() {
+                                    return Err(EvalError::boxed(format!(
+                                        "'{name}' does not accept keyword arguments, got :{k}"
+                                    )));
+                                }
+                                return call_builtin(name, values);
+                            }
+                            Value::Closure(c) => {
+                                // Tail call: continue with the callee's body
+                                // instead of recursing.
+                                env = bind_params(&c, values, keywords)?;
+                                let Some((last, init)) = c.body.split_last() else {
+                                    return Ok(Value::Nil);
+                                };
+                                for i in init {
+                                    eval(i, &env)?;
+                                }
+                                x = last.clone();
+                            }
+                            _ => return Err(EvalError::boxed(format!("{f} is not a function")))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn eval_arg(x: &Exp, index: usize, env: &Env) -> Result<Value, Box<dyn Error>> {
+    let arg = match x {
+        Exp::SExp { args, .. } => args.get(index),
+        _ => None
+    };
+    match arg {
+        Some(arg) => eval(arg, env),
+        None => Err(EvalError::boxed(format!("Missing argument {index} in {x}")))
+    }
+}
+
+/// Fill in a quasiquoted template: `(unquote x)` is replaced by the value of
+/// `x`, and `(unquote-splicing x)` by the items of the list `x` evaluates to.
+/// Nested quasiquotes are left alone, except for their deepest unquotes.
+fn quasiquote(x: &Exp, depth: usize, env: &Env) -> Result<Exp, Box<dyn Error>> {
+    let form = x.car_symbol();
+    match form.as_deref() {
+        Some("unquote") if depth == 1 => {
+            let Some(arg) = x.arg(0) else {
+                return Err(EvalError::boxed(format!("{}: 'unquote' expects 1 argument", x.span())));
+            };
+            return eval(&arg, env)?.to_exp(arg.span());
+        }
+        Some("unquote-splicing") if depth == 1 => {
+            return Err(EvalError::boxed(format!("{}: Can only splice into a list: {x}", x.span())));
+        }
+        _ => {}
+    }
+    let depth = match form.as_deref() {
+        Some("quasiquote") => depth + 1,
+        Some("unquote") | Some("unquote-splicing") => depth - 1,
+        _ => depth,
+    };
+    let items = match x {
+        Exp::List(items, _) => items.clone(),
+        Exp::SExp { .. } => x.items().unwrap_or_default(),
+        _ => return Ok(x.clone()),
+    };
+    let mut filled = Vec::new();
+    for item in items {
+        if depth == 1 && item.car_symbol().as_deref() == Some("unquote-splicing") {
+            let Some(arg) = item.arg(0) else {
+                return Err(EvalError::boxed(format!("{}: 'unquote-splicing' expects 1 argument", item.span())));
+            };
+            match eval(&arg, env)?.to_exp(arg.span())? {
+                Exp::List(spliced, _) => filled.extend(spliced),
+                x @ Exp::SExp { .. } => filled.extend(x.items().unwrap_or_default()),
+                Exp::Nil(_) => {}
+                other => {
+                    return Err(EvalError::boxed(format!("{}: Can't splice {other}, it isn't a list", arg.span())));
+                }
+            }
+        } else {
+            filled.push(quasiquote(&item, depth, env)?);
+        }
+    }
+    Ok(match x {
+        Exp::List(_, span) => Exp::List(filled, *span),
+        _ if filled.is_empty() => Exp::Nil(x.span()),
+        _ => Exp::new_sexp(filled, x.span()),
+    })
+}
+
+/// `(def name value)` or `(def (name params...) body...)`
+fn eval_def(x: &Exp, env: &Env) -> Result<Value, Box<dyn Error>> {
+    match x.arg(0) {
+        Some(Exp::Symbol(name, _)) => {
+            let mut value = eval_arg(x, 1, env)?;
+            // Name anonymous functions after the variable they're bound to
+            if let Value::Closure(c) = &value {
+                if c.name.is_none() {
+                    value = Value::Closure(Rc::new(Closure {
+                        name: Some(name.clone()),
+                        params: c.params.clone(),
+                        body: c.body.clone(),
+                        env: c.env.clone(),
+                    }));
+                }
+            }
+            env.borrow_mut().define(&name, value);
+            Ok(Value::Nil)
+        }
+        Some(signature @ Exp::SExp { .. }) => {
+            let Some(name) = signature.car_symbol() else {
+                return Err(EvalError::boxed(format!("Invalid function name in {x}")));
+            };
+            let params = parse_params(&signature.args().unwrap_or_default())?;
+            let body = x.args().unwrap_or_default().split_off(1);
+            define_closure(name, params, body, env);
+            Ok(Value::Nil)
+        }
+        _ => Err(EvalError::boxed(format!("Invalid definition: {x}")))
+    }
+}
+
+/// `(fn [params] body...)` or the compiler's `(fn name [params] [] body...)`
+fn eval_fn(x: &Exp, env: &Env) -> Result<Value, Box<dyn Error>> {
+    let args = x.args().unwrap_or_default();
+    match args.first() {
+        Some(Exp::List(params, _)) => {
+            Ok(Value::Closure(Rc::new(Closure {
+                name: None,
+                params: parse_params(params)?,
+                body: args[1..].to_vec(),
+                env: env.clone(),
+            })))
+        }
+        Some(Exp::Symbol(name, _)) => {
+            let Some(Exp::List(params, _)) = args.get(1) else {
+                return Err(EvalError::boxed(format!("Expected a parameter list for '{name}'")));
+            };
+            // The keyword parameters come before the body, even if there
+            // are none
+            let Some(Exp::List(..)) = args.get(2) else {
+                return Err(EvalError::boxed(format!("Expected a keyword parameter list for '{name}'")));
+            };
+            let params = parse_params(params)?;
+            let body = args.iter().skip(3).cloned().collect();
+            Ok(define_closure(name.clone(), params, body, env))
+        }
+        _ => Err(EvalError::boxed(format!("Invalid function: {x}")))
+    }
+}
+
+fn define_closure(name: String, params: Vec<Param>, body: Vec<Exp>, env: &Env) -> Value {
+    let value = Value::Closure(Rc::new(Closure {
+        name: Some(name.clone()),
+        params,
+        body,
+        env: env.clone(),
+    }));
+    env.borrow_mut().define(&name, value.clone());
+    value
+}
+
+/// Parameters are either names, or `[name default]` lists
+fn parse_params(params: &[Exp]) -> Result<Vec<Param>, Box<dyn Error>> {
+    params.iter().map(|p| {
+        match p {
+            Exp::Symbol(name, _) => Ok(Param { name: name.clone(), default: None }),
+            Exp::List(contents, _) => match contents.as_slice() {
+                [Exp::Symbol(name, _), default] => {
+                    Ok(Param { name: name.clone(), default: Some(default.clone()) })
                 }
-                _ => Exp::Nil
+                _ => Err(EvalError::boxed(format!("Invalid parameter: {p}")))
             }
+            _ => Err(EvalError::boxed(format!("Invalid parameter: {p}")))
+        }
+    }).collect()
+}
+
+/// Create the environment for a call, binding the arguments to the closure's
+/// parameters. Positional arguments come first, then keyword arguments bind
+/// by name, and any parameter left over takes its default value.
+fn bind_params(
+    c: &Closure,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+) -> Result<Env, Box<dyn Error>> {
+    let name = c.name.as_deref().unwrap_or("fn");
+    if args.len() > c.params.len() {
+        return Err(EvalError::boxed(format!(
+            "'{name}' expects {} arguments, got {}", c.params.len(), args.len()
+        )));
+    }
+    let mut bound: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+    bound.resize(c.params.len(), None);
+    for (k, v) in kwargs {
+        let Some(index) = c.params.iter().position(|p| p.name == k) else {
+            return Err(EvalError::boxed(format!("'{name}' has no parameter named '{k}'")));
+        };
+        if bound[index].is_some() {
+            return Err(EvalError::boxed(format!("Parameter '{k}' of '{name}' is bound more than once")));
         }
-        _ => x
+        bound[index] = Some(v);
     }
+    let env = Scope::new(Some(c.env.clone()));
+    for (param, value) in c.params.iter().zip(bound) {
+        let value = match (value, &param.default) {
+            (Some(v), _) => v,
+            (None, Some(default)) => eval(default, &env)?,
+            (None, None) => {
+                return Err(EvalError::boxed(format!(
+                    "Missing argument '{}' in call to '{name}'", param.name
+                )));
+            }
+        };
+        env.borrow_mut().define(&param.name, value);
+    }
+    Ok(env)
+}
+
+fn call_builtin(name: &str, args: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+    match name {
+        "+" | "-" | "*" | "/" | "%" => {
+            let mut args = args.into_iter();
+            let Some(mut result) = args.next() else {
+                return Err(EvalError::boxed(format!("'{name}' expects at least 1 argument")));
+            };
+            if name == "-" && args.len() == 0 {
+                // Unary minus negates
+                return match result {
+                    Value::Float(x) => Ok(Value::Float(-x)),
+                    x => arithmetic(name, Value::Int(0), x),
+                };
+            }
+            for arg in args {
+                result = arithmetic(name, result, arg)?;
+            }
+            Ok(result)
+        }
+        "=" | "!=" | "<" | "<=" | ">" | ">=" => {
+            let [lhs, rhs] = expect_args::<2>(name, args)?;
+            Ok(Value::Bool(compare(name, &lhs, &rhs)?))
+        }
+        "not" => {
+            let [x] = expect_args::<1>(name, args)?;
+            Ok(Value::Bool(!x.is_truthy()))
+        }
+        "print" => {
+            let line = args.iter()
+                           .map(|x| format!("{x}"))
+                           .collect::<Vec<String>>()
+                           .join(" ");
+            println!("{line}");
+            Ok(Value::Nil)
+        }
+        "list" => Ok(Value::Array(Rc::new(RefCell::new(args)))),
+        "values" => {
+            // A single value is just that value
+            let mut args = args;
+            match args.len() {
+                0 => Err(EvalError::boxed(format!("'{name}' expects at least 1 argument"))),
+                1 => Ok(args.remove(0)),
+                _ => Ok(Value::Values(Rc::new(args))),
+            }
+        }
+        "len" => {
+            let [x] = expect_args::<1>(name, args)?;
+            let length = match &x {
+                Value::Array(contents) => contents.borrow().len(),
+                Value::Str(s) => s.chars().count(),
+                Value::Quoted(q) => q.items().or_else(|| match &**q {
+                    Exp::List(items, _) => Some(items.clone()),
+                    _ => None
+                }).map(|items| items.len()).unwrap_or(0),
+                _ => return Err(EvalError::boxed(format!("{x} has no length"))),
+            };
+            Ok(Value::Int(length as i64))
+        }
+        "str" => Ok(Value::Str(args.iter().map(|x| x.to_string()).collect())),
+        "symbol" => {
+            let [x] = expect_args::<1>(name, args)?;
+            Ok(Value::Quoted(Rc::new(Exp::Symbol(x.to_string(), Span::default()))))
+        }
+        "items" => {
+            // The items of quoted code, e.g. to take apart a macro's argument
+            let [x] = expect_args::<1>(name, args)?;
+            let items = match &x {
+                Value::Quoted(q) => match &**q {
+                    Exp::List(items, _) => items.clone(),
+                    _ => q.items().unwrap_or_default(),
+                },
+                Value::Array(contents) => return Ok(Value::Array(contents.clone())),
+                _ => return Err(EvalError::boxed(format!("{x} has no items"))),
+            };
+            let items = items.iter().map(Value::from_exp).collect();
+            Ok(Value::Array(Rc::new(RefCell::new(items))))
+        }
+        "time.now" => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            Ok(Value::Int(now.as_millis() as i64))
+        }
+        "int" | "f32" | "float" | "f64" => {
+            let [x] = expect_args::<1>(name, args)?;
+            let converted = match (name, &x) {
+                // Like the JIT, floats out of range saturate and NaN becomes 0
+                ("int", Value::Float(n)) => Some(Value::Int(*n as i64)),
+                ("int", _) => x.as_int().map(Value::Int),
+                // There's no single precision value, so round to one instead
+                ("f32", _) => as_float(&x).map(|n| Value::Float(n as f32 as f64)),
+                _ => as_float(&x).map(Value::Float),
+            };
+            converted.ok_or_else(|| EvalError::boxed(format!("Can't convert {x} with '{name}'")))
+        }
+        "array" => {
+            let [length] = expect_args::<1>(name, args)?;
+            // Like the JIT, lengths are unsigned 32-bit integers
+            let Some(length) = length.as_int().and_then(|n| u32::try_from(n).ok()) else {
+                return Err(EvalError::boxed(format!("Invalid array length: {length}")));
+            };
+            Ok(Value::Array(Rc::new(RefCell::new(vec![Value::Int(0); length as usize]))))
+        }
+        "array_get" => {
+            let [array, index] = expect_args::<2>(name, args)?;
+            let (Value::Array(contents), Some(index)) = (&array, index.as_int()) else {
+                return Err(EvalError::boxed(format!("Can't index {array}")));
+            };
+            let item = contents.borrow().get(index as usize).cloned();
+            item.ok_or_else(|| EvalError::boxed(format!("Index {index} is out of bounds")))
+        }
+        "array_set" => {
+            let [array, index, value] = expect_args::<3>(name, args)?;
+            let (Value::Array(contents), Some(index)) = (&array, index.as_int()) else {
+                return Err(EvalError::boxed(format!("Can't index {array}")));
+            };
+            match contents.borrow_mut().get_mut(index as usize) {
+                Some(item) => *item = value,
+                None => return Err(EvalError::boxed(format!("Index {index} is out of bounds"))),
+            }
+            Ok(Value::Int(0))
+        }
+        _ => Err(EvalError::boxed(format!("Unknown builtin '{name}'")))
+    }
+}
+
+fn arithmetic(name: &str, lhs: Value, rhs: Value) -> Result<Value, Box<dyn Error>> {
+    match (&lhs, &rhs) {
+        (Value::Float(_), _) | (_, Value::Float(_)) => {
+            let (Some(a), Some(b)) = (as_float(&lhs), as_float(&rhs)) else {
+                return Err(EvalError::boxed(format!("Can't apply '{name}' to {lhs} and {rhs}")));
+            };
+            Ok(Value::Float(match name {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            }))
+        }
+        _ => {
+            let (Some(a), Some(b)) = (lhs.as_int(), rhs.as_int()) else {
+                return Err(EvalError::boxed(format!("Can't apply '{name}' to {lhs} and {rhs}")));
+            };
+            if matches!(name, "/" | "%") && b == 0 {
+                return Err(EvalError::boxed("Division by zero".to_string()));
+            }
+            // Integers wrap around on overflow, like machine integers do
+            Ok(Value::Int(match name {
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" => a.wrapping_div(b),
+                _ => a.wrapping_rem(b),
+            }))
+        }
+    }
+}
+
+fn compare(name: &str, lhs: &Value, rhs: &Value) -> Result<bool, Box<dyn Error>> {
+    let ordering = match (lhs, rhs) {
+        (Value::Str(a), Value::Str(b)) | (Value::Keyword(a), Value::Keyword(b)) => a.partial_cmp(b),
+        // Code is equal when it's written the same way
+        (Value::Quoted(a), Value::Quoted(b)) => a.to_string().partial_cmp(&b.to_string()),
+        _ => compare_numbers(lhs, rhs)?,
+    };
+    let Some(ordering) = ordering else { return Ok(name == "!="); };
+    Ok(match name {
+        "=" => ordering.is_eq(),
+        "!=" => ordering.is_ne(),
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+fn compare_numbers(lhs: &Value, rhs: &Value) -> Result<Option<Ordering>, Box<dyn Error>> {
+    Ok(match (lhs.as_int(), rhs.as_int()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        _ => match (as_float(lhs), as_float(rhs)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => return Err(EvalError::boxed(format!("Can't compare {lhs} and {rhs}")))
+        }
+    })
+}
+
+fn as_float(x: &Value) -> Option<f64> {
+    match x {
+        Value::Float(n) => Some(*n),
+        _ => x.as_int().map(|n| n as f64)
+    }
+}
+
+fn expect_args<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], Box<dyn Error>> {
+    let count = args.len();
+    args.try_into().map_err(|_| EvalError::boxed(format!("'{name}' expects {N} arguments, got {count}")))
 }
@@ -1,3 +1,261 @@
-fn main() {
-    println!("Hello, world!");
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::collections::HashSet;
+use std::io::{self, IsTerminal};
+use std::process::ExitCode;
+use loom_compiler::frontend::function_definition;
+use loom_compiler::jit::JIT;
+use loom_compiler::object::{ObjectCompiler, link};
+use loom_compiler::resolve::resolve_function;
+use loom_reader::diagnostics::{Diagnostic, SourceMap};
+use loom_reader::format::format;
+use loom_reader::parse::{Exp, ParseError, Span, read_all_expressions};
+use loom_runtime::eval::{Interpreter, Value};
+use loom_runtime::repl::{Repl, call_compiled};
+
+const USAGE: &str = "\
+Usage: loom <command> [options] <file>
+
+Commands:
+    repl                 Start an interactive session (the default)
+    run [--jit] <file>   Run a file with the interpreter, or with the JIT
+    compile <file>       Compile every function in a file and print its IR
+    build [--object] <file>
+                         Compile a file into an executable which runs its main
+                         function, or only into an object file
+    check <file>         Check a file for syntax errors, and if it can be compiled,
+                         for unresolved names and type errors
+    fmt [--write] <file> Print a file in canonical form, or rewrite it";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first().map(|c| c.as_str()).unwrap_or("repl");
+    if command == "repl" {
+        return match Repl::default().run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let flags: Vec<&str> = args[1..].iter()
+                                    .filter(|a| a.starts_with("--"))
+                                    .map(|a| a.as_str())
+                                    .collect();
+    let Some(path) = args[1..].iter().find(|a| !a.starts_with("--")) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let result = match command {
+        "run" if flags.contains(&"--jit") => run_jit(path),
+        "run" => run(path),
+        "compile" => compile(path),
+        "build" => build(path, flags.contains(&"--object")),
+        "check" => check(path),
+        "fmt" => fmt(path, flags.contains(&"--write")),
+        _ => {
+            eprintln!("Unknown command '{command}'\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", describe_error(path, e.as_ref()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Format an error, with a snippet of the source file if it points into it.
+fn describe_error(path: &str, e: &(dyn Error + 'static)) -> String {
+    let diagnostic = match (e.downcast_ref::<ParseError>(), e.downcast_ref::<Diagnostic>()) {
+        (Some(e), _) => e.diagnostic(),
+        (None, Some(d)) => d.clone(),
+        (None, None) => return format!("{path}: error: {e}"),
+    };
+    diagnostic.render(&source_map(path), use_color()).trim_end().to_string()
+}
+
+/// The file a command reads, which gets the first id
+fn source_map(path: &str) -> SourceMap {
+    let mut sources = SourceMap::new();
+    sources.add(path, &fs::read_to_string(path).unwrap_or_default());
+    sources
+}
+
+fn use_color() -> bool {
+    io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+/// Read every expression in a file, printing every syntax error in it if
+/// there are any.
+fn read(path: &str) -> Result<Vec<Exp>, Box<dyn Error>> {
+    let source = fs::read_to_string(path)?;
+    let mut sources = SourceMap::new();
+    let file = sources.add(path, &source);
+    let (expressions, errors) = read_all_expressions(source, file);
+    if errors.is_empty() {
+        return Ok(expressions);
+    }
+    Err(report_syntax_errors(&sources, &errors))
+}
+
+fn report_syntax_errors(sources: &SourceMap, errors: &[ParseError]) -> Box<dyn Error> {
+    for e in errors {
+        eprintln!("{}", e.diagnostic().render(sources, use_color()));
+    }
+    match errors.len() {
+        1 => "Aborting due to a syntax error".into(),
+        n => format!("Aborting due to {n} syntax errors").into(),
+    }
+}
+
+/// Evaluate a file with the interpreter, then call its `main` function if it
+/// defines one.
+fn run(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::default();
+    for x in read(path)? {
+        interpreter.eval(&x)?;
+    }
+    let main = interpreter.globals.borrow().get("main");
+    if let Some(main) = main {
+        let result = interpreter.call(&main, Vec::new())?;
+        if !matches!(result, Value::Nil) {
+            println!("{result}");
+        }
+    }
+    Ok(())
+}
+
+/// Compile every function in a file, then call its `main` function.
+fn run_jit(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut jit = JIT::default();
+    let forms = module_forms(path, &|name| jit.is_defined(name))?;
+    let mut main: Option<*const u8> = None;
+    for (name, code) in jit.compile_module(&forms)? {
+        if name == "main" {
+            main = Some(code);
+        }
+    }
+    let Some(main) = main else {
+        return Err(format!("{path} has no main function").into());
+    };
+    // Safety: `main` was just compiled, and takes no parameters.
+    let result = unsafe { call_compiled(&mut jit, "main", main, Span::default())? };
+    println!("{result}");
+    Ok(())
+}
+
+fn compile(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut jit = JIT::default();
+    let forms = module_forms(path, &|name| jit.is_defined(name))?;
+    for (name, _) in jit.compile_module(&forms)? {
+        if let (Some(ty), Some(ir)) = (jit.type_of(&name), jit.ir(&name)) {
+            println!("; {name} : {ty}\n{ir}");
+        }
+    }
+    Ok(())
+}
+
+/// Compile a file ahead of time into an executable named after it, which
+/// runs its `main` function, or only into an object file, which has an entry
+/// point if there's a `main` function.
+fn build(path: &str, object_only: bool) -> Result<(), Box<dyn Error>> {
+    let name = Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+    let mut compiler = ObjectCompiler::new(name)?;
+    let forms = module_forms(path, &|name| compiler.is_defined(name))?;
+    let functions = compiler.compile_module(&forms)?;
+    if !object_only || functions.iter().any(|f| f == "main") {
+        compiler.define_entry("main")?;
+    }
+    let object = format!("{name}.o");
+    fs::write(&object, compiler.emit()?)?;
+    if !object_only {
+        link(Path::new(&object), Path::new(name))?;
+        fs::remove_file(&object)?;
+    }
+    Ok(())
+}
+
+/// The top-level forms of a file to compile, which are functions and type
+/// declarations, once the names in every function resolve. Functions can
+/// call themselves and each other, in any order, and anything else the
+/// compiler knows of.
+fn module_forms(path: &str, is_defined: &dyn Fn(&str) -> bool) -> Result<Vec<Exp>, Box<dyn Error>> {
+    let expressions = Interpreter::default().expand_all(read(path)?)?;
+
+    let mut functions = Vec::new();
+    for x in &expressions {
+        if is_declaration(x) {
+            continue;
+        }
+        let Some((name, x)) = function_definition(x)?.and_then(|x| Some((x.arg_symbol(0)?, x))) else {
+            return Err(format!("Only functions and type declarations can be compiled, found {x}").into());
+        };
+        functions.push((name, x));
+    }
+
+    // Resolve the names in every function first, to report all the problems
+    // at once
+    let sources = source_map(path);
+    let names: HashSet<String> = functions.iter().map(|(name, _)| name.clone()).collect();
+    let mut errors = 0;
+    for (_, x) in &functions {
+        let resolution = resolve_function(x, &|name| names.contains(name) || is_defined(name));
+        for d in &resolution.diagnostics {
+            eprintln!("{}", d.render(&sources, use_color()));
+        }
+        errors += resolution.errors().count();
+    }
+    match errors {
+        0 => Ok(expressions),
+        1 => Err("Aborting due to an unresolved name".into()),
+        n => Err(format!("Aborting due to {n} unresolved names").into()),
+    }
+}
+
+/// Whether a top-level form is a type declaration.
+fn is_declaration(x: &Exp) -> bool {
+    matches!(x.car_symbol().as_deref(), Some("deftype") | Some("extern"))
+}
+
+/// Whether a top-level form is for the compiler, even if it's malformed, so
+/// that the compiler reports what's wrong with it.
+fn is_module_form(x: &Exp) -> bool {
+    is_declaration(x) || !matches!(function_definition(x), Ok(None))
+}
+
+/// Check a file for syntax errors. A file of functions and type
+/// declarations is checked the way the compiler sees it too, since it can be
+/// compiled; anything else is a script only the interpreter runs.
+fn check(path: &str) -> Result<(), Box<dyn Error>> {
+    let expressions = Interpreter::default().expand_all(read(path)?)?;
+    if !expressions.iter().all(is_module_form) {
+        println!("{path}: ok, {} top-level expressions", expressions.len());
+        return Ok(());
+    }
+    let mut jit = JIT::default();
+    let forms = module_forms(path, &|name| jit.is_defined(name))?;
+    let functions = jit.check_module(&forms)?;
+    println!("{path}: ok, {} functions", functions.len());
+    Ok(())
+}
+
+fn fmt(path: &str, write: bool) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(path)?;
+    let mut sources = SourceMap::new();
+    let file = sources.add(path, &source);
+    let formatted = format(&source, file).map_err(|errors| report_syntax_errors(&sources, &errors))?;
+    if write {
+        fs::write(path, formatted)?;
+    } else {
+        print!("{formatted}");
+    }
+    Ok(())
 }
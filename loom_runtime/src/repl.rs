@@ -0,0 +1,223 @@
+use core::mem;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use loom_compiler::frontend::function_definition;
+use loom_compiler::jit::{JIT, take_runtime_error};
+use loom_compiler::types::Type;
+use loom_reader::diagnostics::{Diagnostic, SourceMap};
+use loom_reader::parse::{Exp, ParseError, Span, is_incomplete, read_expressions};
+use crate::eval::Interpreter;
+
+const HELP: &str = "\
+Enter expressions to evaluate them, or function definitions to compile them.
+Commands:
+    :load <file>   Compile every function in a file
+    :ir <name>     Show the Cranelift IR of a function
+    :type <name>   Show the type of a function
+    :reset         Forget every definition
+    :help          Show this message
+    :quit          Exit the REPL";
+
+/// An interactive session with a long-lived JIT, so that functions defined in
+/// earlier inputs can be called by later ones.
+#[derive(Default)]
+pub struct Repl {
+    jit: JIT,
+    /// Expands macros, running the interpreter for procedural ones
+    macros: Interpreter,
+    /// How many expressions have been evaluated, used to name their wrappers
+    count: usize,
+}
+
+impl Repl {
+    /// Read inputs from stdin until it's closed or the user quits.
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut buffer = String::new();
+        loop {
+            print!("{}", if buffer.is_empty() { "loom> " } else { "....> " });
+            io::stdout().flush()?;
+            let Some(line) = lines.next() else {
+                println!();
+                return Ok(());
+            };
+            let line = line?;
+
+            if buffer.is_empty() {
+                match line.trim().strip_prefix(':') {
+                    Some("quit") | Some("q") => return Ok(()),
+                    Some(command) => {
+                        if let Err(e) = self.command(command) {
+                            println!("error: {e}");
+                        }
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+
+            // Keep reading until every expression is closed
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if is_incomplete(&buffer) {
+                continue;
+            }
+            let input = mem::take(&mut buffer);
+            match self.eval(&input) {
+                Ok(results) => {
+                    for result in results {
+                        println!("{result}");
+                    }
+                }
+                Err(e) => println!("{}", describe_error(&input, e.as_ref())),
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<(), Box<dyn Error>> {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let arg = arg.trim();
+        match name {
+            "load" => {
+                let source = fs::read_to_string(arg)?;
+                for result in self.eval(&source)? {
+                    println!("{result}");
+                }
+            }
+            "ir" => match self.jit.ir(arg) {
+                Some(ir) => print!("{ir}"),
+                None => return Err(format!("No function named '{arg}'").into()),
+            }
+            "type" => match self.jit.type_of(arg) {
+                Some(ty) => println!("{arg} : {ty}"),
+                None => return Err(format!("No function named '{arg}'").into()),
+            }
+            "reset" => *self = Self::default(),
+            "help" => println!("{HELP}"),
+            _ => return Err(format!("Unknown command ':{name}', try :help").into()),
+        }
+        Ok(())
+    }
+
+    /// Compile every function definition in the input, and evaluate every
+    /// other expression, returning what each of them produced.
+    pub fn eval(&mut self, input: &str) -> Result<Vec<Exp>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        for x in read_expressions(input.to_string())? {
+            let Some(x) = self.macros.expand_top(&x)? else {
+                // A macro definition
+                results.push(Exp::Symbol(x.arg_symbol(0).unwrap_or_default(), x.span()));
+                continue;
+            };
+            if let Some(function) = function_definition(&x)? {
+                self.compile(&function)?;
+                results.push(Exp::Symbol(function.arg_symbol(0).unwrap_or_default(), x.span()));
+            } else if matches!(x.car_symbol().as_deref(), Some("deftype") | Some("extern")) {
+                let name = self.jit.declare(&x)?;
+                results.push(Exp::Symbol(name, x.span()));
+            } else {
+                // Wrap the expression in a function without parameters, so it
+                // can be compiled and called. It's built directly, rather
+                // than read, so errors point at the original input.
+                let name = format!("__repl_{}", self.count);
+                self.count += 1;
+                let span = x.span();
+                let wrapper = Exp::SExp {
+                    kind: Box::new(Exp::Symbol("fn".to_string(), span)),
+                    args: vec![
+                        Exp::Symbol(name.clone(), span),
+                        Exp::List(Vec::new(), span),
+                        Exp::List(Vec::new(), span),
+                        x,
+                    ],
+                    kwargs: Vec::new(),
+                    span,
+                };
+                let code = self.compile(&wrapper)?;
+                // Safety: the wrapper was just compiled, and takes no
+                // parameters.
+                results.push(unsafe { call_compiled(&mut self.jit, &name, code, span)? });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Compile a function, resetting the session if the JIT panics, since its
+    /// state can't be trusted afterwards.
+    fn compile(&mut self, code: &Exp) -> Result<*const u8, Box<dyn Error>> {
+        let jit = &mut self.jit;
+        match panic::catch_unwind(AssertUnwindSafe(|| jit.compile_exp(code))) {
+            Ok(result) => Ok(result?),
+            Err(_) => {
+                *self = Self::default();
+                Err("The JIT crashed, so the session was reset".into())
+            }
+        }
+    }
+}
+
+/// Call a compiled function without parameters, reading its result the way
+/// its type says it's represented. If the code runs into an error, like a
+/// division by zero, the error is returned instead.
+///
+/// # Safety
+///
+/// `code` must be the code of the function called `name`, which must take no
+/// parameters.
+pub unsafe fn call_compiled(jit: &mut JIT, name: &str, code: *const u8, span: Span) -> Result<Exp, String> {
+    let result = match jit.type_of(name).map(|s| &s.ty) {
+        Some(Type::Fn(_, result)) => result.as_ref().clone(),
+        _ => Type::Int,
+    };
+    take_runtime_error();
+    let value = match &result {
+        Type::Float => Exp::Float(mem::transmute::<*const u8, fn() -> f64>(code)(), span),
+        Type::F32 => Exp::Float(mem::transmute::<*const u8, fn() -> f32>(code)() as f64, span),
+        Type::Bool => Exp::Bool(mem::transmute::<*const u8, fn() -> i64>(code)() != 0, span),
+        Type::Nil => {
+            mem::transmute::<*const u8, fn() -> i64>(code)();
+            Exp::Nil(span)
+        }
+        // A closure, which can't be called from here
+        Type::Fn(..) => {
+            mem::transmute::<*const u8, fn() -> i64>(code)();
+            Exp::Symbol(format!("<{result}>"), span)
+        }
+        // Several values, which a function written for the purpose reads
+        Type::Values(types) => {
+            let writer = jit.compile_values_writer(name)?;
+            let mut words = vec![0u64; types.len()];
+            mem::transmute::<*const u8, fn(*mut u64)>(writer)(words.as_mut_ptr());
+            let mut items = vec![Exp::Symbol("values".to_string(), span)];
+            items.extend(types.iter().zip(words).map(|(ty, word)| match ty {
+                Type::Float => Exp::Float(f64::from_bits(word), span),
+                Type::F32 => Exp::Float(f32::from_bits(word as u32) as f64, span),
+                Type::Bool => Exp::Bool(word != 0, span),
+                Type::Nil => Exp::Nil(span),
+                Type::Fn(..) => Exp::Symbol(format!("<{ty}>"), span),
+                _ => Exp::Int(word as i64, span),
+            }));
+            Exp::new_sexp(items, span)
+        }
+        _ => Exp::Int(mem::transmute::<*const u8, fn() -> i64>(code)(), span),
+    };
+    match take_runtime_error() {
+        Some(e) => Err(e),
+        None => Ok(value),
+    }
+}
+
+/// Format an error, with a snippet of the input if it points into it.
+fn describe_error(input: &str, e: &(dyn Error + 'static)) -> String {
+    let diagnostic = match (e.downcast_ref::<ParseError>(), e.downcast_ref::<Diagnostic>()) {
+        (Some(e), _) => e.diagnostic(),
+        (None, Some(d)) => d.clone(),
+        (None, None) => return format!("error: {e}"),
+    };
+    let mut sources = SourceMap::new();
+    sources.add("<repl>", input);
+    diagnostic.render(&sources, false).trim_end().to_string()
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// The example programs at the root of the repository
+const FILES: &[&str] = &["basic.loom", "lazer.loom", "shader.loom", "test.loom"];
+
+/// Run the `loom` binary from the root of the repository.
+fn loom(args: &[&str]) -> Output {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    Command::new(env!("CARGO_BIN_EXE_loom")).args(args).current_dir(root).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn bundled_files_check() {
+    for file in FILES {
+        assert!(stdout(&loom(&["check", file])).starts_with(&format!("{file}: ok")), "{file}");
+    }
+    assert_eq!(stdout(&loom(&["check", "test.loom"])), "test.loom: ok, 4 functions\n");
+}
+
+#[test]
+fn bundled_files_format() {
+    for file in FILES {
+        let formatted = stdout(&loom(&["fmt", file]));
+        assert!(!formatted.is_empty(), "{file}");
+    }
+}
+
+#[test]
+fn bundled_programs_run() {
+    assert_eq!(stdout(&loom(&["run", "basic.loom"])), "2\n");
+    assert_eq!(stdout(&loom(&["run", "--jit", "basic.loom"])), "2\n");
+    // Only functions, which compile, and define nothing to run
+    assert_eq!(stdout(&loom(&["run", "test.loom"])), "");
+    assert!(stdout(&loom(&["compile", "test.loom"])).contains("; hello : "));
+}
+
+#[test]
+fn failures_are_reported() {
+    let output = loom(&["run", "lazer.loom"]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "lazer.loom: error: 1:2: Variable 'signal' is not defined\n");
+    assert!(!loom(&["check", "missing.loom"]).status.success());
+}
@@ -0,0 +1,122 @@
+use core::mem;
+use loom_compiler::jit::{self, take_runtime_error};
+use loom_runtime::eval::{Interpreter, Value};
+
+/// A function name, its source, and the inputs to call it with
+type Program = (&'static str, &'static str, &'static [(i64, i64)]);
+
+/// Programs which both the JIT and the interpreter understand
+const PROGRAMS: &[Program] = &[
+    ("foo", r#"
+        (fn foo [a b] []
+            (set c (if a
+                (if b 30 40)
+                50
+            ))
+            (+ c 2)
+        )
+    "#, &[(0, 0), (0, 1), (1, 0), (1, 1)]),
+    ("recursive_fib", r#"
+        (fn recursive_fib [n] []
+            (if (= n 0)
+                0
+                (if (= n 1)
+                    1
+                    (+
+                        (recursive_fib (- n 1))
+                        (recursive_fib (- n 2))
+                    )
+                )
+            )
+        )
+    "#, &[(0, 0), (1, 0), (10, 0), (20, 0)]),
+    ("arithmetic", r#"
+        (fn arithmetic [a b] []
+            (set q (/ a b))
+            (set r (% a b))
+            (+ (* q 1000) r (if (< a b) 1 0) (if (>= a b) 10 0))
+        )
+    "#, &[(7, 2), (-7, 2), (7, -2), (2, 7), (100, 100), (7, -1), (7, 0), (0, 0)]),
+    ("count", r#"
+        (fn count [n step] []
+            (set total 0)
+            (while (> n 0)
+                (set total (+ total step))
+                (set n (- n 1))
+            )
+            total
+        )
+    "#, &[(0, 3), (10, 3), (1000, -7)]),
+    ("closures", r#"
+        (fn closures [a b] []
+            (set total 0)
+            (fn add [x] [] (set total (+ total x)) total)
+            (set twice (fn [f x] (f (f x))))
+            (add a)
+            (add b)
+            (+ (twice (fn [x] (* x 2)) total) ((fn [y] (+ y a)) b))
+        )
+    "#, &[(0, 0), (1, 2), (-5, 17)]),
+    ("receive", r#"
+        (fn receive [a b] []
+            (let-values [[lo hi] (if (< a b) (values a b) (values b a))]
+                (let-values [[q r] (values (/ hi 3) (% hi 3))]
+                    (+ (* lo 1000) (* q 10) r)
+                )
+            )
+        )
+    "#, &[(0, 0), (7, 2), (-5, 17)]),
+    ("negate", r#"
+        (fn negate [a b] []
+            (+ (- a) (* (- b) 100) (- 5))
+        )
+    "#, &[(0, 0), (3, -4), (-7, 2)]),
+    ("convert", r#"
+        (fn convert [a b] []
+            (set ratio (/ (float a) (f64 b)))
+            (+ (int (* ratio 1000.0)) (int (* (float a) 2.5)) (- (int (f32 a)) a))
+        )
+    "#, &[(7, 2), (-7, 2), (1, 3), (16777217, 1), (-16777219, 7)]),
+];
+
+/// Runs every program through both the JIT and the interpreter, the
+/// reference implementation, and checks that they agree.
+#[test]
+fn the_jit_agrees_with_the_interpreter() {
+    let mut jit = jit::JIT::default();
+    let mut interpreter = Interpreter::default();
+    let mut failures = Vec::new();
+
+    for (name, code, inputs) in PROGRAMS {
+        let code_ptr = jit.compile(code).unwrap_or_else(|e| panic!("{name}: {e}"));
+        // Every program takes two parameters, extras are simply ignored
+        let compiled = unsafe { mem::transmute::<*const u8, fn(i64, i64) -> i64>(code_ptr) };
+        interpreter.eval_source(code).unwrap_or_else(|e| panic!("{name}: {e}"));
+        let f = interpreter.eval_source(name).unwrap();
+
+        for (a, b) in inputs.iter() {
+            let expected = interpreter.call(&f, arity_args(&f, *a, *b)).map(|x| x.as_int());
+            let expected = match expected {
+                Ok(Some(n)) => Ok(n),
+                Ok(None) => panic!("({name} {a} {b}) doesn't return an integer"),
+                Err(e) => Err(e.to_string()),
+            };
+            let actual = compiled(*a, *b);
+            let actual = take_runtime_error().map_or(Ok(actual), Err);
+            if expected != actual {
+                failures.push(format!("({name} {a} {b}): interpreter = {expected:?}, jit = {actual:?}"));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn arity_args(f: &Value, a: i64, b: i64) -> Vec<Value> {
+    match f {
+        Value::Closure(c) => {
+            [a, b].into_iter().take(c.params.len()).map(Value::Int).collect()
+        }
+        _ => vec![Value::Int(a), Value::Int(b)]
+    }
+}
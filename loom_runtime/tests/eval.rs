@@ -0,0 +1,118 @@
+use loom_runtime::eval::Interpreter;
+
+/// Evaluate a source file, and return its last value as text.
+fn eval(source: &str) -> String {
+    let mut interpreter = Interpreter::default();
+    interpreter.eval_source(source).unwrap_or_else(|e| panic!("{e}")).to_string()
+}
+
+/// Evaluate a source file which should fail, and return why.
+fn error(source: &str) -> String {
+    match Interpreter::default().eval_source(source) {
+        Ok(value) => panic!("Expected an error, found {value}"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn named_functions_need_keyword_parameters() {
+    assert_eq!(eval("(fn f [a] [] (+ a 1)) (f 2)"), "3");
+    assert_eq!(error("(fn f [a] (+ a 1))"), "Expected a keyword parameter list for 'f'");
+    // Anonymous functions have none
+    assert_eq!(eval("((fn [a] (+ a 1)) 2)"), "3");
+}
+
+#[test]
+fn arrays() {
+    assert_eq!(eval("(def a (array 3)) (array_set a 1 5) (+ (array_get a 1) (len a))"), "8");
+    assert_eq!(error("(array -1)"), "Invalid array length: -1");
+    assert_eq!(error("(array_get (array 2) 2)"), "Index 2 is out of bounds");
+    assert_eq!(error("(array_get (array 2) -1)"), "Index -1 is out of bounds");
+}
+
+#[test]
+fn definitions() {
+    assert_eq!(eval("(def x 40) (+ x 2)"), "42");
+    assert_eq!(eval("(def (add a [b 10]) (+ a b)) (values (add 1 2) (add 1) (add 1 :b 5))"), "(values 3 11 6)");
+    assert_eq!(eval("(def x 1) (def x 2) x"), "2");
+    // Inner definitions are local to the function
+    assert_eq!(eval("(def x 1) (def (f) (def x 2) x) (values (f) x)"), "(values 2 1)");
+    assert_eq!(error("(def 1 2)"), "Invalid definition: (def 1 2)");
+}
+
+#[test]
+fn closures_capture_their_environment() {
+    assert_eq!(eval("
+        (def (adder n) (fn [x] (+ x n)))
+        (def add5 (adder 5))
+        (add5 10)
+    "), "15");
+    // Captured variables are shared, not copied
+    assert_eq!(eval("
+        (def (counter)
+            (set count 0)
+            (fn [] (set count (+ count 1))))
+        (def next (counter))
+        (next)
+        (next)
+        (def other (counter))
+        (values (next) (other))
+    "), "(values 3 1)");
+    // From more than one level up
+    assert_eq!(eval("
+        (def (outer a) (fn [b] (fn [c] (+ (* a 100) (* b 10) c))))
+        (((outer 1) 2) 3)
+    "), "123");
+}
+
+#[test]
+fn conditionals_and_sequences() {
+    assert_eq!(eval("(if (< 1 2) 10 20)"), "10");
+    assert_eq!(eval("(if (> 1 2) 10 20)"), "20");
+    assert_eq!(eval("(if false 10)"), "nil");
+    // Like the JIT, 0 and nil are false too
+    assert_eq!(eval("(values (if 0 1 2) (if nil 1 2) (if \"\" 1 2))"), "(values 2 2 1)");
+    assert_eq!(eval("(do (set a 1) (set a (+ a 1)) a)"), "2");
+    assert_eq!(eval("(do)"), "nil");
+    assert_eq!(eval("(set n 0) (while (< n 5) (set n (+ n 1))) n"), "5");
+}
+
+#[test]
+fn tail_calls_run_in_constant_space() {
+    assert_eq!(eval("
+        (def (count n total) (if (= n 0) total (count (- n 1) (+ total 1))))
+        (count 100000 0)
+    "), "100000");
+    // Through `do` and `let`, and between functions
+    assert_eq!(eval("
+        (def (even? n) (if (= n 0) true (do (odd? (- n 1)))))
+        (def (odd? n) (if (= n 0) false (let [m (- n 1)] (even? m))))
+        (even? 100001)
+    "), "false");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("(+ 1 missing)"), "1:6: Variable 'missing' is not defined");
+    assert_eq!(error("(/ 1 0)"), "Division by zero");
+    assert_eq!(error("(% 1 0)"), "Division by zero");
+    assert_eq!(error("(+ 1 \"a\")"), "Can't apply '+' to 1 and a");
+    assert_eq!(error("(1 2)"), "1 is not a function");
+    assert_eq!(error("(def (f a) a) (f 1 2)"), "'f' expects 1 arguments, got 2");
+    assert_eq!(error("(def (f a) a) (f :b 1)"), "'f' has no parameter named 'b'");
+    assert_eq!(error("(not 1 2)"), "'not' expects 1 arguments, got 2");
+    assert_eq!(error("(let [a] a)"), "'let' expects a value for every name: (let [a] a)");
+    assert_eq!(error("(let-values [[a b] (values 1 2 3)] a)"), "1:20: Expected 2 values, got 3");
+    // Evaluation stops at the first error
+    assert_eq!(error("(set a 1) (/ a 0) (set a 2)"), "Division by zero");
+}
+
+#[test]
+fn numbers_convert() {
+    assert_eq!(eval("(values (int 2.9) (int -2.9) (int (/ 1.0 0.0)) (int true))"), "(values 2 -2 9223372036854775807 1)");
+    assert_eq!(eval("(values (float 2) (f64 2) (f32 0.1) (f64 (f32 0.1)))"), "(values 2.0 2.0 0.10000000149011612 0.10000000149011612)");
+    // Unlike in compiled code, integers and floats mix
+    assert_eq!(eval("(values (+ 1.5 2) (* 2 0.25) (- 3 (float 1)))"), "(values 3.5 0.5 2.0)");
+    assert_eq!(error("(int \"2\")"), "Can't convert 2 with 'int'");
+    assert_eq!(error("(f32 1 2)"), "'f32' expects 1 arguments, got 2");
+}
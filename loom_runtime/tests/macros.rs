@@ -0,0 +1,78 @@
+use loom_reader::parse::read_expressions;
+use loom_runtime::eval::Interpreter;
+
+/// Expand the forms of a source file with the interpreter, returning the
+/// expansions of those which aren't macro definitions, or the error.
+fn expand(source: &str) -> Result<Vec<String>, String> {
+    let forms = read_expressions(source.to_string()).map_err(|e| e.to_string())?;
+    let expanded = Interpreter::default().expand_all(forms).map_err(|e| e.to_string())?;
+    Ok(expanded.iter().map(|x| x.to_string()).collect())
+}
+
+const FRAMES: &str = r#"
+(defmacro frames [prefix count]
+    (set paths (array count))
+    (set i 0)
+    (while (< i count)
+        (array_set paths i (str prefix i ".png"))
+        (set i (+ i 1))
+    )
+    `(tex-sequence :paths ,paths))
+"#;
+
+#[test]
+fn macro_procedures_run_on_the_code() {
+    assert_eq!(expand(&format!("{FRAMES} (frames \"Mouth\" 2)")).unwrap(), vec![
+        r#"(tex-sequence :paths ["Mouth0.png" "Mouth1.png"])"#,
+    ]);
+    // Arguments are code, not values
+    assert_eq!(expand("(defmacro swap [a b] `(,b ,a)) (swap (+ 1 2) print)").unwrap(), vec!["(print (+ 1 2))"]);
+    assert_eq!(expand("(defmacro name [x] (str (len (items x)))) (name (a b c))").unwrap(), vec!["\"3\""]);
+}
+
+#[test]
+fn macros_work_in_evaluated_code() {
+    let mut interpreter = Interpreter::default();
+    interpreter.eval_source("(defmacro unless [c body] `(if ,c nil ,body))").unwrap();
+    assert_eq!(interpreter.eval_source("(unless (> 1 2) 10)").unwrap().to_string(), "10");
+    assert_eq!(interpreter.eval_source("(def (f x) (unless x 5)) (f false)").unwrap().to_string(), "5");
+}
+
+#[test]
+fn errors_in_macro_procedures_name_the_macro() {
+    let e = expand(&format!("{FRAMES}\n(sprite\n    (frames \"Mouth\" \"two\"))")).unwrap_err();
+    assert_eq!(e, "12:5: In macro 'frames': Invalid array length: two");
+    let e = expand("(defmacro one [x] x) (one)").unwrap_err();
+    assert_eq!(e, "1:22: In macro 'one': Missing argument 'x' in call to 'one'");
+    assert!(expand("(defmacro [x] x)").unwrap_err().ends_with("Expected a name for the macro"));
+}
+
+#[test]
+fn expansions_are_expanded_again() {
+    // A macro which expands into uses of itself, and of another macro, with
+    // a function to help
+    let source = "
+        (def (tail xs)
+            (set result (array (- (len xs) 1)))
+            (set i 1)
+            (while (< i (len xs))
+                (array_set result (- i 1) (array_get xs i))
+                (set i (+ i 1)))
+            result)
+        (defmacro unless [c body] `(if ,c false ,body))
+        (defmacro my-and [args]
+            (set args (items args))
+            (if (= (len args) 0)
+                true
+                `(unless (not ,(array_get args 0)) (my-and ,(tail args)))))
+    ";
+    let mut interpreter = Interpreter::default();
+    interpreter.eval_source(source).unwrap();
+    assert_eq!(interpreter.eval_source("(my-and [true 1 2])").unwrap().to_string(), "true");
+    assert_eq!(interpreter.eval_source("(my-and [true 0 2])").unwrap().to_string(), "false");
+    let expanded = interpreter.expand_all(read_expressions("(my-and [a b])".to_string()).unwrap()).unwrap();
+    assert_eq!(expanded[0].to_string(), "(if (not a) false (if (not b) false true))");
+
+    let e = expand("(defmacro forever [x] `(forever ,x)) (forever 1)").unwrap_err();
+    assert!(e.contains("Macro 'forever' keeps expanding into itself"), "{e}");
+}
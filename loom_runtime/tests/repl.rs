@@ -0,0 +1,89 @@
+use loom_runtime::repl::Repl;
+
+/// Evaluate each input in turn, in one session, returning what each
+/// produced, or its error.
+fn session(inputs: &[&str]) -> Vec<String> {
+    let mut repl = Repl::default();
+    inputs.iter().map(|input| match repl.eval(input) {
+        Ok(results) => results.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "),
+        Err(e) => format!("error: {e}"),
+    }).collect()
+}
+
+#[test]
+fn functions_can_be_defined_again() {
+    assert_eq!(session(&[
+        "(fn f [] [] 1)",
+        "(fn g [] [] (f))",
+        "(fn f [] [] 2)",
+        "(f)",
+        // Code compiled before keeps calling the old definition
+        "(g)",
+        "(fn g [] [] (f))",
+        "(g)",
+    ]), vec!["f", "g", "f", "2", "1", "g", "2"]);
+}
+
+#[test]
+fn redefined_functions_can_change_type() {
+    assert_eq!(session(&[
+        "(fn f [x] [] (+ x 1))",
+        "(fn f [] [] 2.5)",
+        "(f)",
+        // Recursive calls, and functions used as values, call the newest one
+        "(fn count [n] [] (if (= n 0) (f) (count (- n 1))))",
+        "(fn count [n] [] (if (= n 0) 7 (count (- n 1))))",
+        "(count 100000)",
+        "(do (set k f) (k))",
+    ]), vec!["f", "f", "2.5", "count", "count", "7", "2.5"]);
+}
+
+#[test]
+fn errors_leave_the_session_usable() {
+    assert_eq!(session(&[
+        "(fn f [] [] 1)",
+        "(fn f [] [] (+ 1 true))",
+        "(f)",
+        "(/ 1 0)",
+        "(f)",
+    ])[2..], ["1", "error: Division by zero", "1"]);
+}
+
+#[test]
+fn several_values_are_printed() {
+    assert_eq!(session(&[
+        "(fn divmod [a b] [] (values (/ a b) (% a b)))",
+        "(divmod 17 5)",
+        "(let-values [[q r] (divmod 17 5)] (+ (* q 10) r))",
+        "(values 1.5 (f32 0.25) (< 1 2) (if (< 2 1) 1))",
+        "(fn nothing [] [] (while false))",
+        "(nothing)",
+    ]), vec!["divmod", "(values 3 2)", "32", "(values 1.5 0.25 true nil)", "nothing", "nil"]);
+}
+
+#[test]
+fn results_are_read_by_type() {
+    assert_eq!(session(&[
+        "(fn half [x] [] (* x (f32 0.5)))",
+        "(half (f32 3))",
+        "(f32 0.1)",
+        "(* (float 3) 0.5)",
+        "(int -2.9)",
+        "(< 1.5 (float 2))",
+    ]), vec!["half", "1.5", "0.10000000149011612", "1.5", "-2", "true"]);
+}
+
+#[test]
+fn generic_numbers_are_compiled_when_needed() {
+    assert_eq!(session(&[
+        "(fn lerp [a b t] [] (+ a (* (- b a) t)))",
+        "(lerp 2.0 4.0 0.25)",
+        "(lerp 2 10 3)",
+        "(fn halfway [a b] [] (lerp a b 0.5))",
+        "(halfway 1.0 2.0)",
+        // The copies of a function follow it when it's defined again
+        "(fn lerp [a b t] [] (- a (* (- b a) t)))",
+        "(lerp 2.0 4.0 0.25)",
+        "(halfway 1.0 2.0)",
+    ]), vec!["lerp", "2.5", "26", "halfway", "1.5", "lerp", "1.5", "1.5"]);
+}